@@ -1,6 +1,8 @@
 use std::env;
 use std::process;
 
+use agentus_common::errors::{split_span, AgentusError, DiagnosticReport};
+
 fn main() {
     let args: Vec<String> = env::args().collect();
 
@@ -12,10 +14,23 @@ fn main() {
     match args[1].as_str() {
         "exec" => {
             if args.len() < 3 {
-                eprintln!("Usage: agentus exec <file.ags>");
+                eprintln!("Usage: agentus exec <file.ags> [--entry <name>] [-- args...]");
                 process::exit(1);
             }
-            cmd_exec(&args[2]);
+            // Everything after `--` is passed to the script as `args`
+            let (options, script_args) = match args.iter().position(|a| a == "--") {
+                Some(pos) => (&args[3..pos], args[pos + 1..].to_vec()),
+                None => (&args[3..], Vec::new()),
+            };
+            let entry = match options {
+                [] => None,
+                [flag, name] if flag == "--entry" => Some(name.as_str()),
+                _ => {
+                    eprintln!("Usage: agentus exec <file.ags> [--entry <name>] [-- args...]");
+                    process::exit(1);
+                }
+            };
+            cmd_exec(&args[2], entry, script_args);
         }
         "compile" => {
             if args.len() < 3 {
@@ -42,89 +57,102 @@ fn print_usage() {
     eprintln!("Agentus - Agent Orchestration Language");
     eprintln!();
     eprintln!("Usage:");
-    eprintln!("  agentus exec <file.ags>      Compile and run a source file");
-    eprintln!("  agentus compile <file.ags>   Compile a source file (output: .agc)");
-    eprintln!("  agentus version              Show version");
-    eprintln!("  agentus help                 Show this help");
+    eprintln!("  agentus exec <file.ags> [--entry <name>] [-- args...]");
+    eprintln!("                                         Compile and run a source file, optionally");
+    eprintln!("                                         from a named entry point such as `setup`");
+    eprintln!("  agentus compile <file.ags>             Compile a source file (output: .agc)");
+    eprintln!("  agentus version                        Show version");
+    eprintln!("  agentus help                           Show this help");
 }
 
-/// Compile and execute a .ags source file.
-fn cmd_exec(path: &str) {
-    // Read source
-    let source = match std::fs::read_to_string(path) {
-        Ok(s) => s,
-        Err(e) => {
-            eprintln!("Error reading '{}': {}", path, e);
-            process::exit(1);
-        }
-    };
-
-    // Lex
-    let (tokens, lex_errors) = agentus_lexer::lexer::Lexer::new(&source).tokenize();
-    if !lex_errors.is_empty() {
-        for err in &lex_errors {
-            eprintln!("Lexer error: {}", err);
-        }
-        process::exit(1);
-    }
-
-    // Parse
-    let program = match agentus_parser::parser::Parser::new(tokens).parse() {
-        Ok(p) => p,
-        Err(errors) => {
-            for err in &errors {
-                eprintln!("Parse error: {}", err);
-            }
-            process::exit(1);
-        }
-    };
-
-    // Semantic analysis
-    if let Err(errors) = agentus_sema::resolver::resolve(&program) {
-        for err in &errors {
-            eprintln!("Semantic error: {}", err);
-        }
-        process::exit(1);
-    }
-
-    // Compile to bytecode
-    let module = match agentus_codegen::compiler::Compiler::new().compile(&program) {
-        Ok(m) => m,
-        Err(e) => {
-            eprintln!("Codegen error: {}", e);
-            process::exit(1);
-        }
-    };
+/// Compile and execute a .ags source file, from the top-level statements or
+/// from the named `entry` point.
+fn cmd_exec(path: &str, entry: Option<&str>, script_args: Vec<String>) {
+    let source = read_source(path);
+    let module = compile_or_exit(&source, path);
 
     // Run
     let mut vm = agentus_runtime::vm::VM::new(module)
-        .with_host(Box::new(agentus_runtime::host::EchoHost));
-    if let Err(e) = vm.run() {
-        eprintln!("Runtime error: {}", e);
-        process::exit(1);
+        .with_host(Box::new(agentus_runtime::host::EchoHost))
+        .with_args(script_args);
+    let result = match entry {
+        Some(name) => vm.run_entry(name),
+        None => vm.run(),
+    };
+    if let Err(e) = result {
+        let mut report = DiagnosticReport::new();
+        report.push(e);
+        exit_with_report(&report, &source, path);
     }
 }
 
 /// Compile a .ags source file to bytecode (placeholder).
 fn cmd_compile(path: &str) {
     // For now, just verify compilation succeeds
-    let source = match std::fs::read_to_string(path) {
+    let source = read_source(path);
+    let _module = compile_or_exit(&source, path);
+    let out_path = path.replace(".ags", ".agc");
+    println!("Compiled successfully: {} -> {}", path, out_path);
+    // TODO: serialize module to .agc binary format
+}
+
+fn read_source(path: &str) -> String {
+    match std::fs::read_to_string(path) {
         Ok(s) => s,
         Err(e) => {
             eprintln!("Error reading '{}': {}", path, e);
             process::exit(1);
         }
-    };
+    }
+}
+
+/// Run the front end and code generator, reporting every error found in
+/// the earliest failing stage and exiting on failure.
+fn compile_or_exit(source: &str, path: &str) -> agentus_ir::module::Module {
+    let mut report = DiagnosticReport::new();
 
-    match agentus_codegen::compiler::compile(&source) {
-        Ok(_module) => {
-            let out_path = path.replace(".ags", ".agc");
-            println!("Compiled successfully: {} -> {}", path, out_path);
-            // TODO: serialize module to .agc binary format
+    // Lex and parse with recovery so all syntax errors are reported at once
+    let (tokens, lex_errors) = agentus_lexer::lexer::Lexer::new(source).tokenize_with_recovery();
+    let lex_spans: Vec<_> = lex_errors.iter().map(|e| e.span).collect();
+    for err in lex_errors {
+        report.push(AgentusError::LexerError { message: err.message, span: err.span });
+    }
+    let (program, parse_errors) = agentus_parser::parser::Parser::new(tokens).parse_with_recovery();
+    for err in &parse_errors {
+        let (message, span) = split_span(err);
+        // Error placeholders were already reported by the lexer
+        if !lex_spans.contains(&span) {
+            report.push(AgentusError::ParserError { message, span });
         }
-        Err(e) => {
-            eprintln!("Compilation error: {}", e);
-            process::exit(1);
+    }
+    if !report.is_empty() {
+        exit_with_report(&report, source, path);
+    }
+
+    // Semantic analysis
+    if let Err(errors) = agentus_sema::resolver::resolve_errors(&program) {
+        for err in errors {
+            report.push(err);
+        }
+        exit_with_report(&report, source, path);
+    }
+
+    // Compile to bytecode
+    match agentus_codegen::compiler::Compiler::new().compile_with_warnings(&program) {
+        Ok((module, warnings)) => {
+            for warning in &warnings {
+                eprintln!("warning: {}", warning);
+            }
+            module
+        }
+        Err(error) => {
+            report.push(error);
+            exit_with_report(&report, source, path);
         }
     }
 }
+
+fn exit_with_report(report: &DiagnosticReport, source: &str, path: &str) -> ! {
+    eprint!("{}", report.render(source, path));
+    process::exit(1);
+}
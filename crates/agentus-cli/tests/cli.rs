@@ -0,0 +1,48 @@
+//! Command-line tests: run the `agentus-cli` binary on scripts written to a
+//! temporary directory.
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Write `source` to a fresh temporary `.ags` file and return its path.
+fn write_script(name: &str, source: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("agentus-cli-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, source).unwrap();
+    path
+}
+
+fn agentus(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_agentus-cli"))
+        .args(args)
+        .output()
+        .expect("failed to run agentus-cli")
+}
+
+const ENTRY_SCRIPT: &str = r#"
+fn setup() {
+    emit "setting up"
+}
+fn main() {
+    emit "main"
+}
+emit "top level"
+"#;
+
+#[test]
+fn test_exec_named_entry_point() {
+    let path = write_script("entries.ags", ENTRY_SCRIPT);
+    let path = path.to_str().unwrap();
+
+    let out = agentus(&["exec", path, "--entry", "setup"]);
+    assert!(out.status.success(), "stderr: {}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "setting up\n");
+
+    let out = agentus(&["exec", path]);
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "top level\n");
+
+    let out = agentus(&["exec", path, "--entry", "missing"]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("undefined entry point 'missing'"));
+}
@@ -1,23 +1,51 @@
 use std::collections::HashMap;
+use agentus_common::errors::{split_span, AgentusError};
 use agentus_ir::instruction::Instruction;
-use agentus_ir::module::{AgentDescriptor, AgentMemoryField, Function, ModuleBuilder, ToolDescriptor, ToolParamDescriptor};
+use agentus_ir::module::{
+    AgentDescriptor, AgentMemoryField, Function, ModuleBuilder, PipelineDescriptor, ToolDescriptor,
+    ToolParamDescriptor, STRIPPED_NAME_IDX,
+};
 use agentus_ir::opcode::OpCode;
 use agentus_parser::ast::*;
 
+/// Settings that control optional compiler passes.
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptions {
+    /// Maximum instruction count of a function body that may be inlined at
+    /// its call sites. `0` disables inlining.
+    pub inline_threshold: usize,
+}
+
 /// Compiles an AST Program into a bytecode Module.
 pub struct Compiler {
     builder: ModuleBuilder,
+    options: CompileOptions,
 }
 
 impl Compiler {
     pub fn new() -> Self {
+        Self::with_options(CompileOptions::default())
+    }
+
+    /// Create a compiler with the given pass settings.
+    pub fn with_options(options: CompileOptions) -> Self {
         Self {
             builder: ModuleBuilder::new(),
+            options,
         }
     }
 
     /// Compile a program into a Module.
-    pub fn compile(mut self, program: &Program) -> Result<agentus_ir::module::Module, String> {
+    pub fn compile(self, program: &Program) -> Result<agentus_ir::module::Module, AgentusError> {
+        self.compile_with_warnings(program).map(|(module, _)| module)
+    }
+
+    /// Compile a program into a Module, also returning non-fatal warnings
+    /// such as agent methods that override a parent's method.
+    pub fn compile_with_warnings(
+        mut self,
+        program: &Program,
+    ) -> Result<(agentus_ir::module::Module, Vec<String>), AgentusError> {
         let mut emitter = FunctionEmitter::new(&mut self.builder);
 
         for stmt in &program.statements {
@@ -26,6 +54,8 @@ impl Compiler {
 
         emitter.emit(Instruction::op_only(OpCode::Halt));
 
+        let warnings = emitter.warnings;
+        let function_table = emitter.function_table;
         let instructions = emitter.instructions;
         let num_registers = emitter.next_register;
         let locals = emitter.locals; // keep the compiler happy
@@ -40,8 +70,61 @@ impl Compiler {
 
         let entry = self.builder.add_function(func);
         self.builder.set_entry_function(entry);
+        self.builder.add_named_entry_point("__main__", entry);
+        // Parameterless top-level functions (`main`, `setup`, ...) can be
+        // run as entry points in place of the top-level statements
+        for stmt in &program.statements {
+            if let Stmt::FnDef(f) = stmt
+                && f.params.is_empty()
+                && let Some(&(_, idx)) = function_table.iter().rev().find(|(name, _)| *name == f.name)
+            {
+                self.builder.add_named_entry_point(&f.name, idx);
+            }
+        }
+
+        let mut module = self.builder.build();
+        if self.options.inline_threshold > 0 {
+            crate::passes::inliner::inline_small_functions(&mut module, self.options.inline_threshold);
+        }
+        Ok((module, warnings))
+    }
+
+    /// Compile a single function definition in isolation.
+    ///
+    /// Constants used by the function are added to this compiler's module
+    /// builder, so the caller should insert the returned `Function` into the
+    /// module obtained from [`Compiler::into_builder`]. The function body
+    /// cannot call other user functions, agents, or tools by name.
+    pub fn compile_function(&mut self, func: &FnDef) -> Result<Function, AgentusError> {
+        let emitter = FunctionEmitter::new(&mut self.builder);
+        emitter.compile_function_body(func, &mut Vec::new())
+    }
+
+    /// Compile a single agent definition in isolation, returning its
+    /// descriptor and the compiled functions of its methods.
+    ///
+    /// Method function indices in the descriptor are positions in the
+    /// returned `Vec`; offset them by the index of the first function once
+    /// the functions are added to a module. Constants are added to this
+    /// compiler's module builder, as with [`Compiler::compile_function`].
+    /// The agent cannot extend another agent.
+    pub fn compile_agent(
+        &mut self,
+        agent: &AgentDef,
+    ) -> Result<(AgentDescriptor, Vec<Function>), AgentusError> {
+        let mut emitter = FunctionEmitter::new(&mut self.builder);
+        let mut functions = Vec::new();
+        let descriptor = emitter.build_agent_descriptor(agent, &mut |_, func| {
+            functions.push(func);
+            (functions.len() - 1) as u32
+        })?;
+        Ok((descriptor, functions))
+    }
 
-        Ok(self.builder.build())
+    /// Consume the compiler, returning the module builder with all constants
+    /// and functions compiled so far.
+    pub fn into_builder(self) -> ModuleBuilder {
+        self.builder
     }
 }
 
@@ -66,6 +149,15 @@ struct FunctionEmitter<'a> {
     agent_table: Vec<(String, u32)>,
     /// Tool name → (descriptor index, param defaults).
     tool_table: Vec<(String, u32, Vec<Option<u16>>)>,
+    /// Pipeline name → descriptor index in the module.
+    pipeline_table: Vec<(String, u32)>,
+    /// Names of fields declared in `global {}` blocks (read with GLoad).
+    global_table: Vec<String>,
+    /// Non-fatal diagnostics, passed up from nested emitters.
+    warnings: Vec<String>,
+    /// Handlers active at the current point, innermost last, with the
+    /// `finally` body (if any) that a `return` must run after popping each.
+    try_stack: Vec<Option<Vec<Stmt>>>,
 }
 
 impl<'a> FunctionEmitter<'a> {
@@ -78,6 +170,10 @@ impl<'a> FunctionEmitter<'a> {
             function_table: Vec::new(),
             agent_table: Vec::new(),
             tool_table: Vec::new(),
+            pipeline_table: Vec::new(),
+            global_table: Vec::new(),
+            warnings: Vec::new(),
+            try_stack: Vec::new(),
         }
     }
 
@@ -96,7 +192,7 @@ impl<'a> FunctionEmitter<'a> {
         self.instructions.len()
     }
 
-    fn compile_stmt(&mut self, stmt: &Stmt) -> Result<(), String> {
+    fn compile_stmt(&mut self, stmt: &Stmt) -> Result<(), AgentusError> {
         match stmt {
             Stmt::Let(l) => {
                 let reg = self.compile_expr(&l.value)?;
@@ -110,9 +206,18 @@ impl<'a> FunctionEmitter<'a> {
             }
             Stmt::Return(r) => {
                 if let Some(value) = &r.value {
-                    let reg = self.compile_expr(value)?;
+                    let mut reg = self.compile_expr(value)?;
+                    if !self.try_stack.is_empty() {
+                        // The value is fixed before any finally body runs,
+                        // even if that body reassigns the returned local.
+                        let saved = self.alloc_register();
+                        self.emit(Instruction::abc(OpCode::Move, saved, reg, 0));
+                        reg = saved;
+                    }
+                    self.compile_unwind_handlers()?;
                     self.emit(Instruction::op_a(OpCode::Ret, reg));
                 } else {
+                    self.compile_unwind_handlers()?;
                     self.emit(Instruction::op_only(OpCode::RetNone));
                 }
                 Ok(())
@@ -125,8 +230,14 @@ impl<'a> FunctionEmitter<'a> {
                 let reg = self.compile_expr(&a.value)?;
                 if let Some(&existing) = self.locals.get(&a.name) {
                     self.emit(Instruction::abc(OpCode::Move, existing, reg, 0));
+                } else if self.global_table.contains(&a.name) {
+                    let name_idx = self.builder.add_string_constant(&a.name);
+                    self.emit(Instruction::abx(OpCode::GStore, reg, name_idx));
                 } else {
-                    return Err(format!("undefined variable '{}' in assignment", a.name));
+                    return Err(AgentusError::codegen(format!(
+                        "undefined variable '{}' in assignment",
+                        a.name
+                    )));
                 }
                 Ok(())
             }
@@ -136,10 +247,38 @@ impl<'a> FunctionEmitter<'a> {
             Stmt::FnDef(f) => self.compile_fn_def(f),
             Stmt::AgentDef(a) => self.compile_agent_def(a),
             Stmt::ToolDef(t) => self.compile_tool_def(t),
+            Stmt::PipelineDef(p) => self.compile_pipeline_def(p),
+            Stmt::GlobalDecl(g) => {
+                // Initialize each field when the entry function reaches the declaration
+                for field in &g.fields {
+                    let reg = match &field.default {
+                        Some(default) => self.compile_expr(default)?,
+                        None => {
+                            let reg = self.alloc_register();
+                            self.emit(Instruction::op_a(OpCode::LoadNone, reg));
+                            reg
+                        }
+                    };
+                    let name_idx = self.builder.add_string_constant(&field.name);
+                    self.emit(Instruction::abx(OpCode::GStore, reg, name_idx));
+                    self.global_table.push(field.name.clone());
+                }
+                Ok(())
+            }
             Stmt::Send(s) => {
-                let target_reg = self.compile_expr(&s.target)?;
+                // C=1 targets the current frame's agent (`send self, msg`)
+                let (target_reg, to_self) = if self.is_self_ref(&s.target) {
+                    (0, 1)
+                } else {
+                    (self.compile_expr(&s.target)?, 0)
+                };
                 let msg_reg = self.compile_expr(&s.message)?;
-                self.emit(Instruction::abc(OpCode::Send, target_reg, msg_reg, 0));
+                self.emit(Instruction::abc(OpCode::Send, target_reg, msg_reg, to_self));
+                Ok(())
+            }
+            Stmt::Kill(k) => {
+                let target_reg = self.compile_expr(&k.target)?;
+                self.emit(Instruction::op_a(OpCode::Kill, target_reg));
                 Ok(())
             }
             Stmt::IndexAssign(ia) => {
@@ -158,7 +297,7 @@ impl<'a> FunctionEmitter<'a> {
                         self.emit(Instruction::abx(OpCode::MStore, val_reg, field_idx));
                         Ok(())
                     }
-                    _ => Err("field assignment is only supported on 'self'".to_string()),
+                    _ => Err(AgentusError::codegen("field assignment is only supported on 'self'")),
                 }
             }
             Stmt::TryCatch(tc) => self.compile_try_catch(tc),
@@ -167,11 +306,16 @@ impl<'a> FunctionEmitter<'a> {
                 self.emit(Instruction::op_a(OpCode::Throw, val_reg));
                 Ok(())
             }
+            Stmt::Yield(y) => {
+                let val_reg = self.compile_expr(&y.value)?;
+                self.emit(Instruction::op_a(OpCode::Yield, val_reg));
+                Ok(())
+            }
             Stmt::Assert(a) => self.compile_assert(a),
         }
     }
 
-    fn compile_if(&mut self, stmt: &IfStmt) -> Result<(), String> {
+    fn compile_if(&mut self, stmt: &IfStmt) -> Result<(), AgentusError> {
         let cond_reg = self.compile_expr(&stmt.condition)?;
 
         // JmpFalse cond_reg, offset (to else/end)
@@ -214,7 +358,7 @@ impl<'a> FunctionEmitter<'a> {
         Ok(())
     }
 
-    fn compile_while(&mut self, stmt: &WhileStmt) -> Result<(), String> {
+    fn compile_while(&mut self, stmt: &WhileStmt) -> Result<(), AgentusError> {
         let loop_start = self.current_offset();
         let cond_reg = self.compile_expr(&stmt.condition)?;
 
@@ -240,26 +384,42 @@ impl<'a> FunctionEmitter<'a> {
         Ok(())
     }
 
-    fn compile_for(&mut self, stmt: &ForStmt) -> Result<(), String> {
+    /// Whether `expr` is the agent reference `self` inside a method, which
+    /// has no register of its own: opcodes that accept it take a flag and
+    /// use the current frame's agent instead.
+    fn is_self_ref(&self, expr: &Expr) -> bool {
+        matches!(expr, Expr::Ident(name, _) if name == "self") && !self.locals.contains_key("self")
+    }
+
+    fn compile_for(&mut self, stmt: &ForStmt) -> Result<(), AgentusError> {
         // Compile iterable
         let iter_source = self.compile_expr(&stmt.iterable)?;
 
-        // Create iterator
+        // Create iterator; C=1 iterates (key, value) pairs for `for k, v in x`
+        let pairs = stmt.value_variable.is_some() as u8;
         let iter_reg = self.alloc_register();
-        self.emit(Instruction::abc(OpCode::IterInit, iter_reg, iter_source, 0));
+        self.emit(Instruction::abc(OpCode::IterInit, iter_reg, iter_source, pairs));
 
-        // Loop variable register
+        // Loop variable registers
         let var_reg = self.alloc_register();
         self.locals.insert(stmt.variable.clone(), var_reg);
+        let value_reg = match &stmt.value_variable {
+            Some(name) => {
+                let reg = self.alloc_register();
+                self.locals.insert(name.clone(), reg);
+                reg
+            }
+            None => 0,
+        };
 
         let loop_start = self.current_offset();
 
         // Two-instruction IterNext sequence:
         // 1. IterNext A=var_reg, sBx=jump_offset_if_exhausted (placeholder)
-        // 2. Extra data: B=iter_reg
+        // 2. Extra data: A=pairs flag, B=iter_reg, C=value_reg
         let iter_next_pos = self.current_offset();
         self.emit(Instruction::asbx(OpCode::IterNext, var_reg, 0)); // placeholder
-        self.emit(Instruction::abc(OpCode::Nop, 0, iter_reg, 0)); // extra data
+        self.emit(Instruction::abc(OpCode::Nop, pairs, iter_reg, value_reg)); // extra data
 
         // Body
         for s in &stmt.body {
@@ -281,51 +441,116 @@ impl<'a> FunctionEmitter<'a> {
         Ok(())
     }
 
-    fn compile_fn_def(&mut self, func: &FnDef) -> Result<(), String> {
+    fn compile_fn_def(&mut self, func: &FnDef) -> Result<(), AgentusError> {
         // For Phase 1, we compile functions inline (not as separate function entries).
         // A proper implementation would create a separate Function in the module
         // and use the Call opcode. For now, we just define the function name.
         // TODO: Implement proper function compilation in Phase 2.
 
+        // Reserve the function's index up front so its body can call itself
+        let func_idx = self.builder.add_function(Function {
+            name_idx: STRIPPED_NAME_IDX,
+            num_params: func.params.len() as u8,
+            num_registers: 0,
+            instructions: Vec::new(),
+        });
+
         // Compile function body in a separate emitter
-        let (fn_instructions, fn_num_registers) = {
+        let compiled_func = {
             let mut fn_emitter = FunctionEmitter::new(self.builder);
             // Propagate tables so functions can call tools, other functions, and agents
             fn_emitter.function_table = self.function_table.clone();
+            fn_emitter.function_table.push((func.name.clone(), func_idx));
             fn_emitter.agent_table = self.agent_table.clone();
             fn_emitter.tool_table = self.tool_table.clone();
-            for param in &func.params {
-                let reg = fn_emitter.alloc_register();
-                fn_emitter.locals.insert(param.name.clone(), reg);
-            }
-            for stmt in &func.body {
-                fn_emitter.compile_stmt(stmt)?;
-            }
-            fn_emitter.emit(Instruction::op_only(OpCode::RetNone));
-            (fn_emitter.instructions, fn_emitter.next_register)
+            fn_emitter.pipeline_table = self.pipeline_table.clone();
+            fn_emitter.global_table = self.global_table.clone();
+            fn_emitter.compile_function_body(func, &mut self.warnings)?
         };
 
-        let compiled_func = Function {
+        self.builder.set_function(func_idx, compiled_func);
+        self.function_table.push((func.name.clone(), func_idx));
+
+        Ok(())
+    }
+
+    /// Compile `func` into a standalone `Function`, using this emitter's
+    /// tables for name resolution. Consumes the emitter, moving any warnings
+    /// it produced into `warnings`.
+    fn compile_function_body(
+        mut self,
+        func: &FnDef,
+        warnings: &mut Vec<String>,
+    ) -> Result<Function, AgentusError> {
+        for param in &func.params {
+            let reg = self.alloc_register();
+            self.locals.insert(param.name.clone(), reg);
+        }
+        for stmt in &func.body {
+            self.compile_stmt(stmt)?;
+        }
+        self.emit(Instruction::op_only(OpCode::RetNone));
+        warnings.append(&mut self.warnings);
+
+        Ok(Function {
             name_idx: self.builder.add_string_constant(&func.name) as u32,
             num_params: func.params.len() as u8,
-            num_registers: fn_num_registers,
-            instructions: fn_instructions,
-        };
+            num_registers: self.next_register,
+            instructions: self.instructions,
+        })
+    }
 
-        let func_idx = self.builder.add_function(compiled_func);
-        self.function_table.push((func.name.clone(), func_idx));
-        self.locals.insert(func.name.clone(), 0); // Register the name
+    fn compile_agent_def(&mut self, agent: &AgentDef) -> Result<(), AgentusError> {
+        let descriptor =
+            self.build_agent_descriptor(agent, &mut |builder, func| builder.add_function(func))?;
+        let desc_idx = self.builder.add_agent(descriptor);
+        self.agent_table.push((agent.name.clone(), desc_idx));
+        self.locals.insert(agent.name.clone(), 0); // register the name for resolution
 
         Ok(())
     }
 
-    fn compile_agent_def(&mut self, agent: &AgentDef) -> Result<(), String> {
-        // Add model/system_prompt to constant pool
-        let model_idx = agent.model.as_ref().map(|m| self.builder.add_string_constant(m));
-        let system_prompt_idx = agent.system_prompt.as_ref().map(|s| self.builder.add_string_constant(s));
+    /// Build an agent's descriptor, compiling each method and handing it to
+    /// `add_function`, which returns the function index to record.
+    fn build_agent_descriptor(
+        &mut self,
+        agent: &AgentDef,
+        add_function: &mut dyn FnMut(&mut ModuleBuilder, Function) -> u32,
+    ) -> Result<AgentDescriptor, AgentusError> {
+        // Start from a copy of the parent's descriptor, if any
+        let parent = match &agent.extends {
+            Some(parent_name) => {
+                let parent_idx = self
+                    .agent_table
+                    .iter()
+                    .rev()
+                    .find(|(name, _)| name == parent_name)
+                    .map(|(_, idx)| *idx)
+                    .ok_or_else(|| {
+                        AgentusError::codegen(format!(
+                            "undefined parent agent '{}' for agent '{}'",
+                            parent_name, agent.name
+                        ))
+                    })?;
+                self.builder.get_agent(parent_idx).cloned()
+            }
+            None => None,
+        };
 
-        // Build memory field descriptors
-        let mut memory_fields = Vec::new();
+        // Add model/system_prompt to constant pool; unset ones are inherited
+        let model_idx = agent
+            .model
+            .as_ref()
+            .map(|m| self.builder.add_string_constant(m))
+            .or(parent.as_ref().and_then(|p| p.model_idx));
+        let system_prompt_idx = agent
+            .system_prompt
+            .as_ref()
+            .map(|s| self.builder.add_string_constant(s))
+            .or(parent.as_ref().and_then(|p| p.system_prompt_idx));
+
+        // Build memory field descriptors; a child field replaces a parent field of the same name
+        let mut memory_fields = parent.as_ref().map(|p| p.memory_fields.clone()).unwrap_or_default();
         for field in &agent.memory_fields {
             let name_idx = self.builder.add_string_constant(&field.name);
             let default_idx = field.default.as_ref().map(|expr| {
@@ -336,11 +561,17 @@ impl<'a> FunctionEmitter<'a> {
                     _ => self.builder.add_none_constant(),
                 }
             });
-            memory_fields.push(AgentMemoryField { name_idx, default_idx });
+            let field = AgentMemoryField { name_idx, default_idx };
+            match memory_fields.iter_mut().find(|f| f.name_idx == name_idx) {
+                Some(existing) => *existing = field,
+                None => memory_fields.push(field),
+            }
         }
 
-        // Compile each method as a separate Function
-        let mut methods = Vec::new();
+        // Compile each method as a separate Function; inherited methods keep
+        // the parent's compiled function and may be overridden in place
+        let mut methods = parent.as_ref().map(|p| p.methods.clone()).unwrap_or_default();
+        let inherited_count = methods.len();
         for method in &agent.methods {
             let method_name_idx = self.builder.add_string_constant(&method.name);
 
@@ -350,6 +581,8 @@ impl<'a> FunctionEmitter<'a> {
                 fn_emitter.function_table = self.function_table.clone();
                 fn_emitter.agent_table = self.agent_table.clone();
                 fn_emitter.tool_table = self.tool_table.clone();
+                fn_emitter.pipeline_table = self.pipeline_table.clone();
+                fn_emitter.global_table = self.global_table.clone();
                 // Methods don't get an implicit `self` register;
                 // self.field is compiled as MLoad/MStore using the frame's agent_id
                 for param in &method.params {
@@ -360,6 +593,7 @@ impl<'a> FunctionEmitter<'a> {
                     fn_emitter.compile_stmt(stmt)?;
                 }
                 fn_emitter.emit(Instruction::op_only(OpCode::RetNone));
+                self.warnings.append(&mut fn_emitter.warnings);
                 (fn_emitter.instructions, fn_emitter.next_register)
             };
 
@@ -370,26 +604,35 @@ impl<'a> FunctionEmitter<'a> {
                 instructions: fn_instructions,
             };
 
-            let func_idx = self.builder.add_function(compiled_func);
-            methods.push((method_name_idx, func_idx));
+            let func_idx = add_function(self.builder, compiled_func);
+            let inherited = methods[..inherited_count]
+                .iter_mut()
+                .find(|(name_idx, _)| *name_idx == method_name_idx);
+            match inherited {
+                Some(inherited) => {
+                    self.warnings.push(format!(
+                        "method '{}' in agent '{}' overrides the method inherited from '{}'",
+                        method.name,
+                        agent.name,
+                        agent.extends.as_deref().unwrap_or_default()
+                    ));
+                    inherited.1 = func_idx;
+                }
+                None => methods.push((method_name_idx, func_idx)),
+            }
         }
 
         let name_idx = self.builder.add_string_constant(&agent.name);
-        let descriptor = AgentDescriptor {
+        Ok(AgentDescriptor {
             name_idx,
             model_idx,
             system_prompt_idx,
             memory_fields,
             methods,
-        };
-        let desc_idx = self.builder.add_agent(descriptor);
-        self.agent_table.push((agent.name.clone(), desc_idx));
-        self.locals.insert(agent.name.clone(), 0); // register the name for resolution
-
-        Ok(())
+        })
     }
 
-    fn compile_tool_def(&mut self, tool: &ToolDef) -> Result<(), String> {
+    fn compile_tool_def(&mut self, tool: &ToolDef) -> Result<(), AgentusError> {
         let name_idx = self.builder.add_string_constant(&tool.name);
         let description_idx = tool.description.as_ref().map(|d| self.builder.add_string_constant(d));
 
@@ -424,7 +667,48 @@ impl<'a> FunctionEmitter<'a> {
         Ok(())
     }
 
-    fn compile_try_catch(&mut self, tc: &TryCatchStmt) -> Result<(), String> {
+    fn compile_pipeline_def(&mut self, pipeline: &PipelineDef) -> Result<(), AgentusError> {
+        let name_idx = self.builder.add_string_constant(&pipeline.name);
+        let mut stages = Vec::new();
+        for stage in &pipeline.stages {
+            let func_idx = self
+                .function_table
+                .iter()
+                .find(|(n, _)| *n == stage.name)
+                .map(|(_, idx)| *idx)
+                .ok_or_else(|| {
+                    AgentusError::codegen(format!(
+                        "undefined function '{}' for stage in pipeline '{}'",
+                        stage.name, pipeline.name
+                    ))
+                })?;
+            stages.push(func_idx);
+        }
+
+        let desc_idx = self.builder.add_pipeline(PipelineDescriptor { name_idx, stages });
+        self.pipeline_table.push((pipeline.name.clone(), desc_idx));
+        Ok(())
+    }
+
+    /// Pops every enclosing handler before a `return`, innermost first,
+    /// running each region's `finally` body once its handler is gone.
+    fn compile_unwind_handlers(&mut self) -> Result<(), AgentusError> {
+        let regions = self.try_stack.clone();
+        for (depth, finally_body) in regions.iter().enumerate().rev() {
+            self.emit(Instruction::op_only(OpCode::TryEnd));
+            if let Some(finally_body) = finally_body {
+                // A return inside the finally body only unwinds outer regions.
+                let saved = std::mem::replace(&mut self.try_stack, regions[..depth].to_vec());
+                for s in finally_body {
+                    self.compile_stmt(s)?;
+                }
+                self.try_stack = saved;
+            }
+        }
+        Ok(())
+    }
+
+    fn compile_try_catch(&mut self, tc: &TryCatchStmt) -> Result<(), AgentusError> {
         let err_reg = self.alloc_register();
 
         // TryBegin — push handler; offset to catch is placeholder
@@ -432,9 +716,11 @@ impl<'a> FunctionEmitter<'a> {
         self.emit(Instruction::asbx(OpCode::TryBegin, err_reg, 0));
 
         // Try body
+        self.try_stack.push(tc.finally_body.clone());
         for s in &tc.try_body {
             self.compile_stmt(s)?;
         }
+        self.try_stack.pop();
 
         // TryEnd — pop handler (normal completion)
         self.emit(Instruction::op_only(OpCode::TryEnd));
@@ -455,20 +741,56 @@ impl<'a> FunctionEmitter<'a> {
         self.emit(Instruction::op_a(OpCode::GetError, err_reg));
         self.locals.insert(tc.catch_var.clone(), err_reg);
 
-        // Catch body
+        let Some(finally_body) = &tc.finally_body else {
+            for s in &tc.catch_body {
+                self.compile_stmt(s)?;
+            }
+
+            // Patch jump-over-catch
+            let after_catch = self.current_offset();
+            let jmp_offset = (after_catch as i32) - (jmp_over_catch as i32) - 1;
+            self.instructions[jmp_over_catch] = Instruction::sbx(OpCode::Jmp, jmp_offset);
+            return Ok(());
+        };
+
+        // Catch body, guarded so that a throw out of it still runs finally
+        let rethrow_reg = self.alloc_register();
+        let rethrow_begin_pos = self.current_offset();
+        self.emit(Instruction::asbx(OpCode::TryBegin, rethrow_reg, 0));
+        self.try_stack.push(Some(finally_body.clone()));
         for s in &tc.catch_body {
             self.compile_stmt(s)?;
         }
+        self.try_stack.pop();
+        self.emit(Instruction::op_only(OpCode::TryEnd));
+        let jmp_over_rethrow = self.current_offset();
+        self.emit(Instruction::sbx(OpCode::Jmp, 0)); // placeholder
+
+        // Rethrow handler: run finally, then throw the catch block's error on
+        let rethrow_start = self.current_offset();
+        let rethrow_offset = (rethrow_start as i16) - (rethrow_begin_pos as i16) - 1;
+        self.instructions[rethrow_begin_pos] =
+            Instruction::asbx(OpCode::TryBegin, rethrow_reg, rethrow_offset);
+        self.emit(Instruction::op_a(OpCode::GetError, rethrow_reg));
+        for s in finally_body {
+            self.compile_stmt(s)?;
+        }
+        self.emit(Instruction::op_a(OpCode::Throw, rethrow_reg));
 
-        // Patch jump-over-catch
-        let after_catch = self.current_offset();
-        let jmp_offset = (after_catch as i32) - (jmp_over_catch as i32) - 1;
-        self.instructions[jmp_over_catch] = Instruction::sbx(OpCode::Jmp, jmp_offset);
+        // Both the success path and the completed catch block land here
+        let finally_start = self.current_offset();
+        for jmp in [jmp_over_catch, jmp_over_rethrow] {
+            let jmp_offset = (finally_start as i32) - (jmp as i32) - 1;
+            self.instructions[jmp] = Instruction::sbx(OpCode::Jmp, jmp_offset);
+        }
+        for s in finally_body {
+            self.compile_stmt(s)?;
+        }
 
         Ok(())
     }
 
-    fn compile_assert(&mut self, a: &AssertStmt) -> Result<(), String> {
+    fn compile_assert(&mut self, a: &AssertStmt) -> Result<(), AgentusError> {
         let cond_reg = self.compile_expr(&a.condition)?;
 
         // JmpTrue => skip the throw if condition is true
@@ -496,8 +818,51 @@ impl<'a> FunctionEmitter<'a> {
         Ok(())
     }
 
+    /// Compile `assert_eq(actual, expected)` or `assert_ne(actual, expected)`:
+    /// compare the two values and, if the check fails, throw a message
+    /// showing both. Evaluates to `none`.
+    fn compile_assert_cmp(&mut self, name: &str, args: &[Expr]) -> Result<u8, AgentusError> {
+        if args.len() != 2 {
+            return Err(AgentusError::codegen(format!("{}() takes exactly 2 arguments", name)));
+        }
+        let actual_reg = self.compile_expr(&args[0])?;
+        let expected_reg = self.compile_expr(&args[1])?;
+        let (op, prefix) = if name == "assert_eq" {
+            (OpCode::Eq, "assertion failed: expected ")
+        } else {
+            (OpCode::Neq, "assertion failed: expected a value other than ")
+        };
+        let cond_reg = self.alloc_register();
+        self.emit(Instruction::abc(op, cond_reg, actual_reg, expected_reg));
+
+        // JmpTrue => skip the throw if the check passed
+        let jmp_pos = self.current_offset();
+        self.emit(Instruction::asbx(OpCode::JmpTrue, cond_reg, 0)); // placeholder
+
+        // Build "<prefix><expected>, got <actual>"
+        let msg_reg = self.alloc_register();
+        let prefix_idx = self.builder.add_string_constant(prefix);
+        self.emit(Instruction::abx(OpCode::LoadConst, msg_reg, prefix_idx));
+        self.emit(Instruction::abc(OpCode::Concat, msg_reg, msg_reg, expected_reg));
+        let got_reg = self.alloc_register();
+        let got_idx = self.builder.add_string_constant(", got ");
+        self.emit(Instruction::abx(OpCode::LoadConst, got_reg, got_idx));
+        self.emit(Instruction::abc(OpCode::Concat, msg_reg, msg_reg, got_reg));
+        self.emit(Instruction::abc(OpCode::Concat, msg_reg, msg_reg, actual_reg));
+        self.emit(Instruction::op_a(OpCode::Throw, msg_reg));
+
+        // Patch jump
+        let after = self.current_offset();
+        let offset = (after as i16) - (jmp_pos as i16) - 1;
+        self.instructions[jmp_pos] = Instruction::asbx(OpCode::JmpTrue, cond_reg, offset);
+
+        let result_reg = self.alloc_register();
+        self.emit(Instruction::op_a(OpCode::LoadNone, result_reg));
+        Ok(result_reg)
+    }
+
     /// Compile an expression and return the register it's stored in.
-    fn compile_expr(&mut self, expr: &Expr) -> Result<u8, String> {
+    fn compile_expr(&mut self, expr: &Expr) -> Result<u8, AgentusError> {
         match expr {
             Expr::StringLit(s, _) => {
                 let reg = self.alloc_register();
@@ -523,6 +888,13 @@ impl<'a> FunctionEmitter<'a> {
                             // (Concat already converts both operands to strings)
                             expr_reg
                         }
+                        TemplateSegment::Formatted(expr, _) => {
+                            // `json` is the only specifier the parser accepts
+                            let expr_reg = self.compile_expr(expr)?;
+                            let reg = self.alloc_register();
+                            self.emit(Instruction::abc(OpCode::ToJson, reg, expr_reg, 0));
+                            reg
+                        }
                     };
 
                     result_reg = Some(match result_reg {
@@ -571,8 +943,40 @@ impl<'a> FunctionEmitter<'a> {
             Expr::Ident(name, _) => {
                 if let Some(&reg) = self.locals.get(name) {
                     Ok(reg)
+                } else if let Some(&(_, func_idx)) =
+                    self.function_table.iter().rev().find(|(n, _)| n == name)
+                {
+                    // A named function used as a value
+                    let reg = self.alloc_register();
+                    self.emit(Instruction::abx(OpCode::MakeFn, reg, func_idx as u16));
+                    Ok(reg)
+                } else if name == "args" || self.global_table.contains(name) {
+                    let reg = self.alloc_register();
+                    let name_idx = self.builder.add_string_constant(name);
+                    self.emit(Instruction::abx(OpCode::GLoad, reg, name_idx));
+                    Ok(reg)
+                } else if name == "env" {
+                    let reg = self.alloc_register();
+                    self.emit(Instruction::op_a(OpCode::LoadEnv, reg));
+                    Ok(reg)
+                } else if name == "cmp" {
+                    // The built-in used as a value, e.g. `items.sort_by(cmp)`:
+                    // wrap the Cmp opcode in a two-parameter function
+                    let func = Function {
+                        name_idx: self.builder.add_string_constant("cmp") as u32,
+                        num_params: 2,
+                        num_registers: 3,
+                        instructions: vec![
+                            Instruction::abc(OpCode::Cmp, 2, 0, 1),
+                            Instruction::op_a(OpCode::Ret, 2),
+                        ],
+                    };
+                    let func_idx = self.builder.add_function(func);
+                    let reg = self.alloc_register();
+                    self.emit(Instruction::abx(OpCode::MakeFn, reg, func_idx as u16));
+                    Ok(reg)
                 } else {
-                    Err(format!("undefined variable '{}'", name))
+                    Err(AgentusError::codegen(format!("undefined variable '{}'", name)))
                 }
             }
             Expr::BinOp(left, op, right, _) => {
@@ -612,7 +1016,7 @@ impl<'a> FunctionEmitter<'a> {
                 // Built-in functions
                 if name == "len" {
                     if args.len() != 1 {
-                        return Err("len() takes exactly 1 argument".to_string());
+                        return Err(AgentusError::codegen("len() takes exactly 1 argument"));
                     }
                     let arg_reg = self.compile_expr(&args[0])?;
                     let result_reg = self.alloc_register();
@@ -621,7 +1025,7 @@ impl<'a> FunctionEmitter<'a> {
                 }
                 if name == "parse_json" {
                     if args.len() != 1 {
-                        return Err("parse_json() takes exactly 1 argument".to_string());
+                        return Err(AgentusError::codegen("parse_json() takes exactly 1 argument"));
                     }
                     let arg_reg = self.compile_expr(&args[0])?;
                     let result_reg = self.alloc_register();
@@ -630,13 +1034,39 @@ impl<'a> FunctionEmitter<'a> {
                 }
                 if name == "to_json" {
                     if args.len() != 1 {
-                        return Err("to_json() takes exactly 1 argument".to_string());
+                        return Err(AgentusError::codegen("to_json() takes exactly 1 argument"));
                     }
                     let arg_reg = self.compile_expr(&args[0])?;
                     let result_reg = self.alloc_register();
                     self.emit(Instruction::abc(OpCode::ToJson, result_reg, arg_reg, 0));
                     return Ok(result_reg);
                 }
+                if name == "assert_eq" || name == "assert_ne" {
+                    return self.compile_assert_cmp(name, args);
+                }
+                if name == "cmp" {
+                    if args.len() != 2 {
+                        return Err(AgentusError::codegen("cmp() takes exactly 2 arguments"));
+                    }
+                    let left_reg = self.compile_expr(&args[0])?;
+                    let right_reg = self.compile_expr(&args[1])?;
+                    let result_reg = self.alloc_register();
+                    self.emit(Instruction::abc(OpCode::Cmp, result_reg, left_reg, right_reg));
+                    return Ok(result_reg);
+                }
+                if name == "to_base64" || name == "from_base64" {
+                    if args.len() != 1 {
+                        return Err(AgentusError::codegen(format!(
+                            "{}() takes exactly 1 argument",
+                            name
+                        )));
+                    }
+                    let op = if name == "to_base64" { OpCode::ToBase64 } else { OpCode::FromBase64 };
+                    let arg_reg = self.compile_expr(&args[0])?;
+                    let result_reg = self.alloc_register();
+                    self.emit(Instruction::abc(op, result_reg, arg_reg, 0));
+                    return Ok(result_reg);
+                }
 
                 // Check agent_table first (agent instantiation)
                 let agent_idx = self
@@ -659,31 +1089,7 @@ impl<'a> FunctionEmitter<'a> {
                     .map(|(_, idx, defaults)| (*idx, defaults.clone()));
 
                 if let Some((tool_desc_idx, param_defaults)) = tool_info {
-                    // Compile explicit arguments
-                    let mut arg_regs = Vec::new();
-                    for arg in args {
-                        arg_regs.push(self.compile_expr(arg)?);
-                    }
-
-                    // Fill in defaults for missing arguments
-                    let total_params = param_defaults.len();
-                    for i in args.len()..total_params {
-                        if let Some(default_idx) = param_defaults[i] {
-                            let reg = self.alloc_register();
-                            self.emit(Instruction::abx(OpCode::LoadConst, reg, default_idx));
-                            arg_regs.push(reg);
-                        }
-                    }
-
-                    // Copy into consecutive destination registers
-                    let first_arg_reg = self.next_register;
-                    for &src_reg in &arg_regs {
-                        let dest = self.alloc_register();
-                        if src_reg != dest {
-                            self.emit(Instruction::abc(OpCode::Move, dest, src_reg, 0));
-                        }
-                    }
-
+                    let (first_arg_reg, num_args) = self.compile_tool_args(args, &param_defaults)?;
                     let result_reg = self.alloc_register();
                     // Two-instruction TCall sequence:
                     // 1. TCall A=result_reg, Bx=tool_desc_idx
@@ -693,12 +1099,35 @@ impl<'a> FunctionEmitter<'a> {
                         result_reg,
                         tool_desc_idx as u16,
                     ));
-                    self.emit(Instruction::abc(
-                        OpCode::Nop,
-                        0,
-                        first_arg_reg,
-                        arg_regs.len() as u8,
+                    self.emit(Instruction::abc(OpCode::Nop, 0, first_arg_reg, num_args));
+                    return Ok(result_reg);
+                }
+
+                // Pipeline invocation: the single argument is the first stage's input
+                let pipeline_idx = self
+                    .pipeline_table
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, idx)| *idx);
+
+                if let Some(pipeline_idx) = pipeline_idx {
+                    if args.len() != 1 {
+                        return Err(AgentusError::codegen(format!(
+                            "pipeline '{}' takes exactly 1 argument",
+                            name
+                        )));
+                    }
+                    let input_reg = self.compile_expr(&args[0])?;
+                    let result_reg = self.alloc_register();
+                    // Two-instruction sequence:
+                    // 1. PipelineRun A=result_reg, Bx=pipeline_idx
+                    // 2. Nop A=0, B=input_reg, C=0
+                    self.emit(Instruction::abx(
+                        OpCode::PipelineRun,
+                        result_reg,
+                        pipeline_idx as u16,
                     ));
+                    self.emit(Instruction::abc(OpCode::Nop, 0, input_reg, 0));
                     return Ok(result_reg);
                 }
 
@@ -710,11 +1139,14 @@ impl<'a> FunctionEmitter<'a> {
                     .map(|(_, idx)| *idx);
 
                 if let Some(func_idx) = func_idx {
+                    let arity = self
+                        .builder
+                        .get_function(func_idx)
+                        .map(|f| f.num_params as usize)
+                        .unwrap_or(args.len());
+
                     // Compile all arguments first (may allocate non-consecutive registers)
-                    let mut arg_regs = Vec::new();
-                    for arg in args {
-                        arg_regs.push(self.compile_expr(arg)?);
-                    }
+                    let arg_regs = self.compile_call_args(args, arity)?;
 
                     // Now copy into consecutive destination registers
                     let first_arg_reg = self.next_register;
@@ -738,16 +1170,27 @@ impl<'a> FunctionEmitter<'a> {
                         OpCode::Nop, // extra data word (opcode ignored by VM)
                         0,
                         first_arg_reg,
-                        args.len() as u8,
+                        arg_regs.len() as u8,
                     ));
                     Ok(result_reg)
                 } else {
-                    Err(format!("undefined function or tool '{}'", name))
+                    Err(AgentusError::codegen(format!("undefined function or tool '{}'", name)))
                 }
             }
             Expr::MethodCall(obj, method_name, args, _) => {
+                if args.iter().any(|a| matches!(a, Expr::Spread(..))) {
+                    return Err(AgentusError::codegen(format!(
+                        "spread arguments are not supported in method call '{}'",
+                        method_name
+                    )));
+                }
+
+                // `self.method(...)` dispatches on the current frame's agent,
+                // so the receiver is never materialized
+                let self_call = self.is_self_ref(obj);
+
                 // Compile receiver
-                let obj_reg = self.compile_expr(obj)?;
+                let obj_reg = if self_call { None } else { Some(self.compile_expr(obj)?) };
 
                 // Compile all args
                 let mut arg_regs = Vec::new();
@@ -758,9 +1201,11 @@ impl<'a> FunctionEmitter<'a> {
                 // Copy handle + args to consecutive registers
                 let first_arg_reg = self.next_register;
 
-                // First: the handle
+                // First: the handle (left empty for a call on `self`)
                 let handle_dest = self.alloc_register();
-                if obj_reg != handle_dest {
+                if let Some(obj_reg) = obj_reg
+                    && obj_reg != handle_dest
+                {
                     self.emit(Instruction::abc(OpCode::Move, handle_dest, obj_reg, 0));
                 }
 
@@ -778,10 +1223,15 @@ impl<'a> FunctionEmitter<'a> {
 
                 // Three-instruction method call sequence:
                 // 1. Call A=result_reg, Bx=0xFFFE (sentinel)
-                // 2. Nop A=0, B=first_arg_reg, C=num_args_with_handle
+                // 2. Nop A=self_call, B=first_arg_reg, C=num_args_with_handle
                 // 3. Nop A=0, Bx=method_name_const_idx
                 self.emit(Instruction::abx(OpCode::Call, result_reg, 0xFFFE));
-                self.emit(Instruction::abc(OpCode::Nop, 0, first_arg_reg, num_args_with_handle));
+                self.emit(Instruction::abc(
+                    OpCode::Nop,
+                    self_call as u8,
+                    first_arg_reg,
+                    num_args_with_handle,
+                ));
                 self.emit(Instruction::abx(OpCode::Nop, 0, method_name_idx));
 
                 Ok(result_reg)
@@ -795,7 +1245,7 @@ impl<'a> FunctionEmitter<'a> {
                         self.emit(Instruction::abx(OpCode::MLoad, result_reg, field_idx));
                         Ok(result_reg)
                     }
-                    _ => Err("field access is only supported on 'self'".to_string()),
+                    _ => Err(AgentusError::codegen("field access is only supported on 'self'")),
                 }
             }
             Expr::IndexAccess(obj, index, _) => {
@@ -863,10 +1313,52 @@ impl<'a> FunctionEmitter<'a> {
                 self.emit(Instruction::abc(OpCode::Exec, result_reg, prompt_reg, 0));
                 Ok(result_reg)
             }
+            Expr::Batch(calls, _) => {
+                if calls.len() > u8::MAX as usize {
+                    return Err(AgentusError::codegen(format!(
+                        "batch has too many tool calls ({}, max 255)",
+                        calls.len()
+                    )));
+                }
+                // Evaluate every call's arguments up front; the calls
+                // themselves are dispatched together by BatchTCall
+                let mut entries = Vec::with_capacity(calls.len());
+                for call in calls {
+                    let tool_info = match call {
+                        Expr::FnCall(name, args, _) => self
+                            .tool_table
+                            .iter()
+                            .find(|(n, _, _)| n == name)
+                            .map(|(_, idx, defaults)| (*idx, defaults.clone(), args)),
+                        _ => None,
+                    };
+                    let (tool_desc_idx, param_defaults, args) =
+                        tool_info.ok_or_else(|| {
+                            AgentusError::codegen("batch entries must be tool calls")
+                        })?;
+                    let (first_arg_reg, num_args) = self.compile_tool_args(args, &param_defaults)?;
+                    entries.push((tool_desc_idx, first_arg_reg, num_args));
+                }
+
+                // BatchTCall A=result_reg, B=count, followed by `count`
+                // TCall sequences that describe the individual requests
+                let result_reg = self.alloc_register();
+                self.emit(Instruction::abc(OpCode::BatchTCall, result_reg, calls.len() as u8, 0));
+                for (tool_desc_idx, first_arg_reg, num_args) in entries {
+                    self.emit(Instruction::abx(OpCode::TCall, result_reg, tool_desc_idx as u16));
+                    self.emit(Instruction::abc(OpCode::Nop, 0, first_arg_reg, num_args));
+                }
+                Ok(result_reg)
+            }
             Expr::Recv(target, _) => {
-                let target_reg = self.compile_expr(target)?;
+                // C=1 reads the current frame's agent's mailbox (`recv self`)
+                let (target_reg, from_self) = if self.is_self_ref(target) {
+                    (0, 1)
+                } else {
+                    (self.compile_expr(target)?, 0)
+                };
                 let result_reg = self.alloc_register();
-                self.emit(Instruction::abc(OpCode::Recv, result_reg, target_reg, 0));
+                self.emit(Instruction::abc(OpCode::Recv, result_reg, target_reg, from_self));
                 Ok(result_reg)
             }
             Expr::Retry(attempts, body, _) => {
@@ -892,6 +1384,7 @@ impl<'a> FunctionEmitter<'a> {
 
                 // Compile body — capture last ExprStmt result
                 let mut last_expr_reg = None;
+                self.try_stack.push(None);
                 if let Some((last, rest)) = body.split_last() {
                     for s in rest {
                         self.compile_stmt(s)?;
@@ -905,6 +1398,7 @@ impl<'a> FunctionEmitter<'a> {
                         }
                     }
                 }
+                self.try_stack.pop();
 
                 // TryEnd (normal completion)
                 self.emit(Instruction::op_only(OpCode::TryEnd));
@@ -951,17 +1445,173 @@ impl<'a> FunctionEmitter<'a> {
 
                 Ok(result_reg)
             }
+            Expr::Spread(_, _) => {
+                Err(AgentusError::codegen(
+                    "spread '...' is only allowed in function or tool call arguments",
+                ))
+            }
+            Expr::Lambda(params, body, _) => {
+                // Compile the body as its own function; lambdas do not capture locals
+                let (fn_instructions, fn_num_registers) = {
+                    let mut fn_emitter = FunctionEmitter::new(self.builder);
+                    fn_emitter.function_table = self.function_table.clone();
+                    fn_emitter.agent_table = self.agent_table.clone();
+                    fn_emitter.tool_table = self.tool_table.clone();
+                    fn_emitter.pipeline_table = self.pipeline_table.clone();
+                    fn_emitter.global_table = self.global_table.clone();
+                    for param in params {
+                        let reg = fn_emitter.alloc_register();
+                        fn_emitter.locals.insert(param.clone(), reg);
+                    }
+                    for stmt in body {
+                        fn_emitter.compile_stmt(stmt)?;
+                    }
+                    fn_emitter.emit(Instruction::op_only(OpCode::RetNone));
+                    self.warnings.append(&mut fn_emitter.warnings);
+                    (fn_emitter.instructions, fn_emitter.next_register)
+                };
+
+                let name_idx = self.builder.add_string_constant("<lambda>") as u32;
+                let func_idx = self.builder.add_function(Function {
+                    name_idx,
+                    num_params: params.len() as u8,
+                    num_registers: fn_num_registers,
+                    instructions: fn_instructions,
+                });
+                let reg = self.alloc_register();
+                self.emit(Instruction::abx(OpCode::MakeFn, reg, func_idx as u16));
+                Ok(reg)
+            }
+        }
+    }
+
+    /// Compile the arguments of a tool call, filling in parameter defaults,
+    /// into consecutive registers. Returns the first register and the count.
+    fn compile_tool_args(
+        &mut self,
+        args: &[Expr],
+        param_defaults: &[Option<u16>],
+    ) -> Result<(u8, u8), AgentusError> {
+        // Compile explicit arguments
+        let mut arg_regs = self.compile_call_args(args, param_defaults.len())?;
+
+        // Fill in defaults for missing arguments
+        for default in param_defaults.iter().skip(arg_regs.len()) {
+            if let Some(default_idx) = *default {
+                let reg = self.alloc_register();
+                self.emit(Instruction::abx(OpCode::LoadConst, reg, default_idx));
+                arg_regs.push(reg);
+            }
+        }
+
+        // Copy into consecutive destination registers
+        let first_arg_reg = self.next_register;
+        for &src_reg in &arg_regs {
+            let dest = self.alloc_register();
+            if src_reg != dest {
+                self.emit(Instruction::abc(OpCode::Move, dest, src_reg, 0));
+            }
+        }
+        Ok((first_arg_reg, arg_regs.len() as u8))
+    }
+
+    /// Compile call arguments, expanding a `...list` spread into one `IndexGet`
+    /// per parameter left to fill so the callee sees consecutive registers.
+    /// The list length is checked at runtime first.
+    fn compile_call_args(&mut self, args: &[Expr], arity: usize) -> Result<Vec<u8>, AgentusError> {
+        let spread_count = args.iter().filter(|a| matches!(a, Expr::Spread(..))).count();
+        if spread_count > 1 {
+            return Err(AgentusError::codegen("only one spread argument is allowed per call"));
+        }
+        let fixed_args = args.len() - spread_count;
+
+        let mut arg_regs = Vec::new();
+        for arg in args {
+            match arg {
+                Expr::Spread(list, _) => {
+                    let list_reg = self.compile_expr(list)?;
+                    let expected = arity.saturating_sub(fixed_args);
+                    self.compile_spread_len_check(list_reg, expected);
+                    for i in 0..expected {
+                        let idx_reg = self.alloc_register();
+                        let idx = self.builder.add_num_constant(i as f64);
+                        self.emit(Instruction::abx(OpCode::LoadConst, idx_reg, idx));
+                        let elem_reg = self.alloc_register();
+                        self.emit(Instruction::abc(OpCode::IndexGet, elem_reg, list_reg, idx_reg));
+                        arg_regs.push(elem_reg);
+                    }
+                }
+                _ => arg_regs.push(self.compile_expr(arg)?),
+            }
         }
+        Ok(arg_regs)
+    }
+
+    /// Emit a check that the spread list in `list_reg` has exactly `expected`
+    /// elements, throwing "spread argument expects N elements, got M" otherwise.
+    fn compile_spread_len_check(&mut self, list_reg: u8, expected: usize) {
+        let len_reg = self.alloc_register();
+        self.emit(Instruction::abc(OpCode::Len, len_reg, list_reg, 0));
+        let expected_reg = self.alloc_register();
+        let expected_idx = self.builder.add_num_constant(expected as f64);
+        self.emit(Instruction::abx(OpCode::LoadConst, expected_reg, expected_idx));
+        let cond_reg = self.alloc_register();
+        self.emit(Instruction::abc(OpCode::Eq, cond_reg, len_reg, expected_reg));
+
+        // JmpTrue => skip the throw if the length matches
+        let jmp_pos = self.current_offset();
+        self.emit(Instruction::asbx(OpCode::JmpTrue, cond_reg, 0)); // placeholder
+
+        let msg_reg = self.alloc_register();
+        let prefix = format!("spread argument expects {} elements, got ", expected);
+        let prefix_idx = self.builder.add_string_constant(&prefix);
+        self.emit(Instruction::abx(OpCode::LoadConst, msg_reg, prefix_idx));
+        self.emit(Instruction::abc(OpCode::Concat, msg_reg, msg_reg, len_reg));
+        self.emit(Instruction::op_a(OpCode::Throw, msg_reg));
+
+        // Patch jump
+        let after = self.current_offset();
+        let offset = (after as i16) - (jmp_pos as i16) - 1;
+        self.instructions[jmp_pos] = Instruction::asbx(OpCode::JmpTrue, cond_reg, offset);
     }
 }
 
 /// Convenience: compile source code directly to a Module.
-pub fn compile(source: &str) -> Result<agentus_ir::module::Module, String> {
-    let program = agentus_parser::parser::parse(source).map_err(|errs| errs.join("; "))?;
-    agentus_sema::resolver::resolve(&program).map_err(|errs| errs.join("; "))?;
+/// Fails with the first error of the earliest failing stage.
+pub fn compile(source: &str) -> Result<agentus_ir::module::Module, AgentusError> {
+    let program = agentus_parser::parser::parse(source).map_err(|errs| parse_error(&errs[0]))?;
+    agentus_sema::resolver::resolve_errors(&program).map_err(|mut errs| errs.remove(0))?;
     Compiler::new().compile(&program)
 }
 
+/// Convenience: evaluate a single expression by compiling it as
+/// `emit <expr>` and running the result, returning the emitted value.
+pub fn compile_expression(source: &str) -> Result<agentus_runtime::value::Value, AgentusError> {
+    use agentus_runtime::vm::{SilentHandler, VM};
+
+    let value = agentus_parser::parser::Parser::parse_expression_only(source)
+        .map_err(|message| parse_error(&message))?;
+    let span = value.span();
+    let program = Program {
+        statements: vec![Stmt::Emit(EmitStmt { value, span })],
+        span,
+    };
+    agentus_sema::resolver::resolve_errors(&program).map_err(|mut errs| errs.remove(0))?;
+    let module = Compiler::new().compile(&program)?;
+    let mut vm = VM::new(module).with_output(Box::new(SilentHandler));
+    vm.run()?;
+    vm.get_outputs()
+        .last()
+        .cloned()
+        .ok_or_else(|| AgentusError::runtime("expression produced no value"))
+}
+
+/// Turn a parser error message into a `ParserError`, keeping its span.
+fn parse_error(message: &str) -> AgentusError {
+    let (message, span) = split_span(message);
+    AgentusError::ParserError { message, span }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1013,6 +1663,28 @@ mod tests {
         assert_eq!(func.instructions[2].opcode(), Some(OpCode::Gt));
     }
 
+    #[test]
+    fn test_agent_override_warning() {
+        let src = "agent A {\n    fn hi() -> str {\n        return \"a\"\n    }\n}\nagent B extends A {\n    fn hi() -> str {\n        return \"b\"\n    }\n}";
+        let program = agentus_parser::parser::parse(src).unwrap();
+        let (module, warnings) = Compiler::new().compile_with_warnings(&program).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("method 'hi' in agent 'B' overrides the method inherited from 'A'"));
+        // B keeps one `hi` entry, pointing at its own function
+        assert_eq!(module.agents[1].methods.len(), 1);
+        assert_ne!(module.agents[1].methods[0].1, module.agents[0].methods[0].1);
+    }
+
+    #[test]
+    fn test_disasm_compiled_function() {
+        let module = compile("let x = \"hi\"\nemit x").unwrap();
+        let listing = module.functions[0].disasm(&module);
+        assert!(listing.starts_with("fn __main__"));
+        assert!(listing.contains("LoadConst r0, K0=\"hi\""));
+        assert!(listing.contains("Emit r0"));
+        assert!(listing.contains("0002  Halt"));
+    }
+
     #[test]
     fn test_compile_bool_literals() {
         let module = compile("let x = true\nlet y = false").unwrap();
@@ -1020,4 +1692,42 @@ mod tests {
         assert_eq!(func.instructions[0].opcode(), Some(OpCode::LoadTrue));
         assert_eq!(func.instructions[1].opcode(), Some(OpCode::LoadFalse));
     }
+
+    #[test]
+    fn test_compile_expression() {
+        assert!(matches!(compile_expression("3 + 4"), Ok(agentus_runtime::value::Value::Num(n)) if n == 7.0));
+        let greeting = compile_expression("\"hello\" ++ \" world\"").unwrap();
+        assert_eq!(greeting.as_str(), Some("hello world"));
+        assert!(compile_expression("1 + ").is_err());
+        assert!(matches!(
+            compile_expression("1 2"),
+            Err(AgentusError::ParserError { message, .. }) if message.contains("expected end of expression")
+        ));
+        assert!(matches!(
+            compile_expression("undefined_name"),
+            Err(AgentusError::SemanticError { message, .. }) if message.contains("undefined variable")
+        ));
+        assert!(matches!(
+            compile_expression("[1].nope()"),
+            Err(AgentusError::RuntimeError { message }) if message == "unknown list method 'nope'"
+        ));
+    }
+
+    #[test]
+    fn test_compile_error_variants() {
+        let err = compile("let = 1").unwrap_err();
+        assert!(matches!(err, AgentusError::ParserError { .. }), "got: {:?}", err);
+        assert_eq!(err.span().map(|span| span.start), Some(4));
+
+        let err = compile("emit missing").unwrap_err();
+        assert!(matches!(err, AgentusError::SemanticError { .. }), "got: {:?}", err);
+        assert_eq!(err.span().map(|span| span.start), Some(5));
+
+        let src = "fn add(a: num, b: num) -> num {\n    return a + b\n}\nlet xs = [1]\nemit add(...xs, ...xs)";
+        let err = compile(src).unwrap_err();
+        assert!(matches!(
+            err,
+            AgentusError::CodegenError { ref message } if message == "only one spread argument is allowed per call"
+        ));
+    }
 }
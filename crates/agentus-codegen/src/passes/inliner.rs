@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use agentus_ir::instruction::Instruction;
+use agentus_ir::module::{Function, Module};
+use agentus_ir::opcode::OpCode;
+use super::{instruction_width, relocate_jumps};
+
+/// Inline calls to small straight-line functions.
+///
+/// A function is inlinable when its body, up to and including its first
+/// `Ret`/`RetNone`, has at most `threshold` instructions and uses only
+/// register-local operations (no calls, jumps, agent memory, or I/O). Each
+/// `Call` to such a function is replaced by moves of the arguments into
+/// fresh caller registers followed by the remapped body. Recursive calls are
+/// never inlined, and callers whose registers or jump offsets would overflow
+/// are left unchanged.
+pub fn inline_small_functions(module: &mut Module, threshold: usize) {
+    let inlinable: HashMap<u32, Function> = module
+        .functions
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, func)| {
+            inlinable_body(func, threshold).map(|instructions| {
+                let body = Function { instructions, ..func.clone() };
+                (idx as u32, body)
+            })
+        })
+        .collect();
+    if inlinable.is_empty() {
+        return;
+    }
+
+    for (idx, func) in module.functions.iter_mut().enumerate() {
+        if let Some(inlined) = inline_calls(func, idx as u32, &inlinable) {
+            *func = inlined;
+        }
+    }
+}
+
+/// The instructions up to the first return, if the function qualifies.
+fn inlinable_body(func: &Function, threshold: usize) -> Option<Vec<Instruction>> {
+    let ret_pos = func
+        .instructions
+        .iter()
+        .position(|inst| matches!(inst.opcode(), Some(OpCode::Ret | OpCode::RetNone)))?;
+    let body = &func.instructions[..=ret_pos];
+    if body.len() > threshold || !body.iter().all(|inst| inst.opcode().is_some_and(is_inlinable_op)) {
+        return None;
+    }
+    Some(body.to_vec())
+}
+
+fn is_inlinable_op(op: OpCode) -> bool {
+    matches!(
+        op,
+        OpCode::LoadConst
+            | OpCode::LoadNone
+            | OpCode::LoadTrue
+            | OpCode::LoadFalse
+            | OpCode::LoadEnv
+            | OpCode::Move
+            | OpCode::Add
+            | OpCode::Sub
+            | OpCode::Mul
+            | OpCode::Div
+            | OpCode::Mod
+            | OpCode::Neg
+            | OpCode::Eq
+            | OpCode::Neq
+            | OpCode::Lt
+            | OpCode::Lte
+            | OpCode::Gt
+            | OpCode::Gte
+            | OpCode::Cmp
+            | OpCode::And
+            | OpCode::Or
+            | OpCode::Not
+            | OpCode::Concat
+            | OpCode::StrLen
+            | OpCode::NewList
+            | OpCode::NewMap
+            | OpCode::IndexGet
+            | OpCode::Len
+            | OpCode::ToJson
+            | OpCode::MakeFn
+            | OpCode::GLoad
+            | OpCode::Ret
+            | OpCode::RetNone
+    )
+}
+
+/// Rewrite `func` with inlined calls, or `None` if nothing was inlined.
+fn inline_calls(func: &Function, func_idx: u32, inlinable: &HashMap<u32, Function>) -> Option<Function> {
+    let old = &func.instructions;
+    let mut new = Vec::with_capacity(old.len());
+    // new_index[i] is the position in `new` of the word at old position `i`
+    let mut new_index = vec![0usize; old.len() + 1];
+    let mut num_registers = func.num_registers as usize;
+    let mut changed = false;
+
+    let mut i = 0;
+    while i < old.len() {
+        let inst = old[i];
+        let w = instruction_width(inst).min(old.len() - i);
+        for k in 0..w {
+            new_index[i + k] = new.len();
+        }
+
+        let callee_idx = inst.bx() as u32;
+        let callee = match inst.opcode() {
+            Some(OpCode::Call) if w == 2 && callee_idx != func_idx => inlinable.get(&callee_idx),
+            _ => None,
+        };
+        match callee {
+            Some(callee) if num_registers + callee.num_registers as usize <= u8::MAX as usize => {
+                let base = num_registers as u8;
+                num_registers += callee.num_registers as usize;
+                let (result_reg, first_arg) = (inst.a(), old[i + 1].b());
+                for p in 0..callee.num_params {
+                    new.push(Instruction::abc(OpCode::Move, base + p, first_arg + p, 0));
+                }
+                for body_inst in &callee.instructions {
+                    new.push(remap_registers(*body_inst, base, result_reg));
+                }
+                changed = true;
+            }
+            _ => new.extend_from_slice(&old[i..i + w]),
+        }
+        i += w;
+    }
+    new_index[old.len()] = new.len();
+
+    if !changed {
+        return None;
+    }
+
+    // Fix relative jump offsets now that instruction positions have moved
+    relocate_jumps(old, &new_index, &mut new)?;
+
+    Some(Function {
+        name_idx: func.name_idx,
+        num_params: func.num_params,
+        num_registers: num_registers as u8,
+        instructions: new,
+    })
+}
+
+/// Shift a callee instruction's registers by `base`; returns become moves
+/// into the caller's result register.
+fn remap_registers(inst: Instruction, base: u8, result_reg: u8) -> Instruction {
+    let (a, b, c) = (inst.a() + base, inst.b().wrapping_add(base), inst.c().wrapping_add(base));
+    let op = inst.opcode().expect("inlinable bodies contain only valid opcodes");
+    match op {
+        OpCode::Ret => Instruction::abc(OpCode::Move, result_reg, a, 0),
+        OpCode::RetNone => Instruction::op_a(OpCode::LoadNone, result_reg),
+        OpCode::LoadNone | OpCode::LoadTrue | OpCode::LoadFalse | OpCode::LoadEnv => {
+            Instruction::op_a(op, a)
+        }
+        OpCode::LoadConst | OpCode::MakeFn | OpCode::GLoad => Instruction::abx(op, a, inst.bx()),
+        OpCode::Move
+        | OpCode::Neg
+        | OpCode::Not
+        | OpCode::StrLen
+        | OpCode::Len
+        | OpCode::ToJson => Instruction::abc(op, a, b, 0),
+        // NewList/NewMap: B is the first register of a contiguous range, C a count
+        OpCode::NewList | OpCode::NewMap => Instruction::abc(op, a, b, inst.c()),
+        _ => Instruction::abc(op, a, b, c),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn function(num_params: u8, num_registers: u8, instructions: Vec<Instruction>) -> Function {
+        Function { name_idx: 0, num_params, num_registers, instructions }
+    }
+
+    #[test]
+    fn test_inline_relocates_jumps() {
+        let mut module = Module::new();
+        // F0: r1 = r0 + r0; return r1
+        module.add_function(function(
+            1,
+            2,
+            vec![
+                Instruction::abc(OpCode::Add, 1, 0, 0),
+                Instruction::op_a(OpCode::Ret, 1),
+                Instruction::op_only(OpCode::RetNone),
+            ],
+        ));
+        // F1: jump over a call to F0, then call it again
+        module.add_function(function(
+            0,
+            2,
+            vec![
+                Instruction::sbx(OpCode::Jmp, 2),
+                Instruction::abx(OpCode::Call, 1, 0),
+                Instruction::abc(OpCode::Nop, 0, 0, 1),
+                Instruction::abx(OpCode::Call, 1, 0),
+                Instruction::abc(OpCode::Nop, 0, 0, 1),
+                Instruction::op_only(OpCode::Halt),
+            ],
+        ));
+
+        inline_small_functions(&mut module, 2);
+        let caller = module.get_function(1).unwrap();
+        assert_eq!(caller.num_registers, 6);
+        // Each call became Move (arg), Add, Move (result)
+        assert_eq!(caller.instructions.len(), 8);
+        assert_eq!(caller.instructions[0].sbx_24(), 3);
+        assert_eq!(caller.instructions[4], Instruction::abc(OpCode::Move, 4, 0, 0));
+        assert_eq!(caller.instructions[5], Instruction::abc(OpCode::Add, 5, 4, 4));
+        assert_eq!(caller.instructions[6], Instruction::abc(OpCode::Move, 1, 5, 0));
+    }
+
+    #[test]
+    fn test_threshold_respected() {
+        let mut module = Module::new();
+        module.add_function(function(
+            0,
+            1,
+            vec![Instruction::op_a(OpCode::LoadTrue, 0), Instruction::op_a(OpCode::Ret, 0)],
+        ));
+        let caller = vec![
+            Instruction::abx(OpCode::Call, 0, 0),
+            Instruction::abc(OpCode::Nop, 0, 0, 0),
+            Instruction::op_only(OpCode::Halt),
+        ];
+        module.add_function(function(0, 1, caller.clone()));
+
+        inline_small_functions(&mut module, 1);
+        assert_eq!(module.get_function(1).unwrap().instructions, caller);
+    }
+
+    #[test]
+    fn test_recursive_call_not_inlined() {
+        let mut module = Module::new();
+        // F0 calls itself; small enough to inline but must stay a call
+        let body = vec![
+            Instruction::abx(OpCode::Call, 0, 0),
+            Instruction::abc(OpCode::Nop, 0, 0, 0),
+            Instruction::op_a(OpCode::Ret, 0),
+        ];
+        module.add_function(function(0, 1, body.clone()));
+
+        inline_small_functions(&mut module, 16);
+        assert_eq!(module.get_function(0).unwrap().instructions, body);
+    }
+}
@@ -0,0 +1,47 @@
+//! Optimization passes over compiled modules.
+
+pub mod inliner;
+
+use agentus_ir::instruction::Instruction;
+use agentus_ir::opcode::OpCode;
+
+/// Number of words an instruction occupies, including trailing data words.
+fn instruction_width(inst: Instruction) -> usize {
+    match inst.opcode() {
+        Some(OpCode::Call) if inst.bx() == 0xFFFE => 3,
+        Some(OpCode::Call | OpCode::TCall | OpCode::PipelineRun | OpCode::IterNext) => 2,
+        _ => 1,
+    }
+}
+
+/// Rewrite the relative jump offsets in `new` after instructions have been
+/// inserted or removed.
+///
+/// `new_index[i]` is the position in `new` of the word at position `i` in
+/// `old`, with one extra entry for the end of the function. Returns `None`
+/// if a relocated offset no longer fits its encoding.
+fn relocate_jumps(old: &[Instruction], new_index: &[usize], new: &mut [Instruction]) -> Option<()> {
+    let mut i = 0;
+    while i < old.len() {
+        let inst = old[i];
+        let w = instruction_width(inst);
+        let pos = new_index[i];
+        let relocate = |offset: i32| -> Option<i32> {
+            let target = (i + w) as i32 + offset;
+            let new_target = *new_index.get(usize::try_from(target).ok()?)? as i32;
+            Some(new_target - (pos + w) as i32)
+        };
+        match inst.opcode() {
+            Some(OpCode::Jmp) => {
+                new[pos] = Instruction::sbx(OpCode::Jmp, relocate(inst.sbx_24())?);
+            }
+            Some(op @ (OpCode::JmpTrue | OpCode::JmpFalse | OpCode::TryBegin | OpCode::IterNext)) => {
+                let offset = i16::try_from(relocate(inst.sbx_16() as i32)?).ok()?;
+                new[pos] = Instruction::asbx(op, inst.a(), offset);
+            }
+            _ => {}
+        }
+        i += w;
+    }
+    Some(())
+}
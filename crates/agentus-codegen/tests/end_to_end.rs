@@ -2,10 +2,19 @@
 //!
 //! These tests exercise the full pipeline (lexer → parser → sema → codegen → runtime).
 
-use agentus_codegen::compiler::compile;
-use agentus_runtime::host::{EchoHost, HostInterface};
+use agentus_codegen::compiler::{compile, CompileOptions, Compiler};
+use agentus_common::errors::AgentusError;
+use agentus_ir::instruction::Instruction;
+use agentus_ir::module::{Constant, Function, Module};
+use agentus_ir::opcode::OpCode;
+use agentus_parser::parser::parse;
+use agentus_runtime::host::{EchoHost, ExecRequest, HostInterface, ToolCallRequest};
+use agentus_runtime::sandbox::{ExecutionBudget, OpCategory};
 use agentus_runtime::value::Value;
-use agentus_runtime::vm::{SilentHandler, VM};
+use agentus_runtime::vm::{OutputHandler, SilentHandler, StepResult, VM};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
 /// Helper: compile source, run VM, return collected outputs as strings.
 fn run(source: &str) -> Vec<String> {
@@ -29,7 +38,7 @@ fn run_error(source: &str, expected: &str) {
     let mut vm = VM::new(module).with_output(Box::new(SilentHandler));
     let result = vm.run();
     assert!(result.is_err(), "expected runtime error, got Ok");
-    let err = result.unwrap_err();
+    let err = result.unwrap_err().to_string();
     assert!(
         err.contains(expected),
         "expected error containing '{}', got: {}",
@@ -42,7 +51,7 @@ fn run_error(source: &str, expected: &str) {
 fn expect_compile_error(source: &str, expected: &str) {
     let result = compile(source);
     assert!(result.is_err(), "expected compile error, got Ok");
-    let err = result.unwrap_err();
+    let err = result.unwrap_err().to_string();
     assert!(
         err.contains(expected),
         "expected error containing '{}', got: {}",
@@ -61,6 +70,20 @@ fn test_hello_world() {
     assert_eq!(out, vec!["Hello Agentus!"]);
 }
 
+#[test]
+fn test_comment_styles() {
+    let src = r#"
+# shell-style comment
+let x = 1 // trailing comment
+/* commented out:
+emit "hidden"
+*/
+emit x /* inline */ + 1
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["2"]);
+}
+
 #[test]
 fn test_number_literal() {
     let out = run("emit 42");
@@ -188,6 +211,37 @@ fn test_and_or() {
     assert_eq!(out, vec!["false", "true"]);
 }
 
+#[test]
+fn test_cmp_builtin() {
+    let src = r#"
+emit cmp(1, 2)
+emit cmp("b", "a")
+emit cmp(none, none)
+emit cmp(3, 3)
+emit cmp(none, 0)
+let words = ["pear", "apple", "fig"]
+words.sort_by(cmp)
+emit words
+let nums = [3, 1, 2]
+nums.sort_by(fn(a, b) cmp(b, a))
+emit nums
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["-1", "1", "0", "0", "-1", "[apple, fig, pear]", "[3, 2, 1]"]);
+    run_error("let xs = [2, 1]\nxs.sort_by(fn(a, b) \"x\")", "comparator must return a number");
+    expect_compile_error("emit cmp(1)", "cmp() takes exactly 2 arguments");
+}
+
+#[test]
+fn test_lambda_capture_is_compile_error() {
+    expect_compile_error(
+        "let step = 1\nemit [1, 2].reduce(0, fn(acc, x) acc + x + step)",
+        "lambdas cannot capture local 'step'",
+    );
+    let out = run("emit [1, 2, 3].reduce(0, fn(acc, x) {\n    let y = x * 2\n    return acc + y\n})");
+    assert_eq!(out, vec!["12"]);
+}
+
 #[test]
 fn test_not() {
     let out = run("emit not true\nemit not false");
@@ -455,6 +509,22 @@ fn test_no_interpolation() {
     assert_eq!(out, vec!["plain string"]);
 }
 
+#[test]
+fn test_interpolation_json_format() {
+    let src = r#"
+let list_val = [1, "two", true]
+emit "result: {list_val:json}"
+emit "plain: {list_val}"
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["result: [1, \"two\", true]", "plain: [1, two, true]"]);
+}
+
+#[test]
+fn test_interpolation_unknown_format_error() {
+    expect_compile_error("let x = 1\nemit \"{x:yaml}\"", "unknown format specifier 'yaml'");
+}
+
 // ===================================================================
 // Variable assignment
 // ===================================================================
@@ -554,6 +624,113 @@ emit abs(-3)
     assert_eq!(out, vec!["5", "3"]);
 }
 
+#[test]
+fn test_function_spread_args() {
+    let src = r#"
+fn add3(a: num, b: num, c: num) -> num {
+    return a + b + c
+}
+emit add3(...[1, 2, 3])
+let rest = [20, 30]
+emit add3(10, ...rest)
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["6", "60"]);
+}
+
+#[test]
+fn test_tool_spread_args() {
+    let src = r#"
+tool add {
+    param a: num
+    param b: num
+}
+let nums = [1, 2]
+emit add(...nums)
+"#;
+    let out = run_with_host(src, Box::new(EchoHost));
+    assert_eq!(out, vec!["add(a=1, b=2)"]);
+}
+
+#[test]
+fn test_spread_multiple_error() {
+    let src = r#"
+fn add(a: num, b: num) -> num {
+    return a + b
+}
+let xs = [1]
+emit add(...xs, ...xs)
+"#;
+    expect_compile_error(src, "only one spread argument");
+}
+
+#[test]
+fn test_spread_short_list_error() {
+    let src = r#"
+fn add3(a: num, b: num, c: num) -> num {
+    return a + b + c
+}
+emit add3(...[1, 2])
+"#;
+    run_error(src, "spread argument expects 3 elements, got 2");
+}
+
+#[test]
+fn test_spread_long_list_error() {
+    let src = r#"
+fn add3(a: num, b: num, c: num) -> num {
+    return a + b + c
+}
+let rest = [2, 3, 4]
+try {
+    emit add3(1, ...rest)
+} catch e {
+    emit e
+}
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["spread argument expects 2 elements, got 3"]);
+}
+
+#[test]
+fn test_call_in_if_resumes_once() {
+    // The statement after a call must execute exactly once after Ret.
+    let src = r#"
+fn double(x: num) -> num {
+    return x * 2
+}
+let n = 0
+if true {
+    let d = double(4)
+    n = n + 1
+    emit d
+}
+emit n
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["8", "1"]);
+}
+
+#[test]
+fn test_method_call_in_if_resumes_once() {
+    let src = r#"
+agent Echo {
+    fn say(msg: str) -> str {
+        return msg
+    }
+}
+let e = Echo()
+let n = 0
+if n == 0 {
+    emit e.say("hi")
+    n = n + 1
+}
+emit n
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["hi", "1"]);
+}
+
 // ===================================================================
 // Error cases
 // ===================================================================
@@ -760,6 +937,175 @@ fn test_undefined_tool_error() {
     );
 }
 
+/// Host whose tools report progress through the request's log callback.
+struct LoggingToolHost;
+
+impl HostInterface for LoggingToolHost {
+    fn exec(&self, request: ExecRequest) -> Result<String, String> {
+        Ok(request.user_prompt)
+    }
+
+    fn tool_call(&self, request: ToolCallRequest<'_>) -> Result<Value, String> {
+        request.log(2, &format!("starting {}", request.tool_name));
+        request.log(1, "halfway");
+        Ok(Value::from_str("done"))
+    }
+}
+
+/// Host whose only tool returns binary data.
+struct BytesToolHost;
+
+impl HostInterface for BytesToolHost {
+    fn exec(&self, request: ExecRequest) -> Result<String, String> {
+        Ok(request.user_prompt)
+    }
+
+    fn tool_call(&self, _request: ToolCallRequest<'_>) -> Result<Value, String> {
+        Ok(Value::from_bytes(vec![0x89, b'P', b'N', b'G']))
+    }
+}
+
+#[test]
+fn test_tool_returns_bytes() {
+    let src = r#"
+tool screenshot {
+    description { "Capture the screen" }
+}
+let image = screenshot()
+emit image
+emit len(image)
+emit to_base64(image)
+"#;
+    let out = run_with_host(src, Box::new(BytesToolHost));
+    assert_eq!(out, vec!["<bytes:4>", "4", "iVBORw=="]);
+}
+
+/// Host that records the size of each tool batch it receives.
+struct BatchRecordingHost {
+    batches: Rc<RefCell<Vec<usize>>>,
+}
+
+impl HostInterface for BatchRecordingHost {
+    fn exec(&self, request: ExecRequest) -> Result<String, String> {
+        Ok(request.user_prompt)
+    }
+
+    fn tool_call(&self, request: ToolCallRequest<'_>) -> Result<Value, String> {
+        EchoHost.tool_call(request)
+    }
+
+    fn batch_tool_call(&self, requests: Vec<ToolCallRequest<'_>>) -> Result<Vec<Value>, String> {
+        self.batches.borrow_mut().push(requests.len());
+        requests.into_iter().map(|request| self.tool_call(request)).collect()
+    }
+}
+
+#[test]
+fn test_batch_tool_calls() {
+    let src = r#"
+tool search {
+    param query: str
+}
+tool fetch {
+    param url: str
+    param retries: num = 3
+}
+let results = batch {
+    search("cats"),
+    fetch("a.com"),
+    search("dogs")
+}
+emit results
+emit len(batch { })
+"#;
+    let batches = Rc::new(RefCell::new(Vec::new()));
+    let host = BatchRecordingHost { batches: batches.clone() };
+    let out = run_with_host(src, Box::new(host));
+    assert_eq!(
+        out,
+        vec!["[search(query=cats), fetch(url=a.com, retries=3), search(query=dogs)]", "0"]
+    );
+    assert_eq!(*batches.borrow(), vec![3, 0]);
+
+    // The default implementation dispatches each request in turn
+    let out = run_with_host("tool ping {\n    param host: str\n}\nemit batch { ping(\"a\"), ping(\"b\") }", Box::new(EchoHost));
+    assert_eq!(out, vec!["[ping(host=a), ping(host=b)]"]);
+    expect_compile_error("emit batch { len(\"x\") }", "batch entries must be tool calls");
+    expect_compile_error(
+        "tool ping {\n    param host: str\n}\nemit batch { ping(\"a\") ping(\"b\") }",
+        "expected RBrace, found Ident",
+    );
+    expect_compile_error(
+        "tool ping {\n    param host: str\n}\nemit batch {\n    ping(\"a\")\n    ping(\"b\")\n}",
+        "expected RBrace, found Ident",
+    );
+}
+
+#[test]
+fn test_base64_round_trip() {
+    let src = r#"
+let data = from_base64("AAEC/w==")
+emit data
+emit len(data)
+emit to_base64(data)
+emit to_json(data)
+emit to_base64(from_base64("aGVsbG8gd29ybGQ=")) == "aGVsbG8gd29ybGQ="
+emit len(from_base64(""))
+try {
+    from_base64("abc")
+} catch e {
+    emit e
+}
+"#;
+    let out = run(src);
+    assert_eq!(
+        out,
+        vec![
+            "<bytes:4>",
+            "4",
+            "AAEC/w==",
+            "\"AAEC/w==\"",
+            "true",
+            "0",
+            "<error: from_base64 error: invalid base64: length is not a multiple of 4>",
+        ]
+    );
+    run_error("emit to_base64(\"text\")", "to_base64() requires bytes");
+}
+
+/// Output handler that records emits and logs in the order they arrive.
+struct RecordingHandler(Rc<RefCell<Vec<String>>>);
+
+impl OutputHandler for RecordingHandler {
+    fn on_emit(&self, value: &Value) {
+        self.0.borrow_mut().push(format!("emit: {}", value));
+    }
+
+    fn on_log(&self, level: u8, message: &str) {
+        self.0.borrow_mut().push(format!("log{}: {}", level, message));
+    }
+}
+
+#[test]
+fn test_tool_streams_log_output() {
+    let src = r#"
+tool build {
+    param target: str
+}
+emit build("release")
+"#;
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let module = compile(src).unwrap();
+    let mut vm = VM::new(module)
+        .with_output(Box::new(RecordingHandler(events.clone())))
+        .with_host(Box::new(LoggingToolHost));
+    vm.run().unwrap();
+    assert_eq!(
+        *events.borrow(),
+        vec!["log2: starting build", "log1: halfway", "emit: done"]
+    );
+}
+
 // ===================================================================
 // Agent core tests
 // ===================================================================
@@ -815,26 +1161,53 @@ emit c.increment()
 }
 
 #[test]
-fn test_exec_block_echo() {
+fn test_agent_extends() {
     let src = r#"
-let result = exec { "What is 2+2?" }
-emit result
+agent Base {
+    memory {
+        count: num = 10
+        label: str = "base"
+    }
+
+    fn get_count() -> num {
+        return self.count
+    }
+
+    fn describe() -> str {
+        return "I am " ++ self.label
+    }
+}
+agent Child extends Base {
+    memory {
+        label: str = "child"
+    }
+
+    fn describe() -> str {
+        return "Child of " ++ self.label
+    }
+}
+let c = Child()
+emit c.get_count()
+emit c.describe()
+let b = Base()
+emit b.describe()
 "#;
     let out = run_with_host(src, Box::new(EchoHost));
-    assert_eq!(out, vec!["What is 2+2?"]);
+    assert_eq!(out, vec!["10", "Child of child", "I am base"]);
 }
 
 #[test]
-fn test_exec_block_inline() {
+fn test_agent_extends_undefined_parent() {
     let src = r#"
-emit exec { "hello from exec" }
+agent Child extends Missing {
+    model = "gpt-4o"
+}
 "#;
-    let out = run_with_host(src, Box::new(EchoHost));
-    assert_eq!(out, vec!["hello from exec"]);
+    expect_compile_error(src, "undefined parent agent 'Missing'");
 }
 
 #[test]
-fn test_multiple_agent_instances() {
+fn test_agent_memory_inspection() {
     let src = r#"
 agent Counter {
     memory {
@@ -846,76 +1219,516 @@ agent Counter {
         return self.count
     }
 }
-let a = Counter()
-let b = Counter()
-emit a.increment()
-emit a.increment()
-emit b.increment()
-emit a.increment()
-emit b.increment()
+let c = Counter()
+c.increment()
+c.increment()
+c.increment()
 "#;
-    let out = run_with_host(src, Box::new(EchoHost));
-    assert_eq!(out, vec!["1", "2", "1", "3", "2"]);
+    let module = compile(src).unwrap_or_else(|e| panic!("compile error: {}", e));
+    let mut vm = VM::new(module).with_output(Box::new(SilentHandler));
+    vm.run().unwrap_or_else(|e| panic!("runtime error: {}", e));
+
+    assert_eq!(vm.get_agent_ids(), vec![1]);
+    assert_eq!(vm.get_agent_memory(1).unwrap()["count"], Value::Num(3.0));
+    assert!(vm.get_agent_memory(2).is_none());
+
+    vm.get_agent_memory_mut(1)
+        .unwrap()
+        .insert("count".to_string(), Value::Num(10.0));
+    assert_eq!(vm.get_agent_memory(1).unwrap()["count"], Value::Num(10.0));
 }
 
 #[test]
-fn test_agent_with_model_and_prompt() {
+fn test_spawn_agent_and_set_memory() {
     let src = r#"
-agent Assistant {
-    model = "gpt-4o"
-    system prompt { "You are helpful." }
-
+agent Counter {
     memory {
         count: num = 0
+        step: num = 1
     }
 
-    fn get_count() -> num {
+    fn increment() -> num {
+        self.count = self.count + self.step
         return self.count
     }
 }
-let a = Assistant()
-emit a.get_count()
 "#;
-    let out = run_with_host(src, Box::new(EchoHost));
-    assert_eq!(out, vec!["0"]);
+    let module = compile(src).unwrap_or_else(|e| panic!("compile error: {}", e));
+    let mut vm = VM::new(module).with_output(Box::new(SilentHandler));
+
+    let id = vm.spawn_agent("Counter").unwrap();
+    vm.set_agent_memory(id, "count", Value::Num(1.0)).unwrap();
+    assert_eq!(vm.call_method(id, "increment", &[]).unwrap(), Value::Num(2.0));
+
+    let fields = HashMap::from([
+        ("count".to_string(), Value::Num(10.0)),
+        ("step".to_string(), Value::Num(5.0)),
+    ]);
+    vm.set_agent_memory_fields(id, fields).unwrap();
+    assert_eq!(vm.call_method(id, "increment", &[]).unwrap(), Value::Num(15.0));
+
+    assert!(vm.spawn_agent("Missing").unwrap_err().to_string().contains("undefined agent 'Missing'"));
+    assert!(vm.set_agent_memory(99, "count", Value::None).is_err());
+    let err = vm.call_method(id, "increment", &[Value::Num(1.0)]).unwrap_err();
+    assert!(err.to_string().contains("method 'increment' expects 0 arguments, got 1"));
 }
 
 #[test]
-fn test_agent_memory_default_string() {
+fn test_agent_lifecycle_hooks() {
     let src = r#"
-agent Bot {
+agent Worker {
     memory {
-        name: str = "unnamed"
+        starts: num = 0
     }
 
-    fn get_name() -> str {
-        return self.name
+    fn on_spawn() {
+        self.starts = self.starts + 1
+        emit "spawned"
+    }
+
+    fn on_kill() {
+        emit "killed after {self.starts} start"
+    }
+
+    fn starts() -> num {
+        return self.starts
     }
 }
-let b = Bot()
-emit b.get_name()
+let w = Worker()
+emit w.starts()
+kill w
+emit "done"
 "#;
-    let out = run_with_host(src, Box::new(EchoHost));
-    assert_eq!(out, vec!["unnamed"]);
+    let out = run(src);
+    assert_eq!(out, vec!["spawned", "1", "killed after 1 start", "done"]);
+
+    run_error(
+        "agent A {\n    fn ping() -> str {\n        return \"pong\"\n    }\n}\nlet a = A()\nkill a\nemit a.ping()",
+        "not found",
+    );
+    run_error("kill 5", "kill target is not an agent handle");
 }
 
 #[test]
-fn test_agent_method_no_params() {
+fn test_lifecycle_hook_throw_is_caught() {
     let src = r#"
-agent Ping {
-    fn pong() -> str {
-        return "pong"
+agent Fragile {
+    fn on_spawn() {
+        throw "spawn failed"
     }
 }
-let p = Ping()
-emit p.pong()
+agent Stubborn {
+    fn on_kill() {
+        throw "kill refused"
+    }
+}
+try {
+    let f = Fragile()
+} catch e {
+    emit "caught: " ++ e
+}
+let s = Stubborn()
+try {
+    kill s
+} catch e {
+    emit "caught: " ++ e
+}
 "#;
-    let out = run_with_host(src, Box::new(EchoHost));
-    assert_eq!(out, vec!["pong"]);
+    let module = compile(src).unwrap_or_else(|e| panic!("compile error: {}", e));
+    let mut vm = VM::new(module).with_output(Box::new(SilentHandler));
+    vm.run().unwrap_or_else(|e| panic!("runtime error: {}", e));
+    let out: Vec<String> = vm.get_outputs().iter().map(|v| v.to_string()).collect();
+    assert_eq!(out, vec!["caught: spawn failed", "caught: kill refused"]);
+    // The Fragile instance is discarded; Stubborn refused to die
+    assert_eq!(vm.get_agent_ids(), vec![2]);
 }
 
 #[test]
-fn test_agent_full_example() {
+fn test_lifecycle_hook_with_params_rejected() {
+    expect_compile_error(
+        "agent A {\n    fn on_spawn(x: num) {\n        emit x\n    }\n}",
+        "lifecycle hook 'on_spawn' cannot take parameters",
+    );
+    expect_compile_error(
+        "agent A {\n    fn on_kill(reason: str) {\n        emit reason\n    }\n}",
+        "lifecycle hook 'on_kill' cannot take parameters",
+    );
+}
+
+#[test]
+fn test_global_memory_shared_between_agents() {
+    let src = r#"
+global {
+    counter: num = 0
+    visits: list[str] = []
+}
+
+agent Worker {
+    memory {
+        name: str = "worker"
+    }
+
+    fn bump(by: num) -> num {
+        counter = counter + by
+        visits.push(self.name)
+        return counter
+    }
+}
+
+fn peek() -> num {
+    return counter
+}
+
+let a = Worker()
+let b = Worker()
+emit a.bump(1)
+emit b.bump(10)
+emit a.bump(100)
+emit counter
+emit peek()
+emit len(visits)
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["1", "11", "111", "111", "111", "3"]);
+}
+
+#[test]
+fn test_global_declaration_must_be_top_level() {
+    expect_compile_error(
+        "fn f() {\n    global {\n        x: num = 1\n    }\n}",
+        "'global' declarations are only allowed at the top level",
+    );
+}
+
+#[test]
+fn test_exec_block_echo() {
+    let src = r#"
+let result = exec { "What is 2+2?" }
+emit result
+"#;
+    let out = run_with_host(src, Box::new(EchoHost));
+    assert_eq!(out, vec!["What is 2+2?"]);
+}
+
+#[test]
+fn test_exec_block_inline() {
+    let src = r#"
+emit exec { "hello from exec" }
+"#;
+    let out = run_with_host(src, Box::new(EchoHost));
+    assert_eq!(out, vec!["hello from exec"]);
+}
+
+#[test]
+fn test_sandboxed_vm() {
+    let sandboxed = |src: &str, budget: ExecutionBudget| {
+        let module = compile(src).unwrap_or_else(|e| panic!("compile error: {}", e));
+        let mut vm = VM::new_sandboxed(module, budget)
+            .with_host(Box::new(EchoHost))
+            .with_output(Box::new(SilentHandler));
+        vm.run().map(|_| vm.get_outputs().iter().map(|v| v.to_string()).collect::<Vec<_>>())
+    };
+    let violation = |err: AgentusError| match err {
+        AgentusError::Sandboxed { message } => message,
+        other => panic!("expected a sandbox violation, got: {}", other),
+    };
+
+    let err = sandboxed("emit exec { \"hi\" }", ExecutionBudget::default()).unwrap_err();
+    assert_eq!(violation(err), "opcode Exec is not allowed");
+
+    let allowed = ExecutionBudget {
+        allowed_categories: HashSet::from([OpCategory::Exec]),
+        ..ExecutionBudget::default()
+    };
+    assert_eq!(sandboxed("emit exec { \"hi\" }", allowed).unwrap(), vec!["hi"]);
+
+    let limited = ExecutionBudget { max_instructions: Some(100), ..ExecutionBudget::default() };
+    let err = sandboxed("let i = 0\nwhile true {\n    i = i + 1\n}", limited.clone()).unwrap_err();
+    assert_eq!(violation(err), "instruction limit of 100 exceeded");
+
+    // A compliant program is unaffected by the sandbox, and errors cannot be caught
+    assert_eq!(sandboxed("emit [1, 2].len() * 2", limited).unwrap(), vec!["4"]);
+    let err = sandboxed(
+        "try {\n    emit exec { \"hi\" }\n} catch e {\n    emit \"caught\"\n}",
+        ExecutionBudget::default(),
+    )
+    .unwrap_err();
+    violation(err);
+
+    let shallow = ExecutionBudget { max_call_depth: Some(2), ..ExecutionBudget::default() };
+    let src = "fn b() -> num {\n    return 1\n}\nfn a() -> num {\n    return b()\n}\nemit a()";
+    let err = sandboxed(src, shallow).unwrap_err();
+    assert_eq!(violation(err), "call depth exceeds limit of 2");
+
+    // Callbacks run on the same stack, so recursing through one is still limited
+    let limited_depth = ExecutionBudget { max_call_depth: Some(20), ..ExecutionBudget::default() };
+    let src = "fn f(n: num) -> num {\n    return [n].reduce(0, fn(acc, x) f(x + 1))\n}\nemit f(0)";
+    let err = sandboxed(src, limited_depth).unwrap_err();
+    assert_eq!(violation(err), "call depth exceeds limit of 20");
+
+    let small_heap = ExecutionBudget { max_heap_bytes: Some(4096), ..ExecutionBudget::default() };
+    let src = "let s = \"x\".repeat(1000)\nlet t = s ++ s ++ s ++ s ++ s\nlet i = 0\nwhile i < 100 {\n    i = i + 1\n}";
+    let err = sandboxed(src, small_heap.clone()).unwrap_err();
+    assert!(violation(err).contains("heap usage"));
+
+    // Built-ins sized by an argument are stopped before they allocate
+    let err = sandboxed("let s = \"ab\".repeat(1000000000000000)", small_heap.clone()).unwrap_err();
+    assert_eq!(violation(err), "allocation of 2000000000000000 bytes exceeds heap limit of 4096");
+    let err = sandboxed("let s = \"a\".pad_start(1000000000000000)", small_heap.clone()).unwrap_err();
+    assert!(violation(err).starts_with("allocation of"));
+    let err = sandboxed("let s = (1).to_fixed(1000000000000000)", small_heap.clone()).unwrap_err();
+    assert!(violation(err).starts_with("allocation of"));
+    assert_eq!(sandboxed("emit \"ab\".repeat(3)", small_heap).unwrap(), vec!["ababab"]);
+}
+
+#[test]
+fn test_call_stack_limit() {
+    let src = r#"
+fn down(n: num) -> num {
+    if n == 0 {
+        return 0
+    }
+    return down(n - 1)
+}
+emit down(depth)
+"#;
+    // The entry frame plus 999 frames of `down` fits the default limit
+    let module = compile(&src.replace("depth", "998")).unwrap();
+    let mut vm = VM::new(module).with_output(Box::new(SilentHandler));
+    vm.run().unwrap();
+    assert_eq!(vm.get_outputs()[0].as_num(), Some(0.0));
+
+    run_error(&src.replace("depth", "999"), "call stack depth exceeded");
+    run_error(&src.replace("depth", "100000"), "call stack depth exceeded");
+
+    let module = compile(&src.replace("depth", "10")).unwrap();
+    let mut vm = VM::new(module)
+        .with_output(Box::new(SilentHandler))
+        .with_call_stack_limit(10);
+    let err = vm.run().unwrap_err();
+    assert!(err.to_string().contains("call stack depth exceeded"), "got: {}", err);
+}
+
+#[test]
+fn test_call_stack_limit_through_callbacks() {
+    // Recursing through a callback must hit the limit instead of
+    // overflowing the Rust stack
+    let src = r#"
+fn f(n: num) -> num {
+    return [n].reduce(0, fn(acc, x) f(x + 1))
+}
+emit f(0)
+"#;
+    run_error(src, "call stack depth exceeded");
+    let src = r#"
+fn g(n: num) -> num {
+    [n, n].sort_by(fn(a, b) g(a + 1))
+    return n
+}
+emit g(0)
+"#;
+    run_error(src, "call stack depth exceeded");
+
+    // Callback frames count toward the frame limit
+    let src = r#"
+fn h(n: num) -> num {
+    if n == 0 {
+        return 0
+    }
+    return [n].reduce(0, fn(acc, x) h(x - 1))
+}
+emit h(depth)
+"#;
+    let module = compile(&src.replace("depth", "4")).unwrap();
+    let mut vm = VM::new(module)
+        .with_output(Box::new(SilentHandler))
+        .with_call_stack_limit(10);
+    vm.run().unwrap();
+    let module = compile(&src.replace("depth", "5")).unwrap();
+    let mut vm = VM::new(module)
+        .with_output(Box::new(SilentHandler))
+        .with_call_stack_limit(10);
+    let err = vm.run().unwrap_err();
+    assert!(err.to_string().contains("call stack depth exceeded"), "got: {}", err);
+}
+
+#[test]
+fn test_call_stack_limit_counts_suspended_program() {
+    let src = r#"
+fn down(n: num) -> num {
+    if n == 0 {
+        return 0
+    }
+    return down(n - 1)
+}
+emit down(55)
+"#;
+    let mut vm = VM::new(compile(src).unwrap())
+        .with_output(Box::new(SilentHandler))
+        .with_call_stack_limit(60);
+    assert!(vm.call_function("down", &[Value::Num(40.0)]).is_ok());
+
+    // Pause the program partway down its recursion
+    for _ in 0..200 {
+        assert_eq!(vm.step().unwrap(), StepResult::Continue);
+    }
+    let err = vm.call_function("down", &[Value::Num(40.0)]).unwrap_err();
+    assert!(err.to_string().contains("call stack depth exceeded"), "got: {}", err);
+}
+
+#[test]
+fn test_multiple_agent_instances() {
+    let src = r#"
+agent Counter {
+    memory {
+        count: num = 0
+    }
+
+    fn increment() -> num {
+        self.count = self.count + 1
+        return self.count
+    }
+}
+let a = Counter()
+let b = Counter()
+emit a.increment()
+emit a.increment()
+emit b.increment()
+emit a.increment()
+emit b.increment()
+"#;
+    let out = run_with_host(src, Box::new(EchoHost));
+    assert_eq!(out, vec!["1", "2", "1", "3", "2"]);
+}
+
+#[test]
+fn test_agent_method_calls_self_method() {
+    let src = r#"
+agent A {
+    memory {
+        base: num = 40
+    }
+    fn a() -> num {
+        return self.b()
+    }
+    fn b() -> num {
+        return 42
+    }
+    fn add(n: num) -> num {
+        return self.base + n
+    }
+    fn twice(n: num) -> num {
+        return self.add(n) + self.add(n)
+    }
+}
+let x = A()
+emit x.a()
+emit x.twice(1)
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["42", "82"]);
+}
+
+#[test]
+fn test_agent_method_arity_mismatch() {
+    let agent = "agent A {\n    fn add(n: num) -> num {\n        return n + 1\n    }\n    fn bad() -> num {\n        return self.add()\n    }\n}\nlet a = A()\n";
+    run_error(&format!("{}emit a.add(1, 2)", agent), "method 'add' expects 1 arguments, got 2");
+    run_error(&format!("{}emit a.add()", agent), "method 'add' expects 1 arguments, got 0");
+    run_error(&format!("{}emit a.bad()", agent), "method 'add' expects 1 arguments, got 0");
+}
+
+#[test]
+fn test_agent_with_model_and_prompt() {
+    let src = r#"
+agent Assistant {
+    model = "gpt-4o"
+    system prompt { "You are helpful." }
+
+    memory {
+        count: num = 0
+    }
+
+    fn get_count() -> num {
+        return self.count
+    }
+}
+let a = Assistant()
+emit a.get_count()
+"#;
+    let out = run_with_host(src, Box::new(EchoHost));
+    assert_eq!(out, vec!["0"]);
+}
+
+#[test]
+fn test_agent_memory_default_string() {
+    let src = r#"
+agent Bot {
+    memory {
+        name: str = "unnamed"
+    }
+
+    fn get_name() -> str {
+        return self.name
+    }
+}
+let b = Bot()
+emit b.get_name()
+"#;
+    let out = run_with_host(src, Box::new(EchoHost));
+    assert_eq!(out, vec!["unnamed"]);
+}
+
+#[test]
+fn test_agent_method_no_params() {
+    let src = r#"
+agent Ping {
+    fn pong() -> str {
+        return "pong"
+    }
+}
+let p = Ping()
+emit p.pong()
+"#;
+    let out = run_with_host(src, Box::new(EchoHost));
+    assert_eq!(out, vec!["pong"]);
+}
+
+#[test]
+fn test_agent_method_yield() {
+    let src = r#"
+agent Gen {
+    fn next() -> num {
+        yield 1
+        yield 2
+        let i = 3
+        while i < 5 {
+            yield i
+            i = i + 1
+        }
+        return 0
+    }
+}
+let g = Gen()
+let other = Gen()
+emit g.next()
+emit g.next()
+emit other.next()
+emit g.next()
+emit g.next()
+emit g.next()
+emit g.next()
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["1", "2", "1", "3", "4", "0", "1"]);
+}
+
+#[test]
+fn test_yield_outside_method_error() {
+    expect_compile_error("fn f() -> num {\n    yield 1\n}", "only allowed inside agent methods");
+}
+
+#[test]
+fn test_agent_full_example() {
     let src = r#"
 agent Counter {
     model = "gpt-4o"
@@ -1014,6 +1827,33 @@ emit recv b
     assert_eq!(out, vec!["for-a", "for-b"]);
 }
 
+#[test]
+fn test_send_self() {
+    let src = r#"
+agent Worker {
+    memory { }
+    fn schedule(task: str) {
+        send self, task
+    }
+    fn next() -> str {
+        return recv self
+    }
+    fn round_trip() -> str {
+        send self, "next_task"
+        return recv self
+    }
+}
+let w = Worker()
+emit w.round_trip()
+w.schedule("later")
+emit w.next()
+emit recv w
+send w, "from outside"
+emit w.next()
+"#;
+    assert_eq!(run(src), vec!["next_task", "later", "none", "from outside"]);
+}
+
 #[test]
 fn test_send_different_types() {
     let src = r#"
@@ -1103,116 +1943,576 @@ emit m.len()
 #[test]
 fn test_map_method_contains() {
     let src = r#"
-let m = {"name": "Alice"}
-emit m.contains("name")
-emit m.contains("age")
+let m = {"name": "Alice"}
+emit m.contains("name")
+emit m.contains("age")
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["true", "false"]);
+}
+
+#[test]
+fn test_map_method_remove() {
+    let src = r#"
+let m = {"a": 1, "b": 2}
+let removed = m.remove("a")
+emit removed
+emit len(m)
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["1", "1"]);
+}
+
+#[test]
+fn test_map_method_keys() {
+    let src = r#"
+let m = {"x": 10}
+let ks = m.keys()
+emit len(ks)
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["1"]);
+}
+
+#[test]
+fn test_map_method_values() {
+    let src = r#"
+let m = {"x": 10}
+let vs = m.values()
+emit len(vs)
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["1"]);
+}
+
+#[test]
+fn test_map_get_and_get_or() {
+    let src = r#"
+let m = {"present": 42}
+emit m.get("missing") == none
+emit m.get("present")
+emit m.get_or("missing", "fallback")
+emit m.get_or("present", "fallback")
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["true", "42", "fallback", "42"]);
+}
+
+#[test]
+fn test_map_list_pair_conversions() {
+    let src = r#"
+emit {"b": 2, "a": 1}.to_list_of_pairs()
+emit {"a": 1}.to_list_of_pairs().to_map(fn(pair) pair[0]) == {"a": ["a", 1]}
+let users = [{"id": "u1", "name": "Ann"}, {"id": "u2", "name": "Bo"}]
+let by_id = users.to_map("id")
+emit by_id["u2"]["name"]
+emit [1, 2, 3].to_map(fn(n) n % 2)
+"#;
+    let out = run(src);
+    assert_eq!(out[0], "[[a, 1], [b, 2]]");
+    assert_eq!(out[1], "true");
+    assert_eq!(out[2], "Bo");
+    // Later items win on duplicate keys
+    let values = run_values(src);
+    match &values[3] {
+        Value::Map(m) => {
+            assert_eq!(m.borrow().get("1"), Some(&Value::Num(3.0)));
+            assert_eq!(m.borrow().get("0"), Some(&Value::Num(2.0)));
+        }
+        other => panic!("expected map, got {:?}", other),
+    }
+    run_error("emit [1].to_map(\"id\")", "list.to_map() cannot read field 'id' of 1");
+}
+
+#[test]
+fn test_list_group_by() {
+    let src = r#"
+let items = [{"t": "a", "n": 1}, {"t": "b", "n": 2}, {"t": "a", "n": 3}]
+emit items.group_by("t") == {"a": [{"t": "a", "n": 1}, {"t": "a", "n": 3}], "b": [{"t": "b", "n": 2}]}
+emit [1, 2, 3, 4, 5].group_by(fn(n) n % 2 == 0) == {"false": [1, 3, 5], "true": [2, 4]}
+emit [{"x": 1}].group_by("t") == {"none": [{"x": 1}]}
+emit [].group_by("t").len()
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["true", "true", "true", "0"]);
+    run_error("emit [1].group_by(\"t\")", "list.group_by() cannot read field 't' of 1");
+}
+
+#[test]
+fn test_map_transform_values_and_keys() {
+    let src = r#"
+let prices = {"APPLE": 2, "PEAR": 3}
+let lowered = prices.transform_keys(fn(k) k.lower())
+emit lowered["apple"]
+emit lowered["pear"]
+emit lowered.contains("APPLE")
+let doubled = prices.transform_values(fn(v) v * 2)
+emit doubled["APPLE"]
+emit doubled["PEAR"]
+emit prices["PEAR"]
+let merged = {"a": 1, "b": 2}.transform_keys(fn(k) "same")
+emit merged["same"]
+emit len(merged)
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["2", "3", "false", "4", "6", "3", "2", "1"]);
+}
+
+#[test]
+fn test_map_for_iteration() {
+    let src = r#"
+let m = {"only": 42}
+for key in m {
+    emit key
+    emit m[key]
+}
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["only", "42"]);
+}
+
+#[test]
+fn test_list_push() {
+    let src = r#"
+let items = [1, 2, 3]
+items.push(4)
+items.push(5)
+emit len(items)
+emit items[3]
+emit items[4]
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["5", "4", "5"]);
+}
+
+#[test]
+fn test_list_index_set() {
+    let src = r#"
+let items = [10, 20, 30]
+items[1] = 99
+emit items[0]
+emit items[1]
+emit items[2]
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["10", "99", "30"]);
+}
+
+#[test]
+fn test_list_len() {
+    let src = r#"
+let items = [1, 2, 3, 4]
+emit len(items)
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["4"]);
+}
+
+#[test]
+fn test_list_sort_mixed_types() {
+    let src = r#"
+let items = ["pear", 3, true, none, "apple", -1, false]
+items.sort()
+emit items
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["[none, false, true, -1, 3, apple, pear]"]);
+}
+
+#[test]
+fn test_list_unique_and_flatten() {
+    let src = r#"
+emit [1, 2, 1, 3, 2].unique()
+emit [[1, 2], [1, 2], [3]].unique()
+emit [[1, 2], [3, 4]].flatten()
+emit [[1], 2, [3]].flatten()
+emit [[1, [2]]].flatten()
+emit [1, 2] == [1, 2]
+emit {"a": [1]} == {"a": [1]}
+"#;
+    let out = run(src);
+    assert_eq!(
+        out,
+        vec!["[1, 2, 3]", "[[1, 2], [3]]", "[1, 2, 3, 4]", "[1, 2, 3]", "[1, [2]]", "true", "true"]
+    );
+}
+
+#[test]
+fn test_list_zip_and_enumerate() {
+    let src = r#"
+emit [1, 2, 3].zip(["a", "b", "c"]) == [[1, "a"], [2, "b"], [3, "c"]]
+emit [1, 2, 3].zip(["a"])
+emit [].zip([1])
+emit ["x", "y"].enumerate() == [[0, "x"], [1, "y"]]
+for pair in ["p", "q"].enumerate() {
+    emit "{pair[0]}:{pair[1]}"
+}
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["true", "[[1, a]]", "[]", "true", "0:p", "1:q"]);
+    run_error("emit [1].zip(2)", "list.zip() requires a list");
+}
+
+#[test]
+fn test_list_chunk() {
+    let src = r#"
+emit [1, 2, 3, 4].chunk(2)
+emit [1, 2, 3, 4, 5].chunk(2) == [[1, 2], [3, 4], [5]]
+emit ["a", "b", "c"].chunk(1)
+emit [1, 2, 3].chunk(3)
+emit [1, 2].chunk(10)
+emit [].chunk(2)
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["[[1, 2], [3, 4]]", "true", "[[a], [b], [c]]", "[[1, 2, 3]]", "[[1, 2]]", "[]"]);
+    run_error("emit [1].chunk(0)", "list.chunk() requires a positive size");
+    run_error("emit [1].chunk(-2)", "list.chunk() requires a positive size");
+}
+
+#[test]
+fn test_equality_on_cyclic_lists() {
+    let src = r#"
+let a = [1, 2]
+a[0] = a
+let b = [1, 2]
+b[0] = b
+let c = [1, 3]
+c[0] = c
+emit a == a
+emit a == b
+emit a != c
+emit [[1], {"k": [2]}] == [[1], {"k": [2]}]
+let i1 = [1]
+let i2 = [1]
+emit [i1, i1] == [i2, i2]
+emit [a, b, c, a].unique().len()
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["true", "true", "true", "true", "true", "2"]);
+}
+
+#[test]
+fn test_list_reduce() {
+    let src = r#"
+fn mul(a: num, b: num) -> num {
+    return a * b
+}
+emit [1, 2, 3, 4].reduce(0, fn(acc, x) acc + x)
+emit [1, 2, 3].reduce("", fn(acc, x) acc ++ x)
+emit [1, 2, 3, 4].reduce(0, "+")
+emit [2, 3, 4].reduce(1, mul)
+emit [].reduce("empty", "+")
+let longest = ["a", "abc", "ab"].reduce("", fn(best, s) {
+    if len(s) > len(best) {
+        return s
+    }
+    return best
+})
+emit longest
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["10", "123", "10", "24", "empty", "abc"]);
+}
+
+#[test]
+fn test_list_reduce_errors() {
+    run_error("emit [1, 2].reduce(0, \"-\")", "unknown reducer '-'");
+    run_error("emit [1, 2].reduce(0, fn(x) x)", "function expects 1 arguments, got 2");
+}
+
+#[test]
+fn test_throw_from_callback_is_caught() {
+    let src = r#"
+fn boom(acc: num, x: num) -> num {
+    throw "boom"
+}
+fn check(acc: num, x: num) -> num {
+    try {
+        throw "inner"
+    } catch e {
+        emit "callback caught: " ++ e
+    }
+    return acc + x
+}
+try {
+    emit [1].reduce(0, boom)
+} catch e {
+    emit "caught: " ++ e
+}
+emit [1, 2].reduce(0, check)
+fn sorted(items: list[num]) -> str {
+    try {
+        items.sort_by(fn(a, b) {
+            throw "bad comparator"
+        })
+    } catch e {
+        return "sort failed: " ++ e
+    }
+    return "sorted"
+}
+emit sorted([2, 1])
+"#;
+    let out = run(src);
+    assert_eq!(
+        out,
+        vec![
+            "caught: boom",
+            "callback caught: inner",
+            "callback caught: inner",
+            "3",
+            "sort failed: bad comparator",
+        ]
+    );
+}
+
+#[test]
+fn test_compare_strings_and_mixed() {
+    let src = r#"
+emit "apple" < "banana"
+emit "b" >= "a"
+emit 10 < "9"
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["true", "true", "true"]);
+}
+
+#[test]
+fn test_compare_none_error() {
+    run_error("emit none < 1", "cannot compare none");
+}
+
+#[test]
+fn test_list_method_len() {
+    let src = r#"
+let items = [1, 2, 3]
+emit items.len()
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["3"]);
+}
+
+#[test]
+fn test_string_len() {
+    let src = r#"
+let s = "hello"
+emit len(s)
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["5"]);
+}
+
+#[test]
+fn test_string_lower_upper() {
+    let out = run("let s = \"Hello\"\nemit s.lower()\nemit s.upper()");
+    assert_eq!(out, vec!["hello", "HELLO"]);
+}
+
+#[test]
+fn test_string_split_and_list_join() {
+    let src = r#"
+let parts = "a,b,c".split(",")
+emit len(parts)
+emit parts[1]
+emit "one::two".split("::")
+emit "abc".split("")
+emit ["a", "b"].join("-")
+emit [1, 2, 3].join("")
+emit "x y z".split(" ").join("+")
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["3", "b", "[one, two]", "[a, b, c]", "a-b", "123", "x+y+z"]);
+}
+
+#[test]
+fn test_iterate_string_chars() {
+    let src = r#"
+for c in "abc" {
+    emit c
+}
+for c in "" {
+    emit "never"
+}
+emit "hey".chars()
+emit len("".chars())
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["a", "b", "c", "[h, e, y]", "0"]);
+}
+
+#[test]
+fn test_env_lookup() {
+    // SAFETY: the variable name is unique to this test, so no other thread reads or writes it
+    unsafe { std::env::set_var("AGENTUS_TEST_ENV_LOOKUP", "secret") };
+    let src = r#"
+emit env["AGENTUS_TEST_ENV_LOOKUP"]
+emit env["AGENTUS_TEST_ENV_UNSET"] == none
+let key = "AGENTUS_TEST_ENV_LOOKUP"
+emit "value: {env[key]}"
 "#;
     let out = run(src);
-    assert_eq!(out, vec!["true", "false"]);
+    assert_eq!(out, vec!["secret", "true", "value: secret"]);
 }
 
 #[test]
-fn test_map_method_remove() {
+fn test_script_args() {
+    let src = "emit len(args)\nemit args";
+    assert_eq!(run(src), vec!["0", "[]"]);
+
+    let module = compile("emit args[0]\nemit len(args)").unwrap();
+    let mut vm = VM::new(module)
+        .with_output(Box::new(SilentHandler))
+        .with_args(vec!["first".to_string(), "second".to_string()]);
+    vm.run().unwrap();
+    let out: Vec<String> = vm.get_outputs().iter().map(|v| v.to_string()).collect();
+    assert_eq!(out, vec!["first", "2"]);
+}
+
+#[test]
+fn test_string_trim() {
     let src = r#"
-let m = {"a": 1, "b": 2}
-let removed = m.remove("a")
-emit removed
-emit len(m)
+emit "[" ++ "  hello  ".trim() ++ "]"
+emit "[" ++ "\thello\n".trim() ++ "]"
+emit "[" ++ "  hello  ".trim_start() ++ "]"
+emit "[" ++ "  hello  ".trim_end() ++ "]"
+emit "[" ++ " \t\r\n ".trim() ++ "]"
 "#;
     let out = run(src);
-    assert_eq!(out, vec!["1", "1"]);
+    assert_eq!(out, vec!["[hello]", "[hello]", "[hello  ]", "[  hello]", "[]"]);
 }
 
 #[test]
-fn test_map_method_keys() {
+fn test_string_pad() {
     let src = r#"
-let m = {"x": 10}
-let ks = m.keys()
-emit len(ks)
+emit "[" ++ "hi".pad_start(5, " ") ++ "]"
+emit "hi".pad_end(5, "-")
+emit "[" ++ "hi".pad_end(4) ++ "]"
+emit "7".pad_start(3, "0")
+emit "toolong".pad_start(3, "*")
+emit "ab".pad_end(2, "*")
 "#;
     let out = run(src);
-    assert_eq!(out, vec!["1"]);
+    assert_eq!(out, vec!["[   hi]", "hi---", "[hi  ]", "007", "toolong", "ab"]);
 }
 
 #[test]
-fn test_map_method_values() {
+fn test_string_repeat_and_reverse() {
     let src = r#"
-let m = {"x": 10}
-let vs = m.values()
-emit len(vs)
+emit "ab".repeat(3) == "ababab"
+emit "x".repeat(0) == ""
+emit "café".reverse() == "éfac"
+emit "".reverse()
 "#;
     let out = run(src);
-    assert_eq!(out, vec!["1"]);
+    assert_eq!(out, vec!["true", "true", "true", ""]);
+    run_error("emit \"ab\".repeat(-1)", "str.repeat() requires a non-negative count");
 }
 
 #[test]
-fn test_map_for_iteration() {
+fn test_string_regex_methods() {
     let src = r#"
-let m = {"only": 42}
-for key in m {
-    emit key
-    emit m[key]
-}
+emit "hello world".match_regex("(\\w+)")
+emit "a=1, b=22".match_regex("(\\w)=(\\d+)")
+emit "no digits".match_regex("\\d+")
+emit "x1y22".match_regex("\\d+")
+emit "a1b2".replace_regex("\\d", "X")
+emit "John Smith".replace_regex("(\\w+) (\\w+)", "$2, $1")
 "#;
     let out = run(src);
-    assert_eq!(out, vec!["only", "42"]);
+    assert_eq!(out, vec!["[hello, world]", "[a, 1, b, 22]", "[]", "[1, 22]", "aXbX", "Smith, John"]);
+    run_error("emit \"abc\".match_regex(\"(unclosed\")", "invalid regex '(unclosed'");
+    run_error("emit \"abc\".replace_regex(\"[\", \"x\")", "invalid regex '['");
 }
 
 #[test]
-fn test_list_push() {
+fn test_string_find_and_count() {
     let src = r#"
-let items = [1, 2, 3]
-items.push(4)
-items.push(5)
-emit len(items)
-emit items[3]
-emit items[4]
+emit "hello world".find("world")
+emit "hello world".find("xyz")
+emit "abc".find("")
+emit "aababab".count("ab")
+emit "aaaa".count("aa")
+emit "abc".count("z")
 "#;
-    let out = run(src);
-    assert_eq!(out, vec!["5", "4", "5"]);
+    assert_eq!(run(src), vec!["6", "-1", "0", "3", "2", "0"]);
 }
 
 #[test]
-fn test_list_index_set() {
+fn test_str_to_num_and_num_to_str() {
     let src = r#"
-let items = [10, 20, 30]
-items[1] = 99
-emit items[0]
-emit items[1]
-emit items[2]
+emit "42".to_num() == 42
+emit "abc".to_num() == none
+emit " -1.5\n".to_num()
+emit "".to_num()
+emit "12abc".to_num()
+emit 42.to_str() == "42"
+let half = 0.5
+emit half.to_str() ++ "!"
 "#;
-    let out = run(src);
-    assert_eq!(out, vec!["10", "99", "30"]);
+    assert_eq!(run(src), vec!["true", "true", "-1.5", "none", "none", "true", "0.5!"]);
 }
 
 #[test]
-fn test_list_len() {
+fn test_map_key_value_iteration() {
     let src = r#"
-let items = [1, 2, 3, 4]
-emit len(items)
+let m = {"b": 2, "a": 1, "c": 3}
+let keys = []
+for k in m {
+    keys.push(k)
+}
+keys.sort()
+emit keys
+let total = 0
+for v in m.values() {
+    total = total + v
+}
+emit total
+for k, v in m {
+    emit "{k}={v}"
+}
+for i, item in ["x", "y"] {
+    emit "{i}:{item}"
+}
+emit m.items() == m.entries()
+emit m.items()
 "#;
-    let out = run(src);
-    assert_eq!(out, vec!["4"]);
+    assert_eq!(
+        run(src),
+        vec!["[a, b, c]", "6", "a=1", "b=2", "c=3", "0:x", "1:y", "true", "[[a, 1], [b, 2], [c, 3]]"]
+    );
 }
 
 #[test]
-fn test_list_method_len() {
+fn test_number_formatting_methods() {
     let src = r#"
-let items = [1, 2, 3]
-emit items.len()
+emit 3.14159.to_fixed(2) == "3.14"
+emit 2.to_fixed(3)
+emit 255.0.to_hex() == "0xff"
+emit (-31).to_hex()
+emit 3.9.to_int() == 3
+emit (-3.9).to_int()
+let x = 1.005
+emit x.to_fixed(0)
 "#;
     let out = run(src);
-    assert_eq!(out, vec!["3"]);
+    assert_eq!(out, vec!["true", "2.000", "true", "-0x1f", "true", "-3", "1"]);
+    run_error("emit 1.5.to_fixed(\"2\")", "requires a non-negative number of decimals");
+    run_error("emit 1.round()", "unknown number method 'round'");
 }
 
 #[test]
-fn test_string_len() {
+fn test_number_clamp_abs_sign() {
     let src = r#"
-let s = "hello"
-emit len(s)
+emit (-5).clamp(-3, 3) == -3
+emit 5.clamp(-3, 3) == 3
+emit 1.5.clamp(-3, 3)
+emit (-7).abs() == 7
+emit 0.sign() == 0
+emit (-5).sign() == -1
+emit 42.sign()
 "#;
     let out = run(src);
-    assert_eq!(out, vec!["5"]);
+    assert_eq!(out, vec!["true", "true", "1.5", "true", "true", "true", "1"]);
+    run_error("emit 1.clamp(3, -3)", "num.clamp() requires numeric bounds with min <= max");
 }
 
 #[test]
@@ -1350,6 +2650,118 @@ try {
     assert_eq!(out, vec!["42"]);
 }
 
+#[test]
+fn test_try_catch_throw_map() {
+    let src = r#"
+try {
+    throw {"code": 404}
+} catch err {
+    emit err
+    emit err["code"]
+}
+"#;
+    let out = run_values(src);
+    assert!(matches!(out[0], Value::Map(_)));
+    assert_eq!(out[1].as_num(), Some(404.0));
+}
+
+#[test]
+fn test_try_finally_on_error() {
+    let src = r#"
+try {
+    throw "boom"
+} catch err {
+    emit err
+} finally {
+    emit "cleanup"
+}
+emit "done"
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["boom", "cleanup", "done"]);
+}
+
+#[test]
+fn test_try_finally_no_error() {
+    let src = r#"
+try {
+    emit "ok"
+} catch err {
+    emit "caught"
+} finally {
+    emit "cleanup"
+}
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["ok", "cleanup"]);
+}
+
+#[test]
+fn test_try_finally_return_inside_try() {
+    let src = r#"
+fn work() -> num {
+    let x = 1
+    try {
+        return x
+    } catch err {
+        emit "caught"
+    } finally {
+        x = 2
+        emit "cleanup"
+    }
+    return 0
+}
+emit work()
+try {
+    throw "after"
+} catch err {
+    emit err
+}
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["cleanup", "1", "after"]);
+}
+
+#[test]
+fn test_try_finally_throw_inside_catch() {
+    let src = r#"
+try {
+    try {
+        throw "first"
+    } catch err {
+        emit err
+        throw "second"
+    } finally {
+        emit "cleanup"
+    }
+    emit "unreachable"
+} catch outer {
+    emit outer
+}
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["first", "cleanup", "second"]);
+}
+
+#[test]
+fn test_try_finally_return_inside_catch() {
+    let src = r#"
+fn work() -> str {
+    try {
+        throw "boom"
+    } catch err {
+        return err
+    } finally {
+        emit "cleanup"
+    }
+    return "unreachable"
+}
+emit work()
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["cleanup", "boom"]);
+}
+
 #[test]
 fn test_try_catch_in_function() {
     let src = r#"
@@ -1456,6 +2868,36 @@ emit "all passed"
     assert_eq!(out, vec!["all passed"]);
 }
 
+#[test]
+fn test_assert_eq_and_assert_ne() {
+    let src = r#"
+assert_eq(1 + 1, 2)
+assert_ne(1, 2)
+assert_eq([1, "a"], [1, "a"])
+try {
+    assert_eq(1 + 1, 3)
+} catch err {
+    emit err
+}
+try {
+    assert_ne("x", "x")
+} catch err {
+    emit err
+}
+emit "done"
+"#;
+    assert_eq!(
+        run(src),
+        vec![
+            "assertion failed: expected 3, got 2",
+            "assertion failed: expected a value other than x, got x",
+            "done",
+        ]
+    );
+    run_error("assert_eq(1 + 1, 3)", "unhandled error: assertion failed: expected 3, got 2");
+    expect_compile_error("assert_eq(1)", "assert_eq() takes exactly 2 arguments");
+}
+
 // ===================================================================
 // Phase 7: Retry
 // ===================================================================
@@ -1607,6 +3049,27 @@ try {
     assert_eq!(out, vec!["caught"]);
 }
 
+#[test]
+fn test_parse_json_error_is_error_value() {
+    let src = r#"
+try {
+    let data = parse_json("not valid json")
+} catch err {
+    emit err
+}
+"#;
+    let out = run_values(src);
+    match &out[0] {
+        Value::Error(msg) => assert!(msg.contains("parse_json error")),
+        other => panic!("expected error value, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_json_error_unhandled() {
+    run_error("let data = parse_json(\"oops\")", "unhandled error: parse_json error");
+}
+
 #[test]
 fn test_to_json_map() {
     let src = r#"
@@ -1673,3 +3136,312 @@ emit result["status"]
     let out = run(src);
     assert_eq!(out, vec!["ok"]);
 }
+
+// ===================================================================
+// Embedding API
+// ===================================================================
+
+#[test]
+fn test_compile_function_in_isolation() {
+    let program = parse("fn double(n: num) -> num {\n    return n * 2\n}").unwrap();
+    let def = program.find_fn("double").unwrap();
+
+    let mut compiler = Compiler::new();
+    let double = compiler.compile_function(def).unwrap();
+    assert_eq!(double.num_params, 1);
+
+    // Hand-craft a main function that calls double(21)
+    let mut builder = compiler.into_builder();
+    let double_idx = builder.add_function(double);
+    let arg_idx = builder.add_num_constant(21.0);
+    let main = Function {
+        name_idx: builder.add_string_constant("__main__") as u32,
+        num_params: 0,
+        num_registers: 2,
+        instructions: vec![
+            Instruction::abx(OpCode::LoadConst, 0, arg_idx),
+            Instruction::abx(OpCode::Call, 1, double_idx as u16),
+            Instruction::abc(OpCode::Nop, 0, 0, 1),
+            Instruction::op_a(OpCode::Emit, 1),
+            Instruction::op_only(OpCode::Halt),
+        ],
+    };
+    let entry = builder.add_function(main);
+    builder.set_entry_function(entry);
+
+    let mut vm = VM::new(builder.build()).with_output(Box::new(SilentHandler));
+    vm.run().unwrap();
+    let out: Vec<String> = vm.get_outputs().iter().map(|v| v.to_string()).collect();
+    assert_eq!(out, vec!["42"]);
+}
+
+#[test]
+fn test_compile_agent_in_isolation() {
+    let program = parse(
+        "agent Counter {\n    memory {\n        count: num = 5\n    }\n    fn increment() -> num {\n        self.count = self.count + 1\n        return self.count\n    }\n}",
+    )
+    .unwrap();
+    let def = program.iter_agent_defs().next().unwrap();
+
+    let mut compiler = Compiler::new();
+    let (mut descriptor, functions) = compiler.compile_agent(def).unwrap();
+    assert_eq!(functions.len(), 1);
+    assert_eq!(descriptor.methods.len(), 1);
+
+    // Wire the agent into a module with an empty main function
+    let mut builder = compiler.into_builder();
+    let mut offset = None;
+    for func in functions {
+        let idx = builder.add_function(func);
+        offset.get_or_insert(idx);
+    }
+    for (_, func_idx) in &mut descriptor.methods {
+        *func_idx += offset.unwrap();
+    }
+    builder.add_agent(descriptor);
+    let main = Function {
+        name_idx: builder.add_string_constant("__main__") as u32,
+        num_params: 0,
+        num_registers: 0,
+        instructions: vec![Instruction::op_only(OpCode::Halt)],
+    };
+    let entry = builder.add_function(main);
+    builder.set_entry_function(entry);
+
+    let mut vm = VM::new(builder.build()).with_output(Box::new(SilentHandler));
+    let id = vm.spawn_agent("Counter").unwrap();
+    assert_eq!(vm.call_method(id, "increment", &[]).unwrap(), Value::Num(6.0));
+}
+
+#[test]
+fn test_merge_modules_and_call_both() {
+    let first = compile("fn double(n: num) -> num {\n    return n * 2\n}").unwrap();
+    let second = compile("fn triple(n: num) -> num {\n    return n * 3\n}\nemit \"unused\"").unwrap();
+    let mut merged = first.merge(second).unwrap();
+
+    let find = |module: &Module, name: &str| {
+        module
+            .functions
+            .iter()
+            .position(|f| {
+                matches!(module.get_constant(f.name_idx as u16),
+                    Some(Constant::Str(s)) if s == name)
+            })
+            .unwrap() as u16
+    };
+    let double_idx = find(&merged, "double");
+    let triple_idx = find(&merged, "triple");
+
+    // Entry program that calls one function from each original module
+    let arg_idx = merged.add_constant(Constant::Num(5.0));
+    let name_idx = merged.add_constant(Constant::Str("__linked__".into()));
+    let entry = merged.add_function(Function {
+        name_idx: name_idx as u32,
+        num_params: 0,
+        num_registers: 3,
+        instructions: vec![
+            Instruction::abx(OpCode::LoadConst, 0, arg_idx),
+            Instruction::abx(OpCode::Call, 1, double_idx),
+            Instruction::abc(OpCode::Nop, 0, 0, 1),
+            Instruction::op_a(OpCode::Emit, 1),
+            Instruction::abx(OpCode::Call, 2, triple_idx),
+            Instruction::abc(OpCode::Nop, 0, 0, 1),
+            Instruction::op_a(OpCode::Emit, 2),
+            Instruction::op_only(OpCode::Halt),
+        ],
+    });
+    merged.entry_function = entry;
+
+    let mut vm = VM::new(merged).with_output(Box::new(SilentHandler));
+    vm.run().unwrap();
+    let out: Vec<String> = vm.get_outputs().iter().map(|v| v.to_string()).collect();
+    assert_eq!(out, vec!["10", "15"]);
+}
+
+#[test]
+fn test_strip_debug_info() {
+    let src = r#"
+fn square(n: num) -> num {
+    return n * n
+}
+fn describe(n: num) -> str {
+    return "square is {square(n)}"
+}
+agent Greeter {
+    fn greet(name: str) -> str {
+        return "hi " ++ name
+    }
+}
+let g = Greeter()
+emit describe(4)
+emit g.greet("square")
+"#;
+    let module = compile(src).unwrap();
+    let stripped = module.strip_debug_info();
+    assert!(stripped.constants.len() < module.constants.len());
+    assert!(stripped.functions.iter().all(|f| f.name_constant().is_none()));
+    // "square" is also a runtime string, so it stays in the pool
+    assert!(stripped.constants.contains(&Constant::Str("square".into())));
+    assert!(!stripped.constants.contains(&Constant::Str("describe".into())));
+
+    let mut vm = VM::new(stripped).with_output(Box::new(SilentHandler));
+    vm.run().unwrap();
+    let out: Vec<String> = vm.get_outputs().iter().map(|v| v.to_string()).collect();
+    assert_eq!(out, vec!["square is 16", "hi square"]);
+}
+
+#[test]
+fn test_named_entry_points() {
+    let src = r#"
+fn greet(name: str) -> str {
+    return "hi " ++ name
+}
+fn setup() {
+    emit greet("setup")
+}
+emit "top level"
+"#;
+    let module = compile(src).unwrap();
+    let mut names: Vec<&str> = module.named_entry_points.keys().map(|k| k.as_str()).collect();
+    names.sort();
+    assert_eq!(names, vec!["__main__", "setup"]);
+    assert_eq!(module.named_entry_points["__main__"], module.entry_function);
+
+    let mut vm = VM::new(module).with_output(Box::new(SilentHandler));
+    vm.run_entry("setup").unwrap();
+    let out: Vec<String> = vm.get_outputs().iter().map(|v| v.to_string()).collect();
+    assert_eq!(out, vec!["hi setup"]);
+}
+
+#[test]
+fn test_call_function_from_rust() {
+    let src = r#"
+fn double(x: num) -> num {
+    return x * 2
+}
+emit "main ran"
+"#;
+    let module = compile(src).unwrap();
+    let mut vm = VM::new(module).with_output(Box::new(SilentHandler));
+    let result = vm.call_function("double", &[Value::Num(5.0)]).unwrap();
+    assert_eq!(result.as_num(), Some(10.0));
+    // The entry program never ran
+    assert!(vm.get_outputs().is_empty());
+
+    let err = vm.call_function("double", &[]).unwrap_err();
+    assert!(err.to_string().contains("expects 1 arguments, got 0"));
+    let err = vm.call_function("missing", &[]).unwrap_err();
+    assert!(err.to_string().contains("undefined function 'missing'"));
+}
+
+// ===================================================================
+// Inlining
+// ===================================================================
+
+/// Helper: compile source with the given inline threshold, returning the
+/// module and its outputs when run.
+fn run_inlined(source: &str, inline_threshold: usize) -> (Module, Vec<String>) {
+    let program = parse(source).unwrap();
+    let options = CompileOptions { inline_threshold };
+    let module = Compiler::with_options(options).compile(&program).unwrap();
+    let mut vm = VM::new(module.clone()).with_output(Box::new(SilentHandler));
+    vm.run().unwrap_or_else(|e| panic!("runtime error: {}", e));
+    let out = vm.get_outputs().iter().map(|v| v.to_string()).collect();
+    (module, out)
+}
+
+fn count_direct_calls(module: &Module) -> usize {
+    let main = module.get_function(module.entry_function).unwrap();
+    main.instructions
+        .iter()
+        .filter(|inst| inst.opcode() == Some(OpCode::Call) && inst.bx() != 0xFFFE)
+        .count()
+}
+
+#[test]
+fn test_inlining_preserves_output() {
+    let src = r#"
+fn square(x: num) -> num {
+    return x * x
+}
+fn greet(name: str) -> str {
+    return "hi " ++ name
+}
+let total = 0
+for i in [1, 2, 3] {
+    if i > 1 {
+        total = total + square(i)
+    }
+}
+emit total
+emit greet("bob")
+emit square(square(2))
+"#;
+    let (plain, expected) = run_inlined(src, 0);
+    let (inlined, out) = run_inlined(src, 8);
+    assert_eq!(out, expected);
+    assert_eq!(out, vec!["13", "hi bob", "16"]);
+    assert_eq!(count_direct_calls(&plain), 4);
+    assert_eq!(count_direct_calls(&inlined), 0);
+}
+
+// ===================================================================
+// Pipelines
+// ===================================================================
+
+#[test]
+fn test_pipeline_two_stages() {
+    let src = r#"
+fn double(x: num) -> num {
+    return x * 2
+}
+fn describe(x: num) -> str {
+    return "result: {x}"
+}
+pipeline Process {
+    stage double
+    stage describe
+}
+emit Process(21)
+let p = Process(1)
+emit p
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["result: 42", "result: 2"]);
+}
+
+#[test]
+fn test_pipeline_arity_errors() {
+    let src = "fn id(x: num) -> num {\n    return x\n}\npipeline P {\n    stage id\n}\nemit P(1, 2)";
+    expect_compile_error(src, "pipeline 'P' takes exactly 1 argument");
+
+    let src = "fn add(a: num, b: num) -> num {\n    return a + b\n}\npipeline P {\n    stage add\n}\nemit P(1)";
+    expect_compile_error(src, "stage 'add' in pipeline 'P' must take exactly 1 parameter, found 2");
+}
+
+#[test]
+fn test_pipeline_stage_throw_is_caught() {
+    let src = r#"
+fn check(x: num) -> num {
+    if x < 0 {
+        throw "negative input"
+    }
+    return x
+}
+fn double(x: num) -> num {
+    return x * 2
+}
+pipeline Process {
+    stage check
+    stage double
+}
+emit Process(2)
+try {
+    emit Process(-1)
+} catch e {
+    emit "caught: " ++ e
+}
+"#;
+    let out = run(src);
+    assert_eq!(out, vec!["4", "caught: negative input"]);
+}
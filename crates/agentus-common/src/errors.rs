@@ -3,13 +3,13 @@ use crate::span::Span;
 /// Unified error type for the Agentus compiler and runtime.
 #[derive(Debug, thiserror::Error)]
 pub enum AgentusError {
-    #[error("Lexer error at {span:?}: {message}")]
+    #[error("Lexer error at {span}: {message}")]
     LexerError { message: String, span: Span },
 
-    #[error("Parser error at {span:?}: {message}")]
+    #[error("Parser error at {span}: {message}")]
     ParserError { message: String, span: Span },
 
-    #[error("Semantic error at {span:?}: {message}")]
+    #[error("Semantic error at {span}: {message}")]
     SemanticError { message: String, span: Span },
 
     #[error("Codegen error: {message}")]
@@ -18,6 +18,252 @@ pub enum AgentusError {
     #[error("Runtime error: {message}")]
     RuntimeError { message: String },
 
+    /// A sandboxed VM exceeded its `ExecutionBudget`.
+    #[error("Sandbox violation: {message}")]
+    Sandboxed { message: String },
+
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
 }
+
+impl AgentusError {
+    pub fn runtime(message: impl Into<String>) -> Self {
+        AgentusError::RuntimeError { message: message.into() }
+    }
+
+    pub fn codegen(message: impl Into<String>) -> Self {
+        AgentusError::CodegenError { message: message.into() }
+    }
+
+    pub fn sandboxed(message: impl Into<String>) -> Self {
+        AgentusError::Sandboxed { message: message.into() }
+    }
+
+    pub fn semantic(message: impl Into<String>, span: Span) -> Self {
+        AgentusError::SemanticError { message: message.into(), span }
+    }
+
+    /// Same as `semantic`, for callers holding a borrowed message.
+    pub fn from_str_with_span(message: &str, span: Span) -> Self {
+        Self::semantic(message, span)
+    }
+
+    /// Render the error with spans resolved to `line:col` against `source`.
+    pub fn display_with_source(&self, source: &str) -> String {
+        let located = |kind: &str, message: &str, span: &Span| {
+            let (line, col) = span.to_line_col(source);
+            format!("{} at {}:{}: {}", kind, line, col, message)
+        };
+        match self {
+            AgentusError::LexerError { message, span } => located("Lexer error", message, span),
+            AgentusError::ParserError { message, span } => located("Parser error", message, span),
+            AgentusError::SemanticError { message, span } => {
+                located("Semantic error", message, span)
+            }
+            other => other.to_string(),
+        }
+    }
+
+    /// The source span this error points at, if it has one.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            AgentusError::LexerError { span, .. }
+            | AgentusError::ParserError { span, .. }
+            | AgentusError::SemanticError { span, .. } => Some(*span),
+            _ => None,
+        }
+    }
+
+    /// The error message without its kind or location.
+    pub fn message(&self) -> String {
+        match self {
+            AgentusError::LexerError { message, .. }
+            | AgentusError::ParserError { message, .. }
+            | AgentusError::SemanticError { message, .. }
+            | AgentusError::CodegenError { message }
+            | AgentusError::RuntimeError { message }
+            | AgentusError::Sandboxed { message } => message.clone(),
+            AgentusError::IoError(e) => e.to_string(),
+        }
+    }
+
+    /// Short label for the compiler stage that produced this error.
+    fn stage(&self) -> &'static str {
+        match self {
+            AgentusError::LexerError { .. } => "lexer",
+            AgentusError::ParserError { .. } => "parse",
+            AgentusError::SemanticError { .. } => "semantic",
+            AgentusError::CodegenError { .. } => "codegen",
+            AgentusError::RuntimeError { .. } => "runtime",
+            AgentusError::Sandboxed { .. } => "sandbox",
+            AgentusError::IoError(_) => "io",
+        }
+    }
+}
+
+/// Split a front-end error message of the form `"... at Span { start: N, end: M, .. }"`
+/// into its text and span. Messages without a span point at the file start.
+pub fn split_span(message: &str) -> (String, Span) {
+    if let Some(idx) = message.rfind(" at Span { start: ") {
+        let numbers: Vec<u32> = message[idx..]
+            .split(|c: char| !c.is_ascii_digit())
+            .filter_map(|n| n.parse().ok())
+            .collect();
+        if let [start, end, ..] = numbers[..] {
+            return (message[..idx].to_string(), Span::new(start, end));
+        }
+    }
+    (message.to_string(), Span::default())
+}
+
+/// A set of errors rendered together as a compiler-style report, with
+/// file locations, the offending source line, carets, and notes.
+#[derive(Debug, Default)]
+pub struct DiagnosticReport {
+    diagnostics: Vec<(AgentusError, Vec<String>)>,
+}
+
+impl DiagnosticReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an error to the report.
+    pub fn push(&mut self, error: AgentusError) {
+        self.diagnostics.push((error, Vec::new()));
+    }
+
+    /// Attach a note to the most recently pushed error.
+    pub fn note(&mut self, note: impl Into<String>) {
+        if let Some((_, notes)) = self.diagnostics.last_mut() {
+            notes.push(note.into());
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.diagnostics.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    /// Render every diagnostic against `source`, followed by a summary line.
+    pub fn render(&self, source: &str, filename: &str) -> String {
+        let mut out = String::new();
+        for (error, notes) in &self.diagnostics {
+            out.push_str(&format!("error[{}]: {}\n", error.stage(), error.message()));
+            let gutter = match error.span() {
+                Some(span) => {
+                    let (line, col) = span.to_line_col(source);
+                    let gutter = " ".repeat(line.to_string().len());
+                    let line_text = source.lines().nth(line - 1).unwrap_or("").trim_end_matches('\r');
+
+                    // Keep tabs so the caret lines up with the source text
+                    let indent: String = line_text
+                        .chars()
+                        .take(col - 1)
+                        .map(|c| if c == '\t' { '\t' } else { ' ' })
+                        .collect();
+                    let width = span
+                        .source_text(source)
+                        .lines()
+                        .next()
+                        .map(|s| s.chars().count())
+                        .unwrap_or(0)
+                        .max(1);
+
+                    out.push_str(&format!("{}--> {}:{}:{}\n", gutter, filename, line, col));
+                    out.push_str(&format!("{} |\n", gutter));
+                    out.push_str(&format!("{} | {}\n", line, line_text));
+                    out.push_str(&format!("{} | {}{}\n", gutter, indent, "^".repeat(width)));
+                    gutter
+                }
+                None => {
+                    out.push_str(&format!("--> {}\n", filename));
+                    String::new()
+                }
+            };
+            for note in notes {
+                out.push_str(&format!("{} = note: {}\n", gutter, note));
+            }
+            out.push('\n');
+        }
+        let count = self.diagnostics.len();
+        out.push_str(&format!(
+            "error: aborting due to {} previous error{}\n",
+            count,
+            if count == 1 { "" } else { "s" }
+        ));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_display() {
+        let err = AgentusError::ParserError {
+            message: "unexpected token".to_string(),
+            span: Span::new(24, 28),
+        };
+        assert_eq!(err.to_string(), "Parser error at 24..28: unexpected token");
+        let source = "let a = 1\nlet b = 2\n    emit a + b\n";
+        assert_eq!(
+            err.display_with_source(source),
+            "Parser error at 3:5: unexpected token"
+        );
+    }
+
+    #[test]
+    fn test_error_factories() {
+        assert!(matches!(
+            AgentusError::runtime("division by zero"),
+            AgentusError::RuntimeError { message } if message == "division by zero"
+        ));
+        assert!(matches!(
+            AgentusError::codegen(String::from("too many registers")),
+            AgentusError::CodegenError { message } if message == "too many registers"
+        ));
+        let err = AgentusError::semantic("undefined variable 'x'", Span::new(4, 5));
+        assert!(matches!(err, AgentusError::SemanticError { .. }));
+        assert_eq!(err.span(), Some(Span::new(4, 5)));
+        let err = AgentusError::from_str_with_span("undefined variable 'x'", Span::new(4, 5));
+        assert_eq!(err.to_string(), "Semantic error at 4..5: undefined variable 'x'");
+        let err = AgentusError::sandboxed("opcode Exec is not allowed");
+        assert!(matches!(err, AgentusError::Sandboxed { .. }));
+        assert_eq!(err.to_string(), "Sandbox violation: opcode Exec is not allowed");
+    }
+
+    #[test]
+    fn test_diagnostic_report_render() {
+        let source = "let a = 1\nlet b = 2\n    emit a + c\n";
+        let mut report = DiagnosticReport::new();
+        report.push(AgentusError::SemanticError {
+            message: "undefined variable 'c'".to_string(),
+            span: Span::new(33, 34),
+        });
+        report.note("variables must be declared with `let` before use");
+        report.push(AgentusError::RuntimeError {
+            message: "division by zero".to_string(),
+        });
+
+        let rendered = report.render(source, "main.ags");
+        let expected = "\
+error[semantic]: undefined variable 'c'
+ --> main.ags:3:14
+  |
+3 |     emit a + c
+  |              ^
+  = note: variables must be declared with `let` before use
+
+error[runtime]: division by zero
+--> main.ags
+
+error: aborting due to 2 previous errors
+";
+        assert_eq!(rendered, expected);
+    }
+}
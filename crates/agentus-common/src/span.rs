@@ -5,17 +5,46 @@ pub struct Span {
     pub start: u32,
     /// Byte offset of the end of the span (exclusive).
     pub end: u32,
+    /// Identifies the source file this span belongs to (0 for single-file input).
+    pub file_id: u32,
 }
 
 impl Span {
     pub fn new(start: u32, end: u32) -> Self {
-        Self { start, end }
+        Self { start, end, file_id: 0 }
+    }
+
+    /// A span within the source file identified by `file_id`.
+    pub fn with_file_id(start: u32, end: u32, file_id: u32) -> Self {
+        Self { start, end, file_id }
+    }
+
+    /// An empty span at `offset`, such as a cursor position.
+    pub fn point(offset: u32) -> Self {
+        Self::new(offset, offset)
+    }
+
+    /// Whether `self` fully encloses `other` (both ends inclusive), in the
+    /// same file.
+    pub fn contains(&self, other: Span) -> bool {
+        self.file_id == other.file_id && self.start <= other.start && other.end <= self.end
+    }
+
+    /// Whether the two spans share at least one byte. Touching spans and
+    /// empty spans never overlap; use `contains` to test a cursor position.
+    pub fn overlaps(&self, other: Span) -> bool {
+        self.file_id == other.file_id
+            && !self.is_empty()
+            && !other.is_empty()
+            && self.start < other.end
+            && other.start < self.end
     }
 
     pub fn merge(self, other: Span) -> Span {
         Span {
             start: self.start.min(other.start),
             end: self.end.max(other.end),
+            file_id: self.file_id,
         }
     }
 
@@ -26,4 +55,82 @@ impl Span {
     pub fn is_empty(&self) -> bool {
         self.start == self.end
     }
+
+    /// Convert the start of this span into a 1-based (line, column) pair.
+    /// Offsets past the end of `source` clamp to the last position.
+    pub fn to_line_col(&self, source: &str) -> (usize, usize) {
+        let mut line = 1;
+        let mut col = 1;
+        for (offset, ch) in source.char_indices() {
+            if offset >= self.start as usize {
+                break;
+            }
+            if ch == '\n' {
+                line += 1;
+                col = 1;
+            } else {
+                col += 1;
+            }
+        }
+        (line, col)
+    }
+
+    /// The slice of `source` covered by this span, or "" if out of range.
+    pub fn source_text<'a>(&self, source: &'a str) -> &'a str {
+        source
+            .get(self.start as usize..self.end as usize)
+            .unwrap_or("")
+    }
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_line_col() {
+        let source = "let a = 1\nlet b = 2\n    emit a + b\n";
+        let span = Span::new(24, 28);
+        assert_eq!(span.to_line_col(source), (3, 5));
+        assert_eq!(span.source_text(source), "emit");
+    }
+
+    #[test]
+    fn test_default_span() {
+        let span = Span::default();
+        assert_eq!(span.to_line_col("emit 1"), (1, 1));
+        assert_eq!(span.to_line_col(""), (1, 1));
+        assert_eq!(span.source_text("emit 1"), "");
+    }
+
+    #[test]
+    fn test_contains_and_overlaps() {
+        let outer = Span::new(10, 20);
+        assert!(outer.contains(Span::new(12, 15)));
+        assert!(outer.contains(outer));
+        assert!(outer.contains(Span::point(20)));
+        assert!(!outer.contains(Span::new(15, 25)));
+        assert!(!outer.contains(Span::with_file_id(12, 15, 1)));
+
+        assert!(outer.overlaps(Span::new(15, 25)));
+        assert!(outer.overlaps(Span::new(0, 11)));
+        // Touching and disjoint spans share no byte
+        assert!(!outer.overlaps(Span::new(20, 30)));
+        assert!(!outer.overlaps(Span::new(0, 10)));
+        assert!(!outer.overlaps(Span::new(30, 40)));
+        assert!(!outer.overlaps(Span::point(15)));
+    }
+
+    #[test]
+    fn test_out_of_range_span() {
+        let span = Span::new(100, 200);
+        assert_eq!(span.to_line_col("ab\ncd"), (2, 3));
+        assert_eq!(span.source_text("ab\ncd"), "");
+    }
 }
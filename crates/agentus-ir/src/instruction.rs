@@ -1,3 +1,4 @@
+use crate::module::{Constant, Module};
 use crate::opcode::OpCode;
 
 /// A 32-bit encoded instruction.
@@ -7,7 +8,7 @@ use crate::opcode::OpCode;
 /// - ABx:  opcode(8) | A(8) | Bx(16)          — register + unsigned 16-bit constant index
 /// - AsBx: opcode(8) | A(8) | sBx(16 signed)  — register + signed 16-bit offset
 /// - sBx:  opcode(8) | sBx(24 signed)          — signed 24-bit offset (no register)
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct Instruction(pub u32);
 
 impl Instruction {
@@ -112,6 +113,125 @@ impl Instruction {
     pub fn raw(&self) -> u32 {
         self.0
     }
+
+    // =====================================================================
+    // Disassembly
+    // =====================================================================
+
+    /// Format this instruction with operands resolved against the module:
+    /// constant indices show their values, and function/agent/tool indices
+    /// show their names (e.g. `LoadConst r0, K0="hello"`).
+    pub fn disasm_with_context(&self, module: &Module) -> String {
+        let op = match self.opcode() {
+            Some(op) => op,
+            None => return format!("UNKNOWN(0x{:02X})", self.opcode_byte()),
+        };
+        let (a, b, c, bx) = (self.a(), self.b(), self.c(), self.bx());
+
+        match op {
+            OpCode::Halt | OpCode::RetNone | OpCode::TryEnd => format!("{}", op),
+            OpCode::Nop => {
+                if a == 0 && bx == 0 {
+                    format!("{}", op)
+                } else {
+                    format!("{} {}, {}, {}", op, a, b, c)
+                }
+            }
+            OpCode::LoadNone
+            | OpCode::LoadEnv
+            | OpCode::LoadTrue
+            | OpCode::LoadFalse
+            | OpCode::Ret
+            | OpCode::Emit
+            | OpCode::Throw
+            | OpCode::GetError
+            | OpCode::Kill
+            | OpCode::Yield => format!("{} r{}", op, a),
+            OpCode::LoadConst | OpCode::MLoad | OpCode::MStore | OpCode::GLoad | OpCode::GStore => {
+                format!("{} r{}, {}", op, a, describe_constant(module, bx))
+            }
+            OpCode::Move
+            | OpCode::Neg
+            | OpCode::Not
+            | OpCode::StrLen
+            | OpCode::Len
+            | OpCode::ListPush
+            | OpCode::ParseJson
+            | OpCode::ToJson
+            | OpCode::ToBase64
+            | OpCode::FromBase64
+            | OpCode::Exec
+            | OpCode::Send
+            | OpCode::Recv
+            | OpCode::Wait
+            | OpCode::IterInit
+            | OpCode::TypeOf => format!("{} r{}, r{}", op, a, b),
+            OpCode::NewList | OpCode::NewMap => format!("{} r{}, r{}, {}", op, a, b, c),
+            OpCode::Log => format!("{} {}, r{}", op, b, c),
+            OpCode::BatchTCall => format!("{} r{}, {}", op, a, b),
+            OpCode::Jmp => format!("{} {:+}", op, self.sbx_24()),
+            OpCode::JmpTrue | OpCode::JmpFalse | OpCode::IterNext | OpCode::TryBegin => {
+                format!("{} r{}, {:+}", op, a, self.sbx_16())
+            }
+            OpCode::MakeFn => {
+                let name = module
+                    .get_function(bx as u32)
+                    .and_then(|f| constant_str(module, f.name_constant()?));
+                format!("{} r{}, F{}={}", op, a, bx, name.unwrap_or("?"))
+            }
+            OpCode::Call => {
+                if bx == 0xFFFE {
+                    format!("{} r{}, <method>", op, a)
+                } else {
+                    let name = module
+                        .get_function(bx as u32)
+                        .and_then(|f| constant_str(module, f.name_constant()?));
+                    format!("{} r{}, F{}={}", op, a, bx, name.unwrap_or("?"))
+                }
+            }
+            OpCode::Spawn => {
+                let name = module
+                    .get_agent(bx as u32)
+                    .and_then(|agent| constant_str(module, agent.name_idx));
+                format!("{} r{}, A{}={}", op, a, bx, name.unwrap_or("?"))
+            }
+            OpCode::TCall => {
+                let name = module
+                    .get_tool(bx as u32)
+                    .and_then(|tool| constant_str(module, tool.name_idx));
+                format!("{} r{}, T{}={}", op, a, bx, name.unwrap_or("?"))
+            }
+            OpCode::PipelineRun => {
+                let name = module
+                    .get_pipeline(bx as u32)
+                    .and_then(|pipeline| constant_str(module, pipeline.name_idx));
+                format!("{} r{}, P{}={}", op, a, bx, name.unwrap_or("?"))
+            }
+            OpCode::Format => {
+                format!("{} r{}, {}", op, a, describe_constant(module, bx))
+            }
+            _ => format!("{} r{}, r{}, r{}", op, a, b, c),
+        }
+    }
+}
+
+/// Format a constant pool reference as `K<idx>=<value>`.
+fn describe_constant(module: &Module, idx: u16) -> String {
+    match module.get_constant(idx) {
+        Some(Constant::None) => format!("K{}=none", idx),
+        Some(Constant::Bool(b)) => format!("K{}={}", idx, b),
+        Some(Constant::Num(n)) => format!("K{}={}", idx, n),
+        Some(Constant::Str(s)) => format!("K{}={:?}", idx, s),
+        None => format!("K{}=<invalid>", idx),
+    }
+}
+
+/// Look up a string constant by index.
+fn constant_str(module: &Module, idx: u16) -> Option<&str> {
+    match module.get_constant(idx) {
+        Some(Constant::Str(s)) => Some(s.as_str()),
+        _ => None,
+    }
 }
 
 impl std::fmt::Display for Instruction {
@@ -180,4 +300,22 @@ mod tests {
         assert_eq!(inst.opcode(), Some(OpCode::Emit));
         assert_eq!(inst.a(), 7);
     }
+
+    #[test]
+    fn test_disasm_with_context() {
+        let mut module = Module::new();
+        let hello = module.add_constant(Constant::Str("hello".into()));
+        let num = module.add_constant(Constant::Num(2.5));
+
+        let load = Instruction::abx(OpCode::LoadConst, 0, hello);
+        assert_eq!(load.disasm_with_context(&module), "LoadConst r0, K0=\"hello\"");
+        let load = Instruction::abx(OpCode::LoadConst, 1, num);
+        assert_eq!(load.disasm_with_context(&module), "LoadConst r1, K1=2.5");
+        let add = Instruction::abc(OpCode::Add, 2, 0, 1);
+        assert_eq!(add.disasm_with_context(&module), "Add r2, r0, r1");
+        let jmp = Instruction::sbx(OpCode::Jmp, -3);
+        assert_eq!(jmp.disasm_with_context(&module), "Jmp -3");
+        let bad = Instruction::abx(OpCode::LoadConst, 0, 99);
+        assert_eq!(bad.disasm_with_context(&module), "LoadConst r0, K99=<invalid>");
+    }
 }
@@ -1,7 +1,9 @@
+use std::collections::HashMap;
 use crate::instruction::Instruction;
+use crate::opcode::OpCode;
 
 /// A constant value in the constant pool.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub enum Constant {
     None,
     Bool(bool),
@@ -9,10 +11,15 @@ pub enum Constant {
     Str(String),
 }
 
+/// `Function::name_idx` of a function whose name was removed by
+/// [`Module::strip_debug_info`]. It lies outside the 16-bit constant pool.
+pub const STRIPPED_NAME_IDX: u32 = u32::MAX;
+
 /// A compiled function.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Function {
-    /// Index into the constant pool for the function name.
+    /// Index into the constant pool for the function name, or
+    /// [`STRIPPED_NAME_IDX`] if the name has been stripped.
     pub name_idx: u32,
     /// Number of parameters.
     pub num_params: u8,
@@ -22,8 +29,32 @@ pub struct Function {
     pub instructions: Vec<Instruction>,
 }
 
+impl Function {
+    /// The constant pool index of this function's name, if it has one.
+    pub fn name_constant(&self) -> Option<u16> {
+        u16::try_from(self.name_idx).ok()
+    }
+
+    /// Disassemble this function into a readable listing, one instruction per
+    /// line prefixed with its index, with operands resolved against `module`.
+    pub fn disasm(&self, module: &Module) -> String {
+        let name = match self.name_constant().and_then(|idx| module.get_constant(idx)) {
+            Some(Constant::Str(s)) => s.as_str(),
+            _ => "?",
+        };
+        let mut out = format!(
+            "fn {} (params={}, registers={})\n",
+            name, self.num_params, self.num_registers
+        );
+        for (i, inst) in self.instructions.iter().enumerate() {
+            out.push_str(&format!("{:04}  {}\n", i, inst.disasm_with_context(module)));
+        }
+        out
+    }
+}
+
 /// Describes an agent type in the module.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct AgentDescriptor {
     /// Index into the constant pool for the agent name.
     pub name_idx: u16,
@@ -38,7 +69,7 @@ pub struct AgentDescriptor {
 }
 
 /// A single memory field in an agent descriptor.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct AgentMemoryField {
     /// Index into the constant pool for the field name.
     pub name_idx: u16,
@@ -47,7 +78,7 @@ pub struct AgentMemoryField {
 }
 
 /// Describes a tool declaration in the module.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct ToolDescriptor {
     /// Index into the constant pool for the tool name.
     pub name_idx: u16,
@@ -58,7 +89,7 @@ pub struct ToolDescriptor {
 }
 
 /// A single parameter in a tool descriptor.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct ToolParamDescriptor {
     /// Index into the constant pool for the parameter name.
     pub name_idx: u16,
@@ -66,8 +97,17 @@ pub struct ToolParamDescriptor {
     pub default_idx: Option<u16>,
 }
 
+/// Describes a pipeline declaration in the module.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PipelineDescriptor {
+    /// Index into the constant pool for the pipeline name.
+    pub name_idx: u16,
+    /// Function table indices of the stages, in execution order.
+    pub stages: Vec<u32>,
+}
+
 /// A compiled module — the output of the compiler, input to the runtime.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Module {
     /// Constant pool: strings, numbers, booleans referenced by instructions.
     pub constants: Vec<Constant>,
@@ -77,8 +117,15 @@ pub struct Module {
     pub agents: Vec<AgentDescriptor>,
     /// Tool descriptor table.
     pub tools: Vec<ToolDescriptor>,
+    /// Pipeline descriptor table.
+    #[serde(default)]
+    pub pipelines: Vec<PipelineDescriptor>,
     /// Index of the entry point function (usually `main` or the top-level script).
     pub entry_function: u32,
+    /// Additional entry points by name (e.g. `__main__`, `setup`, `test`),
+    /// each a function index that can be run instead of `entry_function`.
+    #[serde(default)]
+    pub named_entry_points: HashMap<String, u32>,
 }
 
 impl Module {
@@ -88,7 +135,9 @@ impl Module {
             functions: Vec::new(),
             agents: Vec::new(),
             tools: Vec::new(),
+            pipelines: Vec::new(),
             entry_function: 0,
+            named_entry_points: HashMap::new(),
         }
     }
 
@@ -146,6 +195,249 @@ impl Module {
     pub fn get_tool(&self, idx: u32) -> Option<&ToolDescriptor> {
         self.tools.get(idx as usize)
     }
+
+    /// Add a pipeline descriptor and return its index.
+    pub fn add_pipeline(&mut self, pipeline: PipelineDescriptor) -> u32 {
+        let idx = self.pipelines.len();
+        self.pipelines.push(pipeline);
+        idx as u32
+    }
+
+    /// Get a pipeline descriptor by index.
+    pub fn get_pipeline(&self, idx: u32) -> Option<&PipelineDescriptor> {
+        self.pipelines.get(idx as usize)
+    }
+
+    /// Link `other` into this module.
+    ///
+    /// Constants are deduplicated against this module's pool; functions,
+    /// agents, tools, and pipelines are appended after the existing ones. Every index in
+    /// `other` — instruction operands and descriptor fields — is remapped to
+    /// its new position. The entry function stays this module's entry, and
+    /// named entry points already in this module take precedence over
+    /// `other`'s entries of the same name.
+    pub fn merge(mut self, other: Module) -> Result<Module, String> {
+        let func_offset = self.functions.len() as u32;
+        let agent_offset = self.agents.len() as u32;
+        let tool_offset = self.tools.len() as u32;
+        let pipeline_offset = self.pipelines.len() as u32;
+        if func_offset as usize + other.functions.len() >= 0xFFFE {
+            return Err("merged module has too many functions".to_string());
+        }
+        if agent_offset as usize + other.agents.len() > u16::MAX as usize + 1
+            || tool_offset as usize + other.tools.len() > u16::MAX as usize + 1
+            || pipeline_offset as usize + other.pipelines.len() > u16::MAX as usize + 1
+        {
+            return Err("merged module has too many agents, tools, or pipelines".to_string());
+        }
+
+        let mut const_map = Vec::with_capacity(other.constants.len());
+        for constant in other.constants {
+            if self.constants.len() > u16::MAX as usize {
+                return Err("constant pool overflow while merging modules".to_string());
+            }
+            const_map.push(self.add_constant(constant));
+        }
+        let remap = |idx: u16| -> Result<u16, String> {
+            const_map
+                .get(idx as usize)
+                .copied()
+                .ok_or_else(|| format!("constant index {} out of range while merging modules", idx))
+        };
+
+        for mut func in other.functions {
+            if let Some(name_idx) = func.name_constant() {
+                func.name_idx = remap(name_idx)? as u32;
+            }
+            let insts = &mut func.instructions;
+            let mut i = 0;
+            while i < insts.len() {
+                let inst = insts[i];
+                let (a, bx) = (inst.a(), inst.bx());
+                match inst.opcode() {
+                    Some(
+                        op @ (OpCode::LoadConst
+                        | OpCode::MLoad
+                        | OpCode::MStore
+                        | OpCode::GLoad
+                        | OpCode::GStore
+                        | OpCode::Format),
+                    ) => insts[i] = Instruction::abx(op, a, remap(bx)?),
+                    Some(OpCode::Call) if bx == 0xFFFE => {
+                        // Method call: the second data word holds the method name constant
+                        if let Some(name_word) = insts.get(i + 2).copied() {
+                            insts[i + 2] =
+                                Instruction::abx(OpCode::Nop, name_word.a(), remap(name_word.bx())?);
+                        }
+                        i += 3;
+                        continue;
+                    }
+                    Some(op @ (OpCode::Call | OpCode::MakeFn)) => {
+                        insts[i] = Instruction::abx(op, a, bx + func_offset as u16)
+                    }
+                    Some(OpCode::Spawn) => {
+                        insts[i] = Instruction::abx(OpCode::Spawn, a, bx + agent_offset as u16)
+                    }
+                    Some(OpCode::TCall) => {
+                        insts[i] = Instruction::abx(OpCode::TCall, a, bx + tool_offset as u16)
+                    }
+                    Some(OpCode::PipelineRun) => {
+                        insts[i] =
+                            Instruction::abx(OpCode::PipelineRun, a, bx + pipeline_offset as u16)
+                    }
+                    _ => {}
+                }
+                i += 1;
+            }
+            self.functions.push(func);
+        }
+
+        for mut agent in other.agents {
+            agent.name_idx = remap(agent.name_idx)?;
+            agent.model_idx = agent.model_idx.map(remap).transpose()?;
+            agent.system_prompt_idx = agent.system_prompt_idx.map(remap).transpose()?;
+            for field in &mut agent.memory_fields {
+                field.name_idx = remap(field.name_idx)?;
+                field.default_idx = field.default_idx.map(remap).transpose()?;
+            }
+            for (name_idx, func_idx) in &mut agent.methods {
+                *name_idx = remap(*name_idx)?;
+                *func_idx += func_offset;
+            }
+            self.agents.push(agent);
+        }
+
+        for mut tool in other.tools {
+            tool.name_idx = remap(tool.name_idx)?;
+            tool.description_idx = tool.description_idx.map(remap).transpose()?;
+            for param in &mut tool.params {
+                param.name_idx = remap(param.name_idx)?;
+                param.default_idx = param.default_idx.map(remap).transpose()?;
+            }
+            self.tools.push(tool);
+        }
+
+        for mut pipeline in other.pipelines {
+            pipeline.name_idx = remap(pipeline.name_idx)?;
+            for stage in &mut pipeline.stages {
+                *stage += func_offset;
+            }
+            self.pipelines.push(pipeline);
+        }
+
+        for (name, func_idx) in other.named_entry_points {
+            self.named_entry_points.entry(name).or_insert(func_idx + func_offset);
+        }
+
+        Ok(self)
+    }
+
+    /// A copy of this module without debug metadata.
+    ///
+    /// Every `Function::name_idx` becomes [`STRIPPED_NAME_IDX`], and
+    /// constants that were referenced only as function names are dropped
+    /// from the pool. Constants used by instructions or by agent, tool, and
+    /// pipeline descriptors are kept and renumbered. Stripped functions can no
+    /// longer be looked up by name (e.g. with `VM::call_function`).
+    pub fn strip_debug_info(&self) -> Module {
+        let mut stripped = self.clone();
+        let mut used = vec![false; self.constants.len()];
+        stripped.for_each_constant_ref(|idx| {
+            if let Some(slot) = used.get_mut(*idx as usize) {
+                *slot = true;
+            }
+        });
+
+        let mut const_map = vec![0u16; self.constants.len()];
+        stripped.constants.clear();
+        for (idx, constant) in self.constants.iter().enumerate() {
+            if used[idx] {
+                const_map[idx] = stripped.constants.len() as u16;
+                stripped.constants.push(constant.clone());
+            }
+        }
+        stripped.for_each_constant_ref(|idx| {
+            if let Some(&new_idx) = const_map.get(*idx as usize) {
+                *idx = new_idx;
+            }
+        });
+        for func in &mut stripped.functions {
+            func.name_idx = STRIPPED_NAME_IDX;
+        }
+        stripped
+    }
+
+    /// Visit every constant pool reference that matters at runtime: instruction
+    /// operands and descriptor fields, but not function names.
+    fn for_each_constant_ref(&mut self, mut f: impl FnMut(&mut u16)) {
+        for func in &mut self.functions {
+            let insts = &mut func.instructions;
+            let mut i = 0;
+            while i < insts.len() {
+                let inst = insts[i];
+                let (a, mut bx) = (inst.a(), inst.bx());
+                match inst.opcode() {
+                    Some(
+                        op @ (OpCode::LoadConst
+                        | OpCode::MLoad
+                        | OpCode::MStore
+                        | OpCode::GLoad
+                        | OpCode::GStore
+                        | OpCode::Format),
+                    ) => {
+                        f(&mut bx);
+                        insts[i] = Instruction::abx(op, a, bx);
+                    }
+                    Some(OpCode::Call) if bx == 0xFFFE => {
+                        // Method call: the second data word holds the method name constant
+                        if let Some(name_word) = insts.get(i + 2).copied() {
+                            let mut name_idx = name_word.bx();
+                            f(&mut name_idx);
+                            insts[i + 2] = Instruction::abx(OpCode::Nop, name_word.a(), name_idx);
+                        }
+                        i += 3;
+                        continue;
+                    }
+                    _ => {}
+                }
+                i += 1;
+            }
+        }
+
+        for agent in &mut self.agents {
+            f(&mut agent.name_idx);
+            if let Some(idx) = &mut agent.model_idx {
+                f(idx);
+            }
+            if let Some(idx) = &mut agent.system_prompt_idx {
+                f(idx);
+            }
+            for field in &mut agent.memory_fields {
+                f(&mut field.name_idx);
+                if let Some(idx) = &mut field.default_idx {
+                    f(idx);
+                }
+            }
+            for (name_idx, _) in &mut agent.methods {
+                f(name_idx);
+            }
+        }
+        for tool in &mut self.tools {
+            f(&mut tool.name_idx);
+            if let Some(idx) = &mut tool.description_idx {
+                f(idx);
+            }
+            for param in &mut tool.params {
+                f(&mut param.name_idx);
+                if let Some(idx) = &mut param.default_idx {
+                    f(idx);
+                }
+            }
+        }
+        for pipeline in &mut self.pipelines {
+            f(&mut pipeline.name_idx);
+        }
+    }
 }
 
 impl Default for Module {
@@ -187,18 +479,40 @@ impl ModuleBuilder {
         self.module.add_function(function)
     }
 
+    pub fn get_function(&self, idx: u32) -> Option<&Function> {
+        self.module.get_function(idx)
+    }
+
+    /// Replace a previously added function, such as a placeholder reserved
+    /// so that a function body can refer to its own index.
+    pub fn set_function(&mut self, idx: u32, function: Function) {
+        self.module.functions[idx as usize] = function;
+    }
+
     pub fn add_agent(&mut self, agent: AgentDescriptor) -> u32 {
         self.module.add_agent(agent)
     }
 
+    pub fn get_agent(&self, idx: u32) -> Option<&AgentDescriptor> {
+        self.module.get_agent(idx)
+    }
+
     pub fn add_tool(&mut self, tool: ToolDescriptor) -> u32 {
         self.module.add_tool(tool)
     }
 
+    pub fn add_pipeline(&mut self, pipeline: PipelineDescriptor) -> u32 {
+        self.module.add_pipeline(pipeline)
+    }
+
     pub fn set_entry_function(&mut self, idx: u32) {
         self.module.entry_function = idx;
     }
 
+    pub fn add_named_entry_point(&mut self, name: &str, idx: u32) {
+        self.module.named_entry_points.insert(name.to_string(), idx);
+    }
+
     pub fn build(self) -> Module {
         self.module
     }
@@ -234,4 +548,85 @@ mod tests {
         let module = builder.build();
         assert_eq!(module.constants.len(), 2);
     }
+
+    fn single_function_module(name: &str, value: f64) -> Module {
+        let mut module = Module::new();
+        let name_idx = module.add_constant(Constant::Str(name.into()));
+        let value_idx = module.add_constant(Constant::Num(value));
+        module.add_function(Function {
+            name_idx: name_idx as u32,
+            num_params: 0,
+            num_registers: 1,
+            instructions: vec![
+                Instruction::abx(OpCode::LoadConst, 0, value_idx),
+                Instruction::abx(OpCode::Call, 0, 0),
+                Instruction::abc(OpCode::Nop, 0, 0, 0),
+                Instruction::op_a(OpCode::Ret, 0),
+            ],
+        });
+        module
+    }
+
+    #[test]
+    fn test_merge_remaps_indices() {
+        let first = single_function_module("first", 1.0);
+        let mut second = single_function_module("second", 2.0);
+        second.add_constant(Constant::Num(1.0)); // duplicate of first's constant
+
+        let merged = first.merge(second).unwrap();
+        assert_eq!(merged.entry_function, 0);
+        assert_eq!(merged.functions.len(), 2);
+        // "first", 1, "second", 2 — the duplicate 1 is shared
+        assert_eq!(merged.constants.len(), 4);
+
+        let func = merged.get_function(1).unwrap();
+        assert_eq!(merged.get_constant(func.name_idx as u16), Some(&Constant::Str("second".into())));
+        assert_eq!(func.instructions[0].bx(), 3);
+        assert_eq!(merged.get_constant(3), Some(&Constant::Num(2.0)));
+        assert_eq!(func.instructions[1].bx(), 1); // Call F0 -> F1
+    }
+
+    #[test]
+    fn test_merge_out_of_range_constant() {
+        let first = Module::new();
+        let mut second = Module::new();
+        second.add_function(Function {
+            name_idx: 7,
+            num_params: 0,
+            num_registers: 0,
+            instructions: vec![],
+        });
+        let err = first.merge(second).unwrap_err();
+        assert!(err.contains("constant index 7 out of range"));
+    }
+
+    #[test]
+    fn test_tool_survives_serialization() {
+        let mut module = Module::new();
+        let name_idx = module.add_constant(Constant::Str("ping".into()));
+        let param_idx = module.add_constant(Constant::Str("host".into()));
+        let default_idx = module.add_constant(Constant::Str("localhost".into()));
+        let tool_idx = module.add_tool(ToolDescriptor {
+            name_idx,
+            description_idx: None,
+            params: vec![ToolParamDescriptor { name_idx: param_idx, default_idx: Some(default_idx) }],
+        });
+
+        let json = serde_json::to_string(&module).unwrap();
+        let restored: Module = serde_json::from_str(&json).unwrap();
+        let tool = restored.get_tool(tool_idx).unwrap();
+        assert_eq!(restored.get_constant(tool.name_idx), Some(&Constant::Str("ping".into())));
+        assert_eq!(tool.params.len(), 1);
+        assert_eq!(tool.params[0].name_idx, param_idx);
+        assert_eq!(tool.params[0].default_idx, Some(default_idx));
+        assert!(restored.get_tool(tool_idx + 1).is_none());
+    }
+
+    #[test]
+    fn test_deserialize_module_without_pipelines() {
+        let json = r#"{"constants":[],"functions":[],"agents":[],"tools":[],"entry_function":0}"#;
+        let module: Module = serde_json::from_str(json).unwrap();
+        assert!(module.pipelines.is_empty());
+        assert!(module.named_entry_points.is_empty());
+    }
 }
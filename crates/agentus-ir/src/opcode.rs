@@ -28,6 +28,8 @@ pub enum OpCode {
     LoadFalse = 0x13,
     /// Copy register: r(A) = r(B)
     Move = 0x14,
+    /// Load the environment variables object: r(A) = env
+    LoadEnv = 0x15,
 
     // =====================================================================
     // AGENT MEMORY
@@ -72,6 +74,8 @@ pub enum OpCode {
     Gt = 0x44,
     /// Greater than or equal: r(A) = r(B) >= r(C)
     Gte = 0x45,
+    /// Three-way compare: r(A) = -1, 0 or 1 as r(B) sorts before, with, or after r(C)
+    Cmp = 0x46,
 
     // =====================================================================
     // LOGIC
@@ -94,6 +98,10 @@ pub enum OpCode {
     Format = 0x52,
     /// Substring: r(A) = r(B)\[r(C)..r(D)\]
     Substr = 0x53,
+    /// Base64-encode bytes: r(A) = to_base64(r(B))
+    ToBase64 = 0x54,
+    /// Base64-decode a string into bytes: r(A) = from_base64(r(B))
+    FromBase64 = 0x55,
 
     // =====================================================================
     // COLLECTION OPERATIONS
@@ -134,6 +142,8 @@ pub enum OpCode {
     Ret = 0x69,
     /// Return none
     RetNone = 0x6A,
+    /// Load function reference: r(A) = func_table[Bx]
+    MakeFn = 0x6B,
 
     // =====================================================================
     // LLM EXECUTION
@@ -148,9 +158,11 @@ pub enum OpCode {
     // =====================================================================
     /// Spawn agent: r(A) = spawn(agent_type=constants[Bx], init=r(C)..r(C+N))
     Spawn = 0x78,
-    /// Send message: send(handle=r(A), message=r(B))
+    /// Send message: send(handle=r(A), message=r(B)); C=1 sends to the
+    /// current frame's agent instead of r(A)
     Send = 0x79,
-    /// Receive message (blocking): r(A) = recv(handle=r(B))
+    /// Receive message (blocking): r(A) = recv(handle=r(B)); C=1 receives
+    /// from the current frame's agent instead of r(B)
     Recv = 0x7A,
     /// Receive with timeout: r(A) = recv(handle=r(B), timeout_ms=r(C))
     RecvTimeout = 0x7B,
@@ -164,11 +176,14 @@ pub enum OpCode {
     // =====================================================================
     /// Call tool: r(A) = tcall(tool=constants[Bx], r(C)..r(C+N))
     TCall = 0x80,
+    /// Batched tool calls: r(A) = list of results of the B TCall sequences
+    /// that follow, dispatched to the host together
+    BatchTCall = 0x81,
 
     // =====================================================================
     // PIPELINE
     // =====================================================================
-    /// Run pipeline: r(A) = pipeline_run(pipeline=constants[Bx], input=r(C))
+    /// Run pipeline: r(A) = pipeline_run(pipelines[Bx], input=r(B) of the next word)
     PipelineRun = 0x88,
 
     // =====================================================================
@@ -200,9 +215,10 @@ pub enum OpCode {
     // =====================================================================
     // ITERATOR
     // =====================================================================
-    /// Create iterator: r(A) = iter(r(B))
+    /// Create iterator: r(A) = iter(r(B)); C=1 iterates [key, value] pairs
     IterInit = 0xA8,
-    /// Advance iterator: r(A) = next(r(B)), jump sBx if exhausted
+    /// Advance iterator: r(A) = next(r(B)), jump sBx if exhausted. When the
+    /// extra word has A=1, the pair is split into r(A) and r(C).
     IterNext = 0xA9,
 
     // =====================================================================
@@ -227,6 +243,7 @@ impl OpCode {
             0x12 => Some(Self::LoadTrue),
             0x13 => Some(Self::LoadFalse),
             0x14 => Some(Self::Move),
+            0x15 => Some(Self::LoadEnv),
 
             0x20 => Some(Self::MLoad),
             0x21 => Some(Self::MStore),
@@ -246,6 +263,7 @@ impl OpCode {
             0x43 => Some(Self::Lte),
             0x44 => Some(Self::Gt),
             0x45 => Some(Self::Gte),
+            0x46 => Some(Self::Cmp),
 
             0x48 => Some(Self::And),
             0x49 => Some(Self::Or),
@@ -255,6 +273,8 @@ impl OpCode {
             0x51 => Some(Self::StrLen),
             0x52 => Some(Self::Format),
             0x53 => Some(Self::Substr),
+            0x54 => Some(Self::ToBase64),
+            0x55 => Some(Self::FromBase64),
 
             0x58 => Some(Self::NewList),
             0x59 => Some(Self::NewMap),
@@ -272,6 +292,7 @@ impl OpCode {
             0x68 => Some(Self::Call),
             0x69 => Some(Self::Ret),
             0x6A => Some(Self::RetNone),
+            0x6B => Some(Self::MakeFn),
 
             0x70 => Some(Self::Exec),
             0x71 => Some(Self::ExecStructured),
@@ -284,6 +305,7 @@ impl OpCode {
             0x7D => Some(Self::Kill),
 
             0x80 => Some(Self::TCall),
+            0x81 => Some(Self::BatchTCall),
 
             0x88 => Some(Self::PipelineRun),
 
@@ -10,189 +10,279 @@ enum LexMode {
     StringInterp { brace_depth: u32 },
 }
 
+/// A lexical error with the span of the offending source text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    pub message: String,
+    pub span: Span,
+}
+
 /// The Agentus lexer. Converts source text into a stream of tokens.
 pub struct Lexer<'src> {
     source: &'src str,
     bytes: &'src [u8],
     pos: usize,
+    /// Lexed tokens not yet handed out (all of them when using `tokenize`).
     tokens: Vec<Token>,
-    errors: Vec<String>,
+    errors: Vec<LexError>,
+    /// Set once the `Eof` token has been produced.
+    finished: bool,
     /// Mode stack for handling nested interpolation.
     mode_stack: Vec<LexMode>,
+    /// Source file identifier stamped on every produced span.
+    file_id: u32,
 }
 
 impl<'src> Lexer<'src> {
     pub fn new(source: &'src str) -> Self {
+        Self::new_with_file_id(source, 0)
+    }
+
+    /// Create a lexer whose token spans carry `file_id`, so spans from
+    /// different files stay distinguishable in multi-file compilation.
+    pub fn new_with_file_id(source: &'src str, file_id: u32) -> Self {
         Self {
             source,
             bytes: source.as_bytes(),
             pos: 0,
             tokens: Vec::new(),
             errors: Vec::new(),
+            finished: false,
             mode_stack: vec![LexMode::Normal],
+            file_id,
         }
     }
 
+    fn span(&self, start: u32, end: u32) -> Span {
+        Span::with_file_id(start, end, self.file_id)
+    }
+
     fn current_mode(&self) -> LexMode {
         *self.mode_stack.last().unwrap_or(&LexMode::Normal)
     }
 
-    /// Tokenize the entire source, returning tokens and any errors.
-    pub fn tokenize(mut self) -> (Vec<Token>, Vec<String>) {
-        while !self.is_at_end() {
-            self.skip_whitespace_and_comments();
-            if self.is_at_end() {
-                break;
+    /// Lex and return the next token, or `None` after `Eof` has been
+    /// returned. Only the tokens of the current step are buffered, so a
+    /// parser can interleave lexing and parsing.
+    pub fn next_token(&mut self) -> Option<Token> {
+        while self.tokens.is_empty() {
+            if self.finished {
+                return None;
             }
+            self.lex_step();
+        }
+        Some(self.tokens.remove(0))
+    }
 
-            let ch = self.peek();
+    /// Errors encountered so far while lexing.
+    pub fn errors(&self) -> &[LexError] {
+        &self.errors
+    }
 
-            // In StringInterp mode, a closing } at depth 0 ends the interpolation
-            if let LexMode::StringInterp { brace_depth } = self.current_mode() {
-                if ch == b'}' && brace_depth == 0 {
-                    let start = self.pos;
-                    self.advance();
-                    self.push_token(TokenKind::InterpEnd, start, self.pos);
-                    self.mode_stack.pop();
-                    // Resume string lexing
-                    self.lex_string_continuation();
-                    continue;
+    /// Tokenize the entire source, returning tokens and any errors.
+    pub fn tokenize(self) -> (Vec<Token>, Vec<String>) {
+        let (tokens, errors) = self.tokenize_spanned();
+        (tokens, errors.into_iter().map(|e| e.message).collect())
+    }
+
+    /// Tokenize the entire source, collecting every lex error instead of
+    /// leaving `Error` tokens mid-statement. Each source line containing an
+    /// error is replaced by a single `Error` placeholder (spanning the first
+    /// bad token) so the parser can skip it and resume at the next line.
+    pub fn tokenize_with_recovery(self) -> (Vec<Token>, Vec<LexError>) {
+        let (tokens, errors) = self.tokenize_spanned();
+        if errors.is_empty() {
+            return (tokens, errors);
+        }
+
+        let mut recovered = Vec::with_capacity(tokens.len());
+        let mut line = Vec::new();
+        for token in tokens {
+            let at_line_end = matches!(token.kind, TokenKind::Newline | TokenKind::Eof);
+            line.push(token);
+            if at_line_end {
+                let end = line.pop().unwrap();
+                match line.iter().find(|t| t.kind == TokenKind::Error) {
+                    Some(error) => recovered.push(error.clone()),
+                    None => recovered.append(&mut line),
                 }
+                line.clear();
+                recovered.push(end);
             }
+        }
+        (recovered, errors)
+    }
 
-            match ch {
-                b'\n' => {
-                    let start = self.pos;
-                    self.advance();
-                    self.push_token(TokenKind::Newline, start, self.pos);
-                }
-                b'"' => self.lex_string_start(),
-                b'0'..=b'9' => self.lex_number(),
-                b'a'..=b'z' | b'A'..=b'Z' | b'_' => self.lex_identifier(),
-                b'(' => self.single_char_token(TokenKind::LParen),
-                b')' => self.single_char_token(TokenKind::RParen),
-                b'{' => {
-                    // Track brace depth in StringInterp mode
-                    if let LexMode::StringInterp { brace_depth } = self.current_mode() {
-                        *self.mode_stack.last_mut().unwrap() =
-                            LexMode::StringInterp { brace_depth: brace_depth + 1 };
-                    }
-                    self.single_char_token(TokenKind::LBrace);
+    fn tokenize_spanned(mut self) -> (Vec<Token>, Vec<LexError>) {
+        while !self.finished {
+            self.lex_step();
+        }
+        (self.tokens, self.errors)
+    }
+
+    /// Lex the next piece of source, pushing zero or more tokens. Pushes
+    /// `Eof` and sets `finished` once the source is exhausted.
+    fn lex_step(&mut self) {
+        if self.is_at_end() {
+            self.push_token(TokenKind::Eof, self.pos, self.pos);
+            self.finished = true;
+            return;
+        }
+
+        self.skip_whitespace_and_comments();
+        if self.is_at_end() {
+            return;
+        }
+
+        let ch = self.peek();
+
+        // In StringInterp mode, a closing } at depth 0 ends the interpolation
+        if let LexMode::StringInterp { brace_depth } = self.current_mode()
+            && ch == b'}'
+            && brace_depth == 0
+        {
+            let start = self.pos;
+            self.advance();
+            self.push_token(TokenKind::InterpEnd, start, self.pos);
+            self.mode_stack.pop();
+            // Resume string lexing
+            self.lex_string_continuation();
+            return;
+        }
+
+        match ch {
+            b'\n' => {
+                let start = self.pos;
+                self.advance();
+                self.push_token(TokenKind::Newline, start, self.pos);
+            }
+            b'"' => self.lex_string_start(),
+            b'0'..=b'9' => self.lex_number(),
+            b'a'..=b'z' | b'A'..=b'Z' | b'_' => self.lex_identifier(),
+            b'(' => self.single_char_token(TokenKind::LParen),
+            b')' => self.single_char_token(TokenKind::RParen),
+            b'{' => {
+                // Track brace depth in StringInterp mode
+                if let LexMode::StringInterp { brace_depth } = self.current_mode() {
+                    *self.mode_stack.last_mut().unwrap() =
+                        LexMode::StringInterp { brace_depth: brace_depth + 1 };
                 }
-                b'}' => {
-                    // Decrease brace depth in StringInterp mode
-                    if let LexMode::StringInterp { brace_depth } = self.current_mode() {
-                        if brace_depth > 0 {
-                            *self.mode_stack.last_mut().unwrap() =
-                                LexMode::StringInterp { brace_depth: brace_depth - 1 };
-                        }
-                    }
-                    self.single_char_token(TokenKind::RBrace);
+                self.single_char_token(TokenKind::LBrace);
+            }
+            b'}' => {
+                // Decrease brace depth in StringInterp mode
+                if let LexMode::StringInterp { brace_depth } = self.current_mode()
+                    && brace_depth > 0
+                {
+                    *self.mode_stack.last_mut().unwrap() =
+                        LexMode::StringInterp { brace_depth: brace_depth - 1 };
                 }
-                b'[' => self.single_char_token(TokenKind::LBracket),
-                b']' => self.single_char_token(TokenKind::RBracket),
-                b',' => self.single_char_token(TokenKind::Comma),
-                b':' => self.single_char_token(TokenKind::Colon),
-                b';' => self.single_char_token(TokenKind::Semicolon),
-                b'?' => self.single_char_token(TokenKind::Question),
-                b'%' => self.single_char_token(TokenKind::Percent),
-                b'*' => self.single_char_token(TokenKind::Star),
-                b'.' => {
-                    let start = self.pos;
+                self.single_char_token(TokenKind::RBrace);
+            }
+            b'[' => self.single_char_token(TokenKind::LBracket),
+            b']' => self.single_char_token(TokenKind::RBracket),
+            b',' => self.single_char_token(TokenKind::Comma),
+            b':' => self.single_char_token(TokenKind::Colon),
+            b';' => self.single_char_token(TokenKind::Semicolon),
+            b'?' => self.single_char_token(TokenKind::Question),
+            b'%' => self.single_char_token(TokenKind::Percent),
+            b'*' => self.single_char_token(TokenKind::Star),
+            b'.' => {
+                let start = self.pos;
+                self.advance();
+                if self.peek() == b'.' && self.peek_next() == b'.' {
                     self.advance();
-                    if self.peek() == b'.' {
-                        self.advance();
-                        self.push_token(TokenKind::DotDot, start, self.pos);
-                    } else {
-                        self.push_token(TokenKind::Dot, start, self.pos);
-                    }
-                }
-                b'+' => {
-                    let start = self.pos;
                     self.advance();
-                    if self.peek() == b'+' {
-                        self.advance();
-                        self.push_token(TokenKind::PlusPlus, start, self.pos);
-                    } else {
-                        self.push_token(TokenKind::Plus, start, self.pos);
-                    }
-                }
-                b'-' => {
-                    let start = self.pos;
+                    self.push_token(TokenKind::Ellipsis, start, self.pos);
+                } else if self.peek() == b'.' {
                     self.advance();
-                    if self.peek() == b'>' {
-                        self.advance();
-                        self.push_token(TokenKind::Arrow, start, self.pos);
-                    } else {
-                        self.push_token(TokenKind::Minus, start, self.pos);
-                    }
+                    self.push_token(TokenKind::DotDot, start, self.pos);
+                } else {
+                    self.push_token(TokenKind::Dot, start, self.pos);
                 }
-                b'/' => {
-                    let start = self.pos;
+            }
+            b'+' => {
+                let start = self.pos;
+                self.advance();
+                if self.peek() == b'+' {
                     self.advance();
-                    self.push_token(TokenKind::Slash, start, self.pos);
+                    self.push_token(TokenKind::PlusPlus, start, self.pos);
+                } else {
+                    self.push_token(TokenKind::Plus, start, self.pos);
                 }
-                b'=' => {
-                    let start = self.pos;
+            }
+            b'-' => {
+                let start = self.pos;
+                self.advance();
+                if self.peek() == b'>' {
                     self.advance();
-                    if self.peek() == b'=' {
-                        self.advance();
-                        self.push_token(TokenKind::EqEq, start, self.pos);
-                    } else if self.peek() == b'>' {
-                        self.advance();
-                        self.push_token(TokenKind::FatArrow, start, self.pos);
-                    } else {
-                        self.push_token(TokenKind::Assign, start, self.pos);
-                    }
+                    self.push_token(TokenKind::Arrow, start, self.pos);
+                } else {
+                    self.push_token(TokenKind::Minus, start, self.pos);
                 }
-                b'!' => {
-                    let start = self.pos;
+            }
+            b'/' => {
+                let start = self.pos;
+                self.advance();
+                self.push_token(TokenKind::Slash, start, self.pos);
+            }
+            b'=' => {
+                let start = self.pos;
+                self.advance();
+                if self.peek() == b'=' {
                     self.advance();
-                    if self.peek() == b'=' {
-                        self.advance();
-                        self.push_token(TokenKind::BangEq, start, self.pos);
-                    } else {
-                        self.errors.push(format!("unexpected character '!' at position {}", start));
-                        self.push_token(TokenKind::Error, start, self.pos);
-                    }
+                    self.push_token(TokenKind::EqEq, start, self.pos);
+                } else if self.peek() == b'>' {
+                    self.advance();
+                    self.push_token(TokenKind::FatArrow, start, self.pos);
+                } else {
+                    self.push_token(TokenKind::Assign, start, self.pos);
                 }
-                b'<' => {
-                    let start = self.pos;
+            }
+            b'!' => {
+                let start = self.pos;
+                self.advance();
+                if self.peek() == b'=' {
                     self.advance();
-                    if self.peek() == b'=' {
-                        self.advance();
-                        self.push_token(TokenKind::Lte, start, self.pos);
-                    } else if self.peek() == b'-' {
-                        self.advance();
-                        self.push_token(TokenKind::LeftArrow, start, self.pos);
-                    } else {
-                        self.push_token(TokenKind::Lt, start, self.pos);
-                    }
+                    self.push_token(TokenKind::BangEq, start, self.pos);
+                } else {
+                    self.push_error(format!("unexpected character '!' at position {}", start), start);
                 }
-                b'>' => {
-                    let start = self.pos;
+            }
+            b'<' => {
+                let start = self.pos;
+                self.advance();
+                if self.peek() == b'=' {
                     self.advance();
-                    if self.peek() == b'=' {
-                        self.advance();
-                        self.push_token(TokenKind::Gte, start, self.pos);
-                    } else {
-                        self.push_token(TokenKind::Gt, start, self.pos);
-                    }
+                    self.push_token(TokenKind::Lte, start, self.pos);
+                } else if self.peek() == b'-' {
+                    self.advance();
+                    self.push_token(TokenKind::LeftArrow, start, self.pos);
+                } else {
+                    self.push_token(TokenKind::Lt, start, self.pos);
                 }
-                _ => {
-                    let start = self.pos;
+            }
+            b'>' => {
+                let start = self.pos;
+                self.advance();
+                if self.peek() == b'=' {
                     self.advance();
-                    self.errors.push(format!(
-                        "unexpected character '{}' at position {}",
-                        ch as char, start
-                    ));
-                    self.push_token(TokenKind::Error, start, self.pos);
+                    self.push_token(TokenKind::Gte, start, self.pos);
+                } else {
+                    self.push_token(TokenKind::Gt, start, self.pos);
                 }
             }
+            _ => {
+                // Consume the whole (possibly multi-byte) character
+                let start = self.pos;
+                let bad = self.source[start..].chars().next().unwrap_or('\0');
+                self.pos += bad.len_utf8();
+                self.push_error(
+                    format!("unexpected character '{}' at position {}", bad, start),
+                    start,
+                );
+            }
         }
-
-        self.push_token(TokenKind::Eof, self.pos, self.pos);
-        (self.tokens, self.errors)
     }
 
     // =====================================================================
@@ -228,15 +318,14 @@ impl<'src> Lexer<'src> {
 
         while !self.is_at_end() && self.peek() != b'"' {
             if self.peek() == b'\n' {
-                self.errors.push(format!("unterminated string at position {}", start));
-                self.push_token(TokenKind::Error, start, self.pos);
+                self.push_error(format!("unterminated string at position {}", start), start);
                 return;
             }
 
             // Interpolation: unescaped { starts an expression
             if self.peek() == b'{' {
                 // Emit accumulated string part (even if empty, for consistent parsing)
-                let span = Span::new(start as u32, self.pos as u32);
+                let span = self.span(start as u32, self.pos as u32);
                 self.tokens.push(Token::new(TokenKind::StringLit, span, value));
 
                 // Emit InterpStart
@@ -259,26 +348,25 @@ impl<'src> Lexer<'src> {
                     b'\\' => value.push('\\'),
                     b'{' => value.push('{'),
                     b'}' => value.push('}'),
-                    other => {
+                    _ => {
                         value.push('\\');
-                        value.push(other as char);
+                        value.push(self.peek_char());
                     }
                 }
-                self.advance();
+                self.advance_char();
             } else {
-                value.push(self.advance() as char);
+                value.push(self.advance_char());
             }
         }
 
         if self.is_at_end() {
-            self.errors.push(format!("unterminated string at position {}", start));
-            self.push_token(TokenKind::Error, start, self.pos);
+            self.push_error(format!("unterminated string at position {}", start), start);
             return;
         }
 
         self.advance(); // consume closing "
 
-        let span = Span::new(start as u32, self.pos as u32);
+        let span = self.span(start as u32, self.pos as u32);
         self.tokens.push(Token::new(TokenKind::StringLit, span, value));
     }
 
@@ -293,17 +381,16 @@ impl<'src> Lexer<'src> {
                     self.advance(); // second "
                     self.advance(); // third "
 
-                    let span = Span::new(start as u32, self.pos as u32);
+                    let span = self.span(start as u32, self.pos as u32);
                     let trimmed = dedent_triple_string(&value);
                     self.tokens.push(Token::new(TokenKind::StringLit, span, trimmed));
                     return;
                 }
             }
-            value.push(self.advance() as char);
+            value.push(self.advance_char());
         }
 
-        self.errors.push(format!("unterminated triple-quoted string at position {}", start));
-        self.push_token(TokenKind::Error, start, self.pos);
+        self.push_error(format!("unterminated triple-quoted string at position {}", start), start);
     }
 
     // =====================================================================
@@ -328,15 +415,36 @@ impl<'src> Lexer<'src> {
         ch
     }
 
+    /// The full (possibly multi-byte) character at the current position.
+    fn peek_char(&self) -> char {
+        self.source[self.pos..].chars().next().unwrap_or('\0')
+    }
+
+    /// Consume one full character, so string contents stay valid UTF-8.
+    fn advance_char(&mut self) -> char {
+        let ch = self.peek_char();
+        self.pos += ch.len_utf8().max(1);
+        ch
+    }
+
     fn push_token(&mut self, kind: TokenKind, start: usize, end: usize) {
         let lexeme = self.source[start..end].to_string();
         self.tokens.push(Token::new(
             kind,
-            Span::new(start as u32, end as u32),
+            self.span(start as u32, end as u32),
             lexeme,
         ));
     }
 
+    /// Record an error spanning `start..pos` and emit a matching `Error` token.
+    fn push_error(&mut self, message: String, start: usize) {
+        self.errors.push(LexError {
+            message,
+            span: self.span(start as u32, self.pos as u32),
+        });
+        self.push_token(TokenKind::Error, start, self.pos);
+    }
+
     fn single_char_token(&mut self, kind: TokenKind) {
         let start = self.pos;
         self.advance();
@@ -349,17 +457,57 @@ impl<'src> Lexer<'src> {
                 b' ' | b'\t' | b'\r' => {
                     self.advance();
                 }
+                b'#' => {
+                    // Line comment (shell style): skip until newline
+                    while !self.is_at_end() && self.peek() != b'\n' {
+                        self.advance();
+                    }
+                }
                 b'/' if self.peek_next() == b'/' => {
                     // Line comment: skip until newline
                     while !self.is_at_end() && self.peek() != b'\n' {
                         self.advance();
                     }
                 }
+                b'/' if self.peek_next() == b'*' => self.skip_block_comment(),
                 _ => break,
             }
         }
     }
 
+    /// Skip a `/* ... */` comment, which may nest. A comment spanning lines
+    /// still ends the statement, so it leaves a single Newline token behind.
+    fn skip_block_comment(&mut self) {
+        let start = self.pos;
+        let mut newline_at = None;
+        self.advance(); // /
+        self.advance(); // *
+        let mut depth = 1;
+        while !self.is_at_end() {
+            if self.peek() == b'/' && self.peek_next() == b'*' {
+                self.advance();
+                self.advance();
+                depth += 1;
+            } else if self.peek() == b'*' && self.peek_next() == b'/' {
+                self.advance();
+                self.advance();
+                depth -= 1;
+                if depth == 0 {
+                    if let Some(pos) = newline_at {
+                        self.push_token(TokenKind::Newline, pos, pos + 1);
+                    }
+                    return;
+                }
+            } else {
+                if self.peek() == b'\n' && newline_at.is_none() {
+                    newline_at = Some(self.pos);
+                }
+                self.advance();
+            }
+        }
+        self.push_error(format!("unterminated block comment at position {}", start), start);
+    }
+
     fn lex_number(&mut self) {
         let start = self.pos;
         while !self.is_at_end() && self.peek().is_ascii_digit() {
@@ -386,9 +534,19 @@ impl<'src> Lexer<'src> {
     }
 }
 
+impl Iterator for Lexer<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        self.next_token()
+    }
+}
+
 /// Dedent a triple-quoted string by removing common leading whitespace.
 fn dedent_triple_string(s: &str) -> String {
-    let lines: Vec<&str> = s.lines().collect();
+    // `str::lines` splits on `\r\n`, but leaves the `\r` of a final line with
+    // no `\n` after it; drop it so it never leaks into the output
+    let lines: Vec<&str> = s.lines().map(|l| l.strip_suffix('\r').unwrap_or(l)).collect();
     if lines.is_empty() {
         return String::new();
     }
@@ -515,6 +673,64 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_hash_comments_skipped() {
+        assert_eq!(
+            kinds("# comment\nlet x = 1"),
+            vec![
+                TokenKind::Newline,
+                TokenKind::Let, TokenKind::Ident, TokenKind::Assign,
+                TokenKind::NumberLit, TokenKind::Eof,
+            ]
+        );
+        assert_eq!(
+            kinds("let x = 1 # inline comment\nemit x"),
+            vec![
+                TokenKind::Let, TokenKind::Ident, TokenKind::Assign,
+                TokenKind::NumberLit, TokenKind::Newline,
+                TokenKind::Emit, TokenKind::Ident, TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_block_comments_skipped() {
+        assert_eq!(
+            kinds("let x = 1 /* one\ntwo\nthree */ emit x"),
+            vec![
+                TokenKind::Let, TokenKind::Ident, TokenKind::Assign,
+                TokenKind::NumberLit, TokenKind::Newline,
+                TokenKind::Emit, TokenKind::Ident, TokenKind::Eof,
+            ]
+        );
+        assert_eq!(
+            kinds("/* nested /* comment */ still comment */ emit x"),
+            vec![TokenKind::Emit, TokenKind::Ident, TokenKind::Eof]
+        );
+    }
+
+    #[test]
+    fn test_block_comment_unterminated() {
+        let (_, errors) = Lexer::new("emit 1 /* never closed").tokenize();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("unterminated block comment at position 7"));
+    }
+
+    #[test]
+    fn test_block_comment_and_interpolation() {
+        // Comment markers inside a string are text; inside `{}` they are comments
+        let tokens = lex("\"a /* b */ {x /* note */} c\"");
+        let kinds: Vec<TokenKind> = tokens.iter().map(|t| t.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::StringLit, TokenKind::InterpStart, TokenKind::Ident,
+                TokenKind::InterpEnd, TokenKind::StringLit, TokenKind::Eof,
+            ]
+        );
+        assert_eq!(tokens[0].lexeme, "a /* b */ ");
+    }
+
     #[test]
     fn test_string_escapes() {
         let tokens = lex(r#""hello\nworld""#);
@@ -522,6 +738,46 @@ mod tests {
         assert_eq!(tokens[0].lexeme, "hello\nworld");
     }
 
+    #[test]
+    fn test_string_unicode() {
+        let tokens = lex(r#""café \é" """naïve""""#);
+        assert_eq!(tokens[0].lexeme, "café \\é");
+        assert_eq!(tokens[1].lexeme, "naïve");
+    }
+
+    #[test]
+    fn test_dots() {
+        assert_eq!(
+            kinds(". .. ..."),
+            vec![TokenKind::Dot, TokenKind::DotDot, TokenKind::Ellipsis, TokenKind::Eof]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_with_recovery() {
+        let source = "hello\nlet 💥 x = 1 !\nemit x";
+        let (tokens, errors) = Lexer::new(source).tokenize_with_recovery();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].message.contains("unexpected character '💥'"));
+        assert_eq!(errors[0].span, Span::new(10, 14));
+        assert!(errors[1].message.contains("unexpected character '!'"));
+
+        let kinds: Vec<TokenKind> = tokens.iter().map(|t| t.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Ident,
+                TokenKind::Newline,
+                TokenKind::Error,
+                TokenKind::Newline,
+                TokenKind::Emit,
+                TokenKind::Ident,
+                TokenKind::Eof,
+            ]
+        );
+        assert_eq!(tokens[2].span, errors[0].span);
+    }
+
     #[test]
     fn test_braces_and_brackets() {
         assert_eq!(
@@ -554,6 +810,30 @@ mod tests {
         assert_eq!(result, "hello\nworld");
     }
 
+    #[test]
+    fn test_dedent_triple_string_crlf() {
+        let input = "\r\n        hello\r\n          world\r\n    ";
+        let result = dedent_triple_string(input);
+        assert_eq!(result, "hello\n  world");
+        assert!(!result.contains('\r'));
+
+        // `str::lines` already splits on `\r\n`, but a lone `\r` at the very
+        // end, with no `\n` after it, stays on the last line
+        let input = "\r\n    hello\n      world\r";
+        assert_eq!(dedent_triple_string(input), "hello\n  world");
+        let input = "\n    hello\r\n    world\n    \r";
+        assert_eq!(dedent_triple_string(input), "hello\nworld");
+    }
+
+    #[test]
+    fn test_file_id_on_spans() {
+        let (first, _) = Lexer::new_with_file_id("emit 1", 1).tokenize_spanned();
+        let (second, _) = Lexer::new_with_file_id("emit 2", 2).tokenize_spanned();
+        assert!(first.iter().all(|t| t.span.file_id == 1));
+        assert!(second.iter().all(|t| t.span.file_id == 2));
+        assert_eq!(Lexer::new("emit 3").tokenize().0[0].span.file_id, 0);
+    }
+
     // String interpolation tests
 
     #[test]
@@ -632,4 +912,26 @@ mod tests {
         assert_eq!(tokens[0].kind, TokenKind::StringLit);
         assert_eq!(tokens[0].lexeme, "hello {world}");
     }
+
+    #[test]
+    fn test_iterator_matches_tokenize() {
+        let programs = [
+            "let x = 1\nemit x + 2\n",
+            "agent A {\n    fn f() -> str {\n        return \"hi {name}!\"\n    }\n}\n",
+            "/* block\n comment */\nlet s = \"\"\"\n    a\n    b\n\"\"\"\n# done\nemit s",
+            "let bad = @\nemit 1",
+        ];
+        for src in programs {
+            let (expected, _) = Lexer::new(src).tokenize();
+            let streamed: Vec<Token> = Lexer::new(src).collect();
+            assert_eq!(streamed, expected, "mismatch for {:?}", src);
+        }
+
+        let mut lexer = Lexer::new("emit @");
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Emit);
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Error);
+        assert_eq!(lexer.errors().len(), 1);
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Eof);
+        assert!(lexer.next_token().is_none());
+    }
 }
@@ -28,9 +28,11 @@ pub enum TokenKind {
 
     // Keywords
     Agent,
+    Extends,
     Tool,
     Pipeline,
     Stage,
+    Global,
     Fn,
     Let,
     Return,
@@ -42,13 +44,16 @@ pub enum TokenKind {
     Match,
     Try,
     Catch,
+    Finally,
     Throw,
+    Yield,
     Spawn,
     Send,
     Recv,
     Wait,
     Kill,
     Exec,
+    Batch,
     Assert,
     Retry,
     Emit,
@@ -96,6 +101,7 @@ pub enum TokenKind {
     LeftArrow, // <-
     Question,  // ?
     DotDot,    // ..
+    Ellipsis,  // ...
 
     // Operators
     Plus,     // +
@@ -133,9 +139,11 @@ impl TokenKind {
     pub fn keyword(ident: &str) -> Option<TokenKind> {
         match ident {
             "agent" => Some(TokenKind::Agent),
+            "extends" => Some(TokenKind::Extends),
             "tool" => Some(TokenKind::Tool),
             "pipeline" => Some(TokenKind::Pipeline),
             "stage" => Some(TokenKind::Stage),
+            "global" => Some(TokenKind::Global),
             "fn" => Some(TokenKind::Fn),
             "let" => Some(TokenKind::Let),
             "return" => Some(TokenKind::Return),
@@ -147,13 +155,16 @@ impl TokenKind {
             "match" => Some(TokenKind::Match),
             "try" => Some(TokenKind::Try),
             "catch" => Some(TokenKind::Catch),
+            "finally" => Some(TokenKind::Finally),
             "throw" => Some(TokenKind::Throw),
+            "yield" => Some(TokenKind::Yield),
             "spawn" => Some(TokenKind::Spawn),
             "send" => Some(TokenKind::Send),
             "recv" => Some(TokenKind::Recv),
             "wait" => Some(TokenKind::Wait),
             "kill" => Some(TokenKind::Kill),
             "exec" => Some(TokenKind::Exec),
+            "batch" => Some(TokenKind::Batch),
             "assert" => Some(TokenKind::Assert),
             "retry" => Some(TokenKind::Retry),
             "emit" => Some(TokenKind::Emit),
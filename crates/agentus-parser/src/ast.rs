@@ -7,6 +7,143 @@ pub struct Program {
     pub span: Span,
 }
 
+impl Program {
+    /// Iterate over top-level function definitions.
+    pub fn iter_fn_defs(&self) -> impl Iterator<Item = &FnDef> {
+        self.statements.iter().filter_map(|s| match s {
+            Stmt::FnDef(f) => Some(f),
+            _ => None,
+        })
+    }
+
+    /// Iterate over top-level agent definitions.
+    pub fn iter_agent_defs(&self) -> impl Iterator<Item = &AgentDef> {
+        self.statements.iter().filter_map(|s| match s {
+            Stmt::AgentDef(a) => Some(a),
+            _ => None,
+        })
+    }
+
+    /// Iterate over top-level tool definitions.
+    pub fn iter_tool_defs(&self) -> impl Iterator<Item = &ToolDef> {
+        self.statements.iter().filter_map(|s| match s {
+            Stmt::ToolDef(t) => Some(t),
+            _ => None,
+        })
+    }
+
+    /// Find a top-level function definition by name.
+    pub fn find_fn(&self, name: &str) -> Option<&FnDef> {
+        self.iter_fn_defs().find(|f| f.name == name)
+    }
+
+    /// The innermost AST node whose span contains the byte `offset`, for
+    /// cursor queries such as hover and go-to-definition.
+    pub fn find_node_at(&self, offset: u32) -> Option<AstNodeRef<'_>> {
+        let point = Span::point(offset);
+        self.statements.iter().find_map(|stmt| stmt_node_at(stmt, point))
+    }
+}
+
+/// A borrowed reference to a node found by [`Program::find_node_at`].
+#[derive(Debug, Clone, Copy)]
+pub enum AstNodeRef<'a> {
+    Stmt(&'a Stmt),
+    Expr(&'a Expr),
+    /// An agent method, which is not wrapped in a `Stmt`.
+    Method(&'a FnDef),
+    /// A variable name, either where it is bound (`let x`) or used.
+    Ident(&'a str),
+}
+
+fn stmts_node_at(stmts: &[Stmt], point: Span) -> Option<AstNodeRef<'_>> {
+    stmts.iter().find_map(|stmt| stmt_node_at(stmt, point))
+}
+
+fn exprs_node_at<'a>(exprs: impl IntoIterator<Item = &'a Expr>, point: Span) -> Option<AstNodeRef<'a>> {
+    exprs.into_iter().find_map(|expr| expr_node_at(expr, point))
+}
+
+fn stmt_node_at(stmt: &Stmt, point: Span) -> Option<AstNodeRef<'_>> {
+    // Children are searched even when the statement's span misses the
+    // point, since some spans stop short of the statement's body
+    let inner = match stmt {
+        Stmt::Let(l) if l.name_span.contains(point) => Some(AstNodeRef::Ident(&l.name)),
+        Stmt::Let(l) => expr_node_at(&l.value, point),
+        Stmt::Emit(e) => expr_node_at(&e.value, point),
+        Stmt::Return(r) => exprs_node_at(&r.value, point),
+        Stmt::ExprStmt(e) => expr_node_at(e, point),
+        Stmt::If(i) => expr_node_at(&i.condition, point)
+            .or_else(|| stmts_node_at(&i.then_body, point))
+            .or_else(|| i.else_body.as_deref().and_then(|body| stmts_node_at(body, point))),
+        Stmt::While(w) => {
+            expr_node_at(&w.condition, point).or_else(|| stmts_node_at(&w.body, point))
+        }
+        Stmt::For(f) => {
+            expr_node_at(&f.iterable, point).or_else(|| stmts_node_at(&f.body, point))
+        }
+        Stmt::FnDef(f) => stmts_node_at(&f.body, point),
+        Stmt::Assign(a) => expr_node_at(&a.value, point),
+        Stmt::AgentDef(a) => exprs_node_at(a.memory_fields.iter().filter_map(|f| f.default.as_ref()), point)
+            .or_else(|| {
+                a.methods.iter().find_map(|m| {
+                    stmts_node_at(&m.body, point)
+                        .or_else(|| m.span.contains(point).then_some(AstNodeRef::Method(m)))
+                })
+            }),
+        Stmt::FieldAssign(f) => exprs_node_at([&f.object, &f.value], point),
+        Stmt::ToolDef(t) => exprs_node_at(t.params.iter().filter_map(|p| p.default.as_ref()), point),
+        Stmt::PipelineDef(_) => None,
+        Stmt::GlobalDecl(g) => exprs_node_at(g.fields.iter().filter_map(|f| f.default.as_ref()), point),
+        Stmt::Send(s) => exprs_node_at([&s.target, &s.message], point),
+        Stmt::Kill(k) => expr_node_at(&k.target, point),
+        Stmt::IndexAssign(i) => exprs_node_at([&i.object, &i.index, &i.value], point),
+        Stmt::TryCatch(t) => stmts_node_at(&t.try_body, point)
+            .or_else(|| stmts_node_at(&t.catch_body, point))
+            .or_else(|| t.finally_body.as_deref().and_then(|body| stmts_node_at(body, point))),
+        Stmt::Throw(t) => expr_node_at(&t.value, point),
+        Stmt::Yield(y) => expr_node_at(&y.value, point),
+        Stmt::Assert(a) => {
+            expr_node_at(&a.condition, point).or_else(|| exprs_node_at(&a.message, point))
+        }
+    };
+    inner.or_else(|| stmt.span().contains(point).then_some(AstNodeRef::Stmt(stmt)))
+}
+
+fn expr_node_at(expr: &Expr, point: Span) -> Option<AstNodeRef<'_>> {
+    let inner = match expr {
+        Expr::StringLit(..) | Expr::NumberLit(..) | Expr::BoolLit(..) | Expr::NoneLit(_) => None,
+        Expr::Ident(name, span) => {
+            return span.contains(point).then_some(AstNodeRef::Ident(name));
+        }
+        Expr::TemplateLit(segments, _) => segments.iter().find_map(|seg| match seg {
+            TemplateSegment::Expr(e) | TemplateSegment::Formatted(e, _) => expr_node_at(e, point),
+            TemplateSegment::Literal(_) => None,
+        }),
+        Expr::BinOp(left, _, right, _) => exprs_node_at([&**left, &**right], point),
+        Expr::UnaryOp(_, e, _)
+        | Expr::FieldAccess(e, _, _)
+        | Expr::ExecBlock(e, _)
+        | Expr::Recv(e, _)
+        | Expr::Spread(e, _) => expr_node_at(e, point),
+        Expr::FnCall(_, args, _) | Expr::ListLit(args, _) | Expr::Batch(args, _) => {
+            exprs_node_at(args, point)
+        }
+        Expr::MethodCall(obj, _, args, _) => {
+            expr_node_at(obj, point).or_else(|| exprs_node_at(args, point))
+        }
+        Expr::IndexAccess(obj, index, _) => exprs_node_at([&**obj, &**index], point),
+        Expr::MapLit(pairs, _) => pairs
+            .iter()
+            .find_map(|(k, v)| exprs_node_at([k, v], point)),
+        Expr::Retry(attempts, body, _) => {
+            expr_node_at(attempts, point).or_else(|| stmts_node_at(body, point))
+        }
+        Expr::Lambda(_, body, _) => stmts_node_at(body, point),
+    };
+    inner.or_else(|| expr.span().contains(point).then_some(AstNodeRef::Expr(expr)))
+}
+
 /// A statement.
 #[derive(Debug, Clone)]
 pub enum Stmt {
@@ -28,20 +165,28 @@ pub enum Stmt {
     FnDef(FnDef),
     /// Variable assignment: `name = expr`
     Assign(AssignStmt),
-    /// Agent definition: `agent Name { ... }`
+    /// Agent definition: `agent Name { ... }` or `agent Name extends Base { ... }`
     AgentDef(AgentDef),
     /// Field assignment: `self.field = expr`
     FieldAssign(FieldAssignStmt),
     /// Tool definition: `tool name { ... }`
     ToolDef(ToolDef),
+    /// Pipeline definition: `pipeline Name { stage f ... }`
+    PipelineDef(PipelineDef),
+    /// Global memory declaration: `global { field: type = default ... }`
+    GlobalDecl(GlobalDecl),
     /// Send message: `send target, message`
     Send(SendStmt),
+    /// Terminate an agent: `kill target`
+    Kill(KillStmt),
     /// Index assignment: `collection[key] = value`
     IndexAssign(IndexAssignStmt),
-    /// Try/catch: `try { ... } catch err { ... }`
+    /// Try/catch: `try { ... } catch err { ... } finally { ... }`
     TryCatch(TryCatchStmt),
     /// Throw: `throw expr`
     Throw(ThrowStmt),
+    /// Yield: `yield expr` (suspends an agent method like a generator)
+    Yield(YieldStmt),
     /// Assert: `assert condition, "message"`
     Assert(AssertStmt),
 }
@@ -49,6 +194,8 @@ pub enum Stmt {
 #[derive(Debug, Clone)]
 pub struct LetStmt {
     pub name: String,
+    /// Span of the bound name alone.
+    pub name_span: Span,
     pub type_ann: Option<TypeExpr>,
     pub value: Expr,
     pub span: Span,
@@ -91,6 +238,9 @@ pub struct WhileStmt {
 #[derive(Debug, Clone)]
 pub struct ForStmt {
     pub variable: String,
+    /// Second name in `for k, v in x`, bound to each value while
+    /// `variable` takes the map key or list index.
+    pub value_variable: Option<String>,
     pub iterable: Expr,
     pub body: Vec<Stmt>,
     pub span: Span,
@@ -99,6 +249,8 @@ pub struct ForStmt {
 #[derive(Debug, Clone)]
 pub struct AgentDef {
     pub name: String,
+    /// Parent agent for `agent Name extends Base { ... }`.
+    pub extends: Option<String>,
     pub model: Option<String>,
     pub system_prompt: Option<String>,
     pub memory_fields: Vec<MemoryField>,
@@ -114,6 +266,13 @@ pub struct MemoryField {
     pub span: Span,
 }
 
+/// Fields shared by every agent and function in a VM, declared like agent memory.
+#[derive(Debug, Clone)]
+pub struct GlobalDecl {
+    pub fields: Vec<MemoryField>,
+    pub span: Span,
+}
+
 #[derive(Debug, Clone)]
 pub struct FieldAssignStmt {
     pub object: Expr,
@@ -122,6 +281,12 @@ pub struct FieldAssignStmt {
     pub span: Span,
 }
 
+#[derive(Debug, Clone)]
+pub struct KillStmt {
+    pub target: Expr,
+    pub span: Span,
+}
+
 #[derive(Debug, Clone)]
 pub struct SendStmt {
     pub target: Expr,
@@ -142,6 +307,7 @@ pub struct TryCatchStmt {
     pub try_body: Vec<Stmt>,
     pub catch_var: String,
     pub catch_body: Vec<Stmt>,
+    pub finally_body: Option<Vec<Stmt>>,
     pub span: Span,
 }
 
@@ -151,6 +317,12 @@ pub struct ThrowStmt {
     pub span: Span,
 }
 
+#[derive(Debug, Clone)]
+pub struct YieldStmt {
+    pub value: Expr,
+    pub span: Span,
+}
+
 #[derive(Debug, Clone)]
 pub struct AssertStmt {
     pub condition: Expr,
@@ -175,6 +347,20 @@ pub struct ToolParam {
     pub span: Span,
 }
 
+#[derive(Debug, Clone)]
+pub struct PipelineDef {
+    pub name: String,
+    pub stages: Vec<PipelineStage>,
+    pub span: Span,
+}
+
+/// A `stage fn_name` entry; each stage receives the previous stage's output.
+#[derive(Debug, Clone)]
+pub struct PipelineStage {
+    pub name: String,
+    pub span: Span,
+}
+
 #[derive(Debug, Clone)]
 pub struct FnDef {
     pub name: String,
@@ -208,6 +394,8 @@ pub enum TemplateSegment {
     Literal(String),
     /// An interpolated expression.
     Expr(Expr),
+    /// An interpolated expression with a format specifier: `{data:json}`.
+    Formatted(Expr, String),
 }
 
 /// An expression.
@@ -243,10 +431,44 @@ pub enum Expr {
     MapLit(Vec<(Expr, Expr)>, Span),
     /// Exec block: exec { prompt_expr }
     ExecBlock(Box<Expr>, Span),
+    /// Batched tool calls: batch { tool_a(x), tool_b(y) }
+    Batch(Vec<Expr>, Span),
     /// Recv expression: recv agent_handle
     Recv(Box<Expr>, Span),
     /// Retry expression: retry N { body }
     Retry(Box<Expr>, Vec<Stmt>, Span),
+    /// Spread argument: ...list (only valid in call arguments)
+    Spread(Box<Expr>, Span),
+    /// Anonymous function: fn(a, b) a + b, or fn(x) { body }
+    Lambda(Vec<String>, Vec<Stmt>, Span),
+}
+
+impl Stmt {
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Let(s) => s.span,
+            Stmt::Emit(s) => s.span,
+            Stmt::Return(s) => s.span,
+            Stmt::ExprStmt(e) => e.span(),
+            Stmt::If(s) => s.span,
+            Stmt::While(s) => s.span,
+            Stmt::For(s) => s.span,
+            Stmt::FnDef(s) => s.span,
+            Stmt::Assign(s) => s.span,
+            Stmt::AgentDef(s) => s.span,
+            Stmt::FieldAssign(s) => s.span,
+            Stmt::ToolDef(s) => s.span,
+            Stmt::PipelineDef(s) => s.span,
+            Stmt::GlobalDecl(s) => s.span,
+            Stmt::Send(s) => s.span,
+            Stmt::Kill(s) => s.span,
+            Stmt::IndexAssign(s) => s.span,
+            Stmt::TryCatch(s) => s.span,
+            Stmt::Throw(s) => s.span,
+            Stmt::Yield(s) => s.span,
+            Stmt::Assert(s) => s.span,
+        }
+    }
 }
 
 impl Expr {
@@ -267,8 +489,11 @@ impl Expr {
             Expr::ListLit(_, s) => *s,
             Expr::MapLit(_, s) => *s,
             Expr::ExecBlock(_, s) => *s,
+            Expr::Batch(_, s) => *s,
             Expr::Recv(_, s) => *s,
             Expr::Retry(_, _, s) => *s,
+            Expr::Spread(_, s) => *s,
+            Expr::Lambda(_, _, s) => *s,
         }
     }
 }
@@ -296,3 +521,58 @@ pub enum UnaryOp {
     Neg,
     Not,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{AstNodeRef, Expr, Stmt};
+    use crate::parser::parse;
+
+    #[test]
+    fn test_program_iterators() {
+        let src = r#"
+let x = 1
+fn double(n: num) -> num {
+    return n * 2
+}
+tool search {
+    param query: str
+}
+agent Helper {
+    fn greet() -> str {
+        return "hi"
+    }
+}
+fn triple(n: num) -> num {
+    return n * 3
+}
+emit x
+"#;
+        let program = parse(src).unwrap();
+        let fns: Vec<&str> = program.iter_fn_defs().map(|f| f.name.as_str()).collect();
+        assert_eq!(fns, vec!["double", "triple"]);
+        let agents: Vec<&str> = program.iter_agent_defs().map(|a| a.name.as_str()).collect();
+        assert_eq!(agents, vec!["Helper"]);
+        let tools: Vec<&str> = program.iter_tool_defs().map(|t| t.name.as_str()).collect();
+        assert_eq!(tools, vec!["search"]);
+
+        assert_eq!(program.find_fn("triple").map(|f| f.params.len()), Some(1));
+        // Agent methods are not top-level functions
+        assert!(program.find_fn("greet").is_none());
+    }
+
+    #[test]
+    fn test_find_node_at() {
+        let src = "let x = 42\nemit x + 1\n";
+        let program = parse(src).unwrap();
+        assert!(matches!(program.find_node_at(4), Some(AstNodeRef::Ident("x"))));
+        assert!(matches!(
+            program.find_node_at(9),
+            Some(AstNodeRef::Expr(Expr::NumberLit(n, _))) if *n == 42.0
+        ));
+        // The `x` inside `x + 1`
+        assert!(matches!(program.find_node_at(16), Some(AstNodeRef::Ident("x"))));
+        assert!(matches!(program.find_node_at(18), Some(AstNodeRef::Expr(Expr::BinOp(..)))));
+        assert!(matches!(program.find_node_at(11), Some(AstNodeRef::Stmt(Stmt::Emit(_)))));
+        assert!(program.find_node_at(100).is_none());
+    }
+}
@@ -1,3 +1,4 @@
+use agentus_common::errors::split_span;
 use agentus_common::span::Span;
 use agentus_lexer::token::{Token, TokenKind};
 use crate::ast::*;
@@ -18,13 +19,34 @@ impl Parser {
         }
     }
 
-    /// Parse the token stream into a Program.
-    pub fn parse(mut self) -> Result<Program, Vec<String>> {
+    /// Parse the token stream into a Program, failing if any errors occur.
+    pub fn parse(self) -> Result<Program, Vec<String>> {
+        let (program, errors) = self.parse_with_recovery();
+        if errors.is_empty() {
+            Ok(program)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Parse the token stream, returning every error alongside a partial
+    /// Program containing the statements that parsed successfully. Tooling
+    /// can use the partial AST even when the source has errors.
+    pub fn parse_with_recovery(mut self) -> (Program, Vec<String>) {
         let start_span = self.current_span();
         let mut statements = Vec::new();
 
         self.skip_newlines();
         while !self.is_at_end() {
+            // A line the lexer replaced with an `Error` placeholder
+            if self.current_kind() == TokenKind::Error {
+                let token = &self.tokens[self.pos];
+                let message = format!("invalid token '{}' at {:?}", token.lexeme, token.span);
+                self.errors.push(message);
+                self.synchronize();
+                self.skip_newlines();
+                continue;
+            }
             match self.parse_statement() {
                 Ok(stmt) => statements.push(stmt),
                 Err(e) => {
@@ -35,19 +57,37 @@ impl Parser {
             self.skip_newlines();
         }
 
-        if self.errors.is_empty() {
-            let end_span = if statements.is_empty() {
-                start_span
-            } else {
-                self.tokens.last().map(|t| t.span).unwrap_or(start_span)
-            };
-            Ok(Program {
-                statements,
-                span: start_span.merge(end_span),
-            })
+        let end_span = if statements.is_empty() {
+            start_span
         } else {
-            Err(self.errors)
+            self.tokens.last().map(|t| t.span).unwrap_or(start_span)
+        };
+        let program = Program {
+            statements,
+            span: start_span.merge(end_span),
+        };
+        (program, self.errors)
+    }
+
+    /// Lex and parse `source` as a single expression, for embedders that
+    /// evaluate expression strings rather than whole programs.
+    pub fn parse_expression_only(source: &str) -> Result<Expr, String> {
+        let (tokens, lex_errors) = agentus_lexer::lexer::Lexer::new(source).tokenize();
+        if !lex_errors.is_empty() {
+            return Err(lex_errors.join("; "));
         }
+        let mut parser = Parser::new(tokens);
+        parser.skip_newlines();
+        let expr = parser.parse_expression(0)?;
+        parser.skip_newlines();
+        if !parser.is_at_end() {
+            return Err(format!(
+                "expected end of expression, found {:?} at {:?}",
+                parser.current_kind(),
+                parser.current_span()
+            ));
+        }
+        Ok(expr)
     }
 
     // =====================================================================
@@ -65,59 +105,63 @@ impl Parser {
             TokenKind::Fn => self.parse_fn_def(),
             TokenKind::Agent => self.parse_agent_def(),
             TokenKind::Tool => self.parse_tool_def(),
+            TokenKind::Pipeline => self.parse_pipeline_def(),
+            TokenKind::Global => self.parse_global_decl(),
             TokenKind::Send => self.parse_send(),
+            TokenKind::Kill => self.parse_kill(),
             TokenKind::Try => self.parse_try_catch(),
             TokenKind::Throw => self.parse_throw(),
+            TokenKind::Yield => self.parse_yield(),
             TokenKind::Assert => self.parse_assert(),
             _ => {
                 // Try to parse as expression statement or assignment
                 let expr = self.parse_expression(0)?;
                 // Check for assignment
-                if let Expr::Ident(ref name, _) = expr {
-                    if self.current_kind() == TokenKind::Assign {
-                        let start_span = expr.span();
-                        self.advance(); // consume =
-                        let value = self.parse_expression(0)?;
-                        let span = start_span.merge(value.span());
-                        self.expect_statement_end()?;
-                        return Ok(Stmt::Assign(AssignStmt {
-                            name: name.clone(),
-                            value,
-                            span,
-                        }));
-                    }
+                if let Expr::Ident(ref name, _) = expr
+                    && self.current_kind() == TokenKind::Assign
+                {
+                    let start_span = expr.span();
+                    self.advance(); // consume =
+                    let value = self.parse_expression(0)?;
+                    let span = start_span.merge(value.span());
+                    self.expect_statement_end()?;
+                    return Ok(Stmt::Assign(AssignStmt {
+                        name: name.clone(),
+                        value,
+                        span,
+                    }));
                 }
                 // Check for field assignment: expr.field = value
-                if let Expr::FieldAccess(ref obj, ref field, _) = expr {
-                    if self.current_kind() == TokenKind::Assign {
-                        let start_span = expr.span();
-                        self.advance(); // consume =
-                        let value = self.parse_expression(0)?;
-                        let span = start_span.merge(value.span());
-                        self.expect_statement_end()?;
-                        return Ok(Stmt::FieldAssign(FieldAssignStmt {
-                            object: *obj.clone(),
-                            field: field.clone(),
-                            value,
-                            span,
-                        }));
-                    }
+                if let Expr::FieldAccess(ref obj, ref field, _) = expr
+                    && self.current_kind() == TokenKind::Assign
+                {
+                    let start_span = expr.span();
+                    self.advance(); // consume =
+                    let value = self.parse_expression(0)?;
+                    let span = start_span.merge(value.span());
+                    self.expect_statement_end()?;
+                    return Ok(Stmt::FieldAssign(FieldAssignStmt {
+                        object: *obj.clone(),
+                        field: field.clone(),
+                        value,
+                        span,
+                    }));
                 }
                 // Check for index assignment: expr[key] = value
-                if let Expr::IndexAccess(ref obj, ref index, _) = expr {
-                    if self.current_kind() == TokenKind::Assign {
-                        let start_span = expr.span();
-                        self.advance(); // consume =
-                        let value = self.parse_expression(0)?;
-                        let span = start_span.merge(value.span());
-                        self.expect_statement_end()?;
-                        return Ok(Stmt::IndexAssign(IndexAssignStmt {
-                            object: *obj.clone(),
-                            index: *index.clone(),
-                            value,
-                            span,
-                        }));
-                    }
+                if let Expr::IndexAccess(ref obj, ref index, _) = expr
+                    && self.current_kind() == TokenKind::Assign
+                {
+                    let start_span = expr.span();
+                    self.advance(); // consume =
+                    let value = self.parse_expression(0)?;
+                    let span = start_span.merge(value.span());
+                    self.expect_statement_end()?;
+                    return Ok(Stmt::IndexAssign(IndexAssignStmt {
+                        object: *obj.clone(),
+                        index: *index.clone(),
+                        value,
+                        span,
+                    }));
                 }
                 self.expect_statement_end()?;
                 Ok(Stmt::ExprStmt(expr))
@@ -130,6 +174,7 @@ impl Parser {
         self.expect(TokenKind::Let)?;
 
         let name = self.expect_ident()?;
+        let name_span = self.prev_span();
 
         // Optional type annotation
         let type_ann = if self.current_kind() == TokenKind::Colon {
@@ -146,6 +191,7 @@ impl Parser {
 
         Ok(Stmt::Let(LetStmt {
             name,
+            name_span,
             type_ann,
             value,
             span,
@@ -234,6 +280,12 @@ impl Parser {
         let start = self.current_span();
         self.expect(TokenKind::For)?;
         let variable = self.expect_ident()?;
+        let value_variable = if self.current_kind() == TokenKind::Comma {
+            self.advance();
+            Some(self.expect_ident()?)
+        } else {
+            Option::None
+        };
         self.expect(TokenKind::In)?;
         let iterable = self.parse_expression(0)?;
         self.expect(TokenKind::LBrace)?;
@@ -243,6 +295,7 @@ impl Parser {
         self.expect_statement_end()?;
         Ok(Stmt::For(ForStmt {
             variable,
+            value_variable,
             iterable,
             body,
             span,
@@ -283,6 +336,12 @@ impl Parser {
         let start = self.current_span();
         self.expect(TokenKind::Agent)?;
         let name = self.expect_ident()?;
+        let extends = if self.current_kind() == TokenKind::Extends {
+            self.advance();
+            Some(self.expect_ident()?)
+        } else {
+            None
+        };
         self.expect(TokenKind::LBrace)?;
         self.skip_newlines();
 
@@ -329,29 +388,7 @@ impl Parser {
                 }
                 TokenKind::Memory => {
                     self.advance(); // consume 'memory'
-                    self.expect(TokenKind::LBrace)?;
-                    self.skip_newlines();
-                    while self.current_kind() != TokenKind::RBrace && !self.is_at_end() {
-                        let field_start = self.current_span();
-                        let field_name = self.expect_ident()?;
-                        self.expect(TokenKind::Colon)?;
-                        let type_ann = self.parse_type()?;
-                        let default = if self.current_kind() == TokenKind::Assign {
-                            self.advance();
-                            Some(self.parse_expression(0)?)
-                        } else {
-                            Option::None
-                        };
-                        let field_span = field_start.merge(self.prev_span());
-                        memory_fields.push(MemoryField {
-                            name: field_name,
-                            type_ann,
-                            default,
-                            span: field_span,
-                        });
-                        self.skip_newlines();
-                    }
-                    self.expect(TokenKind::RBrace)?;
+                    memory_fields.extend(self.parse_memory_fields()?);
                     self.skip_newlines();
                 }
                 TokenKind::Fn => {
@@ -396,6 +433,7 @@ impl Parser {
 
         Ok(Stmt::AgentDef(AgentDef {
             name,
+            extends,
             model,
             system_prompt,
             memory_fields,
@@ -404,6 +442,44 @@ impl Parser {
         }))
     }
 
+    /// Parse a `{ name: type = default ... }` block of memory fields.
+    fn parse_memory_fields(&mut self) -> Result<Vec<MemoryField>, String> {
+        self.expect(TokenKind::LBrace)?;
+        self.skip_newlines();
+        let mut fields = Vec::new();
+        while self.current_kind() != TokenKind::RBrace && !self.is_at_end() {
+            let field_start = self.current_span();
+            let field_name = self.expect_ident()?;
+            self.expect(TokenKind::Colon)?;
+            let type_ann = self.parse_type()?;
+            let default = if self.current_kind() == TokenKind::Assign {
+                self.advance();
+                Some(self.parse_expression(0)?)
+            } else {
+                Option::None
+            };
+            let field_span = field_start.merge(self.prev_span());
+            fields.push(MemoryField {
+                name: field_name,
+                type_ann,
+                default,
+                span: field_span,
+            });
+            self.skip_newlines();
+        }
+        self.expect(TokenKind::RBrace)?;
+        Ok(fields)
+    }
+
+    fn parse_global_decl(&mut self) -> Result<Stmt, String> {
+        let start = self.current_span();
+        self.expect(TokenKind::Global)?;
+        let fields = self.parse_memory_fields()?;
+        let span = start.merge(self.prev_span());
+        self.expect_statement_end()?;
+        Ok(Stmt::GlobalDecl(GlobalDecl { fields, span }))
+    }
+
     fn parse_tool_def(&mut self) -> Result<Stmt, String> {
         let start = self.current_span();
         self.expect(TokenKind::Tool)?;
@@ -484,6 +560,43 @@ impl Parser {
         }))
     }
 
+    fn parse_pipeline_def(&mut self) -> Result<Stmt, String> {
+        let start = self.current_span();
+        self.expect(TokenKind::Pipeline)?;
+        let name = self.expect_ident()?;
+        self.expect(TokenKind::LBrace)?;
+        self.skip_newlines();
+
+        let mut stages = Vec::new();
+        while self.current_kind() != TokenKind::RBrace && !self.is_at_end() {
+            match self.current_kind() {
+                TokenKind::Stage => {
+                    let stage_start = self.current_span();
+                    self.advance(); // consume 'stage'
+                    let stage_name = self.expect_ident()?;
+                    stages.push(PipelineStage {
+                        name: stage_name,
+                        span: stage_start.merge(self.prev_span()),
+                    });
+                    self.skip_newlines();
+                }
+                _ => {
+                    return Err(format!(
+                        "unexpected token {:?} in pipeline definition at {:?}",
+                        self.current_kind(),
+                        self.current_span()
+                    ));
+                }
+            }
+        }
+
+        self.expect(TokenKind::RBrace)?;
+        let span = start.merge(self.prev_span());
+        self.expect_statement_end()?;
+
+        Ok(Stmt::PipelineDef(PipelineDef { name, stages, span }))
+    }
+
     fn parse_send(&mut self) -> Result<Stmt, String> {
         let start = self.current_span();
         self.expect(TokenKind::Send)?;
@@ -499,6 +612,15 @@ impl Parser {
         }))
     }
 
+    fn parse_kill(&mut self) -> Result<Stmt, String> {
+        let start = self.current_span();
+        self.expect(TokenKind::Kill)?;
+        let target = self.parse_expression(0)?;
+        let span = start.merge(target.span());
+        self.expect_statement_end()?;
+        Ok(Stmt::Kill(KillStmt { target, span }))
+    }
+
     fn parse_try_catch(&mut self) -> Result<Stmt, String> {
         let start = self.current_span();
         self.expect(TokenKind::Try)?;
@@ -511,12 +633,28 @@ impl Parser {
         self.expect(TokenKind::LBrace)?;
         let catch_body = self.parse_block()?;
         self.expect(TokenKind::RBrace)?;
+
+        // Optional `finally { ... }`, possibly on the next line
+        let before_finally = self.pos;
+        self.skip_newlines();
+        let finally_body = if self.current_kind() == TokenKind::Finally {
+            self.advance();
+            self.expect(TokenKind::LBrace)?;
+            let body = self.parse_block()?;
+            self.expect(TokenKind::RBrace)?;
+            Some(body)
+        } else {
+            self.pos = before_finally;
+            None
+        };
+
         let span = start.merge(self.prev_span());
         self.expect_statement_end()?;
         Ok(Stmt::TryCatch(TryCatchStmt {
             try_body,
             catch_var,
             catch_body,
+            finally_body,
             span,
         }))
     }
@@ -530,6 +668,15 @@ impl Parser {
         Ok(Stmt::Throw(ThrowStmt { value, span }))
     }
 
+    fn parse_yield(&mut self) -> Result<Stmt, String> {
+        let start = self.current_span();
+        self.expect(TokenKind::Yield)?;
+        let value = self.parse_expression(0)?;
+        let span = start.merge(value.span());
+        self.expect_statement_end()?;
+        Ok(Stmt::Yield(YieldStmt { value, span }))
+    }
+
     fn parse_assert(&mut self) -> Result<Stmt, String> {
         let start = self.current_span();
         self.expect(TokenKind::Assert)?;
@@ -556,6 +703,12 @@ impl Parser {
         }
 
         loop {
+            if self.current_kind() == TokenKind::SelfKw {
+                return Err(format!(
+                    "'self' cannot be used as a parameter name at {:?}",
+                    self.current_span()
+                ));
+            }
             let name = self.expect_ident()?;
             self.expect(TokenKind::Colon)?;
             let type_ann = self.parse_type()?;
@@ -727,7 +880,19 @@ impl Parser {
                     while self.current_kind() == TokenKind::InterpStart {
                         self.advance(); // consume InterpStart
                         let expr = self.parse_expression(0)?;
-                        segments.push(TemplateSegment::Expr(expr));
+                        if self.current_kind() == TokenKind::Colon {
+                            self.advance(); // consume :
+                            let spec = self.expect(TokenKind::Ident)?;
+                            if spec.lexeme != "json" {
+                                return Err(format!(
+                                    "unknown format specifier '{}' at {:?}",
+                                    spec.lexeme, spec.span
+                                ));
+                            }
+                            segments.push(TemplateSegment::Formatted(expr, spec.lexeme));
+                        } else {
+                            segments.push(TemplateSegment::Expr(expr));
+                        }
                         self.expect(TokenKind::InterpEnd)?;
 
                         // After InterpEnd, lexer produces another StringLit (possibly empty)
@@ -797,6 +962,15 @@ impl Parser {
                 let span = start.merge(self.prev_span());
                 Ok(Expr::ExecBlock(Box::new(prompt), span))
             }
+            TokenKind::Batch => {
+                let start = self.current_span();
+                self.advance(); // consume 'batch'
+                self.expect(TokenKind::LBrace)?;
+                let calls = self.parse_comma_separated_exprs(TokenKind::RBrace)?;
+                self.expect(TokenKind::RBrace)?;
+                let span = start.merge(self.prev_span());
+                Ok(Expr::Batch(calls, span))
+            }
             TokenKind::Recv => {
                 let start = self.current_span();
                 self.advance(); // consume 'recv'
@@ -814,6 +988,33 @@ impl Parser {
                 let span = start.merge(self.prev_span());
                 Ok(Expr::Retry(Box::new(attempts), body, span))
             }
+            TokenKind::Fn => {
+                let start = self.current_span();
+                self.advance(); // consume 'fn'
+                self.expect(TokenKind::LParen)?;
+                let mut params = Vec::new();
+                while self.current_kind() != TokenKind::RParen {
+                    params.push(self.expect_ident()?);
+                    if self.current_kind() != TokenKind::Comma {
+                        break;
+                    }
+                    self.advance(); // consume comma
+                }
+                self.expect(TokenKind::RParen)?;
+                let body = if self.current_kind() == TokenKind::LBrace {
+                    self.advance();
+                    let body = self.parse_block()?;
+                    self.expect(TokenKind::RBrace)?;
+                    body
+                } else {
+                    // Expression body: fn(x) x * 2
+                    let value = self.parse_expression(0)?;
+                    let span = value.span();
+                    vec![Stmt::Return(ReturnStmt { value: Some(value), span })]
+                };
+                let span = start.merge(self.prev_span());
+                Ok(Expr::Lambda(params, body, span))
+            }
             TokenKind::SelfKw => {
                 let span = self.current_span();
                 self.advance();
@@ -861,7 +1062,31 @@ impl Parser {
     }
 
     fn parse_call_args(&mut self) -> Result<Vec<Expr>, String> {
-        self.parse_comma_separated_exprs(TokenKind::RParen)
+        let mut args = Vec::new();
+        self.skip_newlines();
+        if self.current_kind() == TokenKind::RParen {
+            return Ok(args);
+        }
+
+        loop {
+            self.skip_newlines();
+            if self.current_kind() == TokenKind::Ellipsis {
+                let start = self.current_span();
+                self.advance(); // consume ...
+                let inner = self.parse_expression(0)?;
+                let span = start.merge(inner.span());
+                args.push(Expr::Spread(Box::new(inner), span));
+            } else {
+                args.push(self.parse_expression(0)?);
+            }
+            self.skip_newlines();
+            if self.current_kind() != TokenKind::Comma {
+                break;
+            }
+            self.advance(); // consume comma
+        }
+
+        Ok(args)
     }
 
     fn parse_comma_separated_exprs(&mut self, terminator: TokenKind) -> Result<Vec<Expr>, String> {
@@ -944,7 +1169,7 @@ impl Parser {
         self.tokens
             .get(self.pos)
             .map(|t| t.span)
-            .unwrap_or(Span::default())
+            .unwrap_or_default()
     }
 
     fn prev_span(&self) -> Span {
@@ -1036,7 +1261,7 @@ impl Parser {
             match self.current_kind() {
                 TokenKind::Let | TokenKind::Fn | TokenKind::If | TokenKind::While
                 | TokenKind::For | TokenKind::Return | TokenKind::Emit
-                | TokenKind::Agent | TokenKind::Tool => return,
+                | TokenKind::Agent | TokenKind::Tool | TokenKind::Pipeline | TokenKind::Global => return,
                 _ => self.advance(),
             }
         }
@@ -1059,6 +1284,19 @@ pub fn parse(source: &str) -> Result<Program, Vec<String>> {
     Parser::new(tokens).parse()
 }
 
+/// Convenience: parse source code with lexer and parser error recovery,
+/// returning the partial program and all errors found.
+pub fn parse_with_recovery(source: &str) -> (Program, Vec<String>) {
+    let (tokens, lex_errors) = agentus_lexer::lexer::Lexer::new(source).tokenize_with_recovery();
+    let (program, parse_errors) = Parser::new(tokens).parse_with_recovery();
+    // The lexer's message is more specific than the parser's for the same
+    // `Error` placeholder, so keep only the former
+    let lex_spans: Vec<Span> = lex_errors.iter().map(|e| e.span).collect();
+    let mut errors: Vec<String> = lex_errors.into_iter().map(|e| e.message).collect();
+    errors.extend(parse_errors.into_iter().filter(|e| !lex_spans.contains(&split_span(e).1)));
+    (program, errors)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1140,6 +1378,26 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_spread_argument() {
+        let program = parse("let x = foo(1, ...rest)").unwrap();
+        match &program.statements[0] {
+            Stmt::Let(l) => match &l.value {
+                Expr::FnCall(_, args, _) => {
+                    assert_eq!(args.len(), 2);
+                    match &args[1] {
+                        Expr::Spread(inner, _) => {
+                            assert!(matches!(inner.as_ref(), Expr::Ident(n, _) if n == "rest"))
+                        }
+                        other => panic!("expected spread, got {:?}", other),
+                    }
+                }
+                other => panic!("expected fn call, got {:?}", other),
+            },
+            other => panic!("expected let, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_parse_multiline() {
         let program = parse("let x = \"hello\"\nemit x").unwrap();
@@ -1178,4 +1436,77 @@ mod tests {
             other => panic!("expected let, got {:?}", other),
         }
     }
+
+    #[test]
+    fn test_parse_after_lex_recovery() {
+        let source = "hello\nlet 💥 x = 1\nemit x";
+        let (tokens, lex_errors) = agentus_lexer::lexer::Lexer::new(source).tokenize_with_recovery();
+        assert_eq!(lex_errors.len(), 1);
+        let (program, errors) = Parser::new(tokens.clone()).parse_with_recovery();
+        assert_eq!(program.statements.len(), 2);
+        assert!(matches!(&program.statements[1], Stmt::Emit(_)));
+        assert_eq!(errors.len(), 1);
+        let (message, span) = split_span(&errors[0]);
+        assert_eq!(message, "invalid token '💥'");
+        assert_eq!(span, lex_errors[0].span);
+        // The placeholder is not silently dropped by the strict entry point
+        assert!(Parser::new(tokens).parse().is_err());
+
+        let (_, errors) = parse_with_recovery(source);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0], lex_errors[0].message);
+    }
+
+    #[test]
+    fn test_parse_with_recovery_partial_program() {
+        let (program, errors) = parse_with_recovery("let x = 1\nlet = 2");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(program.statements.len(), 1);
+        match &program.statements[0] {
+            Stmt::Let(l) => assert_eq!(l.name, "x"),
+            other => panic!("expected let, got {:?}", other),
+        }
+        // The strict entry point still treats the error as fatal
+        assert!(parse("let x = 1\nlet = 2").is_err());
+    }
+
+    #[test]
+    fn test_parse_try_catch_finally() {
+        let src = "try {\n    emit 1\n} catch e {\n    emit e\n}\nfinally {\n    emit 2\n}\nemit 3";
+        let program = parse(src).unwrap();
+        assert_eq!(program.statements.len(), 2);
+        match &program.statements[0] {
+            Stmt::TryCatch(tc) => assert_eq!(tc.finally_body.as_ref().map(|b| b.len()), Some(1)),
+            other => panic!("expected try/catch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_pipeline_def() {
+        let program = parse("pipeline Process {\n    stage clean\n    stage summarize\n}").unwrap();
+        match &program.statements[0] {
+            Stmt::PipelineDef(p) => {
+                assert_eq!(p.name, "Process");
+                let stages: Vec<&str> = p.stages.iter().map(|s| s.name.as_str()).collect();
+                assert_eq!(stages, vec!["clean", "summarize"]);
+            }
+            other => panic!("expected pipeline def, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_expression_only() {
+        let expr = Parser::parse_expression_only("1 + 2 * 3\n").unwrap();
+        assert!(matches!(expr, Expr::BinOp(_, BinOp::Add, _, _)));
+        assert!(Parser::parse_expression_only("let x = 1").is_err());
+        assert!(Parser::parse_expression_only("1\n2").is_err());
+    }
+
+    #[test]
+    fn test_parse_self_parameter_rejected() {
+        let src = "agent Bot {\n    fn greet(self: str) -> str {\n        return self\n    }\n}";
+        let errors = parse(src).unwrap_err();
+        assert!(errors[0].contains("'self' cannot be used as a parameter name"));
+        assert!(parse("fn f(self: num) -> num {\n    return 1\n}").is_err());
+    }
 }
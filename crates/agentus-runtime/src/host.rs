@@ -1,3 +1,5 @@
+use crate::value::Value;
+
 /// A request to execute an LLM prompt.
 pub struct ExecRequest {
     pub model: String,
@@ -5,10 +7,25 @@ pub struct ExecRequest {
     pub user_prompt: String,
 }
 
+/// Receives (level, message) log lines streamed by a running tool.
+pub type LogCallback<'a> = &'a dyn Fn(u8, &str);
+
 /// A request to call a tool.
-pub struct ToolCallRequest {
+pub struct ToolCallRequest<'a> {
     pub tool_name: String,
     pub args: Vec<(String, String)>,
+    /// Log sink for the duration of the call. The VM wires this to its output
+    /// handler; it is single-threaded, so a borrow suffices.
+    pub log_callback: Option<LogCallback<'a>>,
+}
+
+impl ToolCallRequest<'_> {
+    /// Stream a log message to the caller, if it is listening.
+    pub fn log(&self, level: u8, message: &str) {
+        if let Some(callback) = self.log_callback {
+            callback(level, message);
+        }
+    }
 }
 
 /// The boundary between the VM and the outside world (LLM providers, tools).
@@ -16,8 +33,17 @@ pub trait HostInterface {
     /// Execute an LLM prompt and return the response text.
     fn exec(&self, request: ExecRequest) -> Result<String, String>;
 
-    /// Call a tool with named arguments and return the result text.
-    fn tool_call(&self, request: ToolCallRequest) -> Result<String, String>;
+    /// Call a tool with named arguments and return its result, usually a
+    /// string but possibly binary data as `Value::Bytes`.
+    /// Progress output may be streamed with `request.log` before returning.
+    fn tool_call(&self, request: ToolCallRequest<'_>) -> Result<Value, String>;
+
+    /// Call several tools at once, returning one result per request in
+    /// request order. Hosts that can run tools concurrently should override
+    /// this; the default calls `tool_call` for each request in turn.
+    fn batch_tool_call(&self, requests: Vec<ToolCallRequest<'_>>) -> Result<Vec<Value>, String> {
+        requests.into_iter().map(|request| self.tool_call(request)).collect()
+    }
 }
 
 /// Echo host: returns the user prompt as the response. For testing.
@@ -28,14 +54,14 @@ impl HostInterface for EchoHost {
         Ok(request.user_prompt)
     }
 
-    fn tool_call(&self, request: ToolCallRequest) -> Result<String, String> {
+    fn tool_call(&self, request: ToolCallRequest<'_>) -> Result<Value, String> {
         // Return a formatted string showing the tool call for testing
         let args_str: Vec<String> = request
             .args
             .iter()
             .map(|(k, v)| format!("{}={}", k, v))
             .collect();
-        Ok(format!("{}({})", request.tool_name, args_str.join(", ")))
+        Ok(Value::from_string(format!("{}({})", request.tool_name, args_str.join(", "))))
     }
 }
 
@@ -47,7 +73,7 @@ impl HostInterface for NoHost {
         Err("no host configured: cannot execute LLM prompts".to_string())
     }
 
-    fn tool_call(&self, _request: ToolCallRequest) -> Result<String, String> {
+    fn tool_call(&self, _request: ToolCallRequest<'_>) -> Result<Value, String> {
         Err("no host configured: cannot call tools".to_string())
     }
 }
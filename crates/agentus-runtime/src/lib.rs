@@ -1,3 +1,4 @@
 pub mod host;
+pub mod sandbox;
 pub mod vm;
 pub mod value;
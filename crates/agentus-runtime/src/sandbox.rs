@@ -0,0 +1,116 @@
+use std::collections::HashSet;
+use std::rc::Rc;
+use agentus_ir::opcode::OpCode;
+use crate::value::Value;
+
+/// Groups of opcodes that a sandbox can allow or deny as a whole.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OpCategory {
+    /// Computation, control flow, collections, and output. Always allowed.
+    Core,
+    /// LLM execution (`exec` blocks).
+    Exec,
+    /// Host tool calls.
+    ToolCall,
+    /// Agent lifecycle and messaging.
+    Agent,
+    /// Reading environment variables through `env`.
+    Env,
+}
+
+impl OpCategory {
+    /// The category an opcode belongs to.
+    pub fn of(op: OpCode) -> Self {
+        match op {
+            OpCode::Exec | OpCode::ExecStructured => OpCategory::Exec,
+            OpCode::TCall | OpCode::BatchTCall => OpCategory::ToolCall,
+            OpCode::Spawn
+            | OpCode::Send
+            | OpCode::Recv
+            | OpCode::RecvTimeout
+            | OpCode::Wait
+            | OpCode::Kill
+            | OpCode::Yield => OpCategory::Agent,
+            OpCode::LoadEnv => OpCategory::Env,
+            _ => OpCategory::Core,
+        }
+    }
+}
+
+/// Limits enforced by a VM created with `VM::new_sandboxed`.
+///
+/// The default budget allows only core opcodes and sets no numeric limits.
+#[derive(Debug, Clone)]
+pub struct ExecutionBudget {
+    /// Opcode categories the program may use; `Core` is always allowed.
+    pub allowed_categories: HashSet<OpCategory>,
+    /// Maximum number of instructions executed over the VM's lifetime.
+    pub max_instructions: Option<u64>,
+    /// Approximate upper bound on bytes held by live values.
+    pub max_heap_bytes: Option<usize>,
+    /// Maximum number of nested call frames.
+    pub max_call_depth: Option<usize>,
+}
+
+impl Default for ExecutionBudget {
+    fn default() -> Self {
+        Self {
+            allowed_categories: HashSet::from([OpCategory::Core]),
+            max_instructions: None,
+            max_heap_bytes: None,
+            max_call_depth: None,
+        }
+    }
+}
+
+impl ExecutionBudget {
+    /// Whether `op` may run under this budget.
+    pub fn allows(&self, op: OpCode) -> bool {
+        let category = OpCategory::of(op);
+        category == OpCategory::Core || self.allowed_categories.contains(&category)
+    }
+}
+
+/// Estimate the bytes held by `values`, counting each shared collection or
+/// string once (which also keeps self-referential lists finite).
+pub(crate) fn heap_bytes<'a>(values: impl IntoIterator<Item = &'a Value>) -> usize {
+    let mut seen = HashSet::new();
+    values.into_iter().map(|v| value_bytes(v, &mut seen)).sum()
+}
+
+fn value_bytes(value: &Value, seen: &mut HashSet<usize>) -> usize {
+    let slot = std::mem::size_of::<Value>();
+    match value {
+        Value::Str(s) | Value::Error(s) => {
+            if seen.insert(Rc::as_ptr(s) as usize) { slot + s.capacity() } else { slot }
+        }
+        Value::Bytes(b) => {
+            if seen.insert(Rc::as_ptr(b) as usize) { slot + b.capacity() } else { slot }
+        }
+        Value::List(l) => {
+            if !seen.insert(Rc::as_ptr(l) as usize) {
+                return slot;
+            }
+            let items = l.borrow();
+            slot + items.iter().map(|v| value_bytes(v, seen)).sum::<usize>()
+        }
+        Value::Map(m) => {
+            if !seen.insert(Rc::as_ptr(m) as usize) {
+                return slot;
+            }
+            let entries = m.borrow();
+            slot + entries
+                .iter()
+                .map(|(k, v)| std::mem::size_of::<String>() + k.capacity() + value_bytes(v, seen))
+                .sum::<usize>()
+        }
+        Value::Iterator(it) => {
+            if !seen.insert(Rc::as_ptr(it) as usize) {
+                return slot;
+            }
+            let state = it.borrow();
+            slot + state.0.iter().map(|v| value_bytes(v, seen)).sum::<usize>()
+        }
+        _ => slot,
+    }
+}
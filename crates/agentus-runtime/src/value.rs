@@ -1,4 +1,5 @@
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::rc::Rc;
 use std::cell::RefCell;
@@ -17,11 +18,27 @@ pub enum Value {
     Map(Rc<RefCell<HashMap<String, Value>>>),
     AgentHandle(u64),
     Error(Rc<String>),
+    /// Binary data, such as images or audio returned by tools.
+    Bytes(Rc<Vec<u8>>),
+    /// Reference to a function in the module (lambda or named function).
+    Function(u32),
+    /// The built-in `env` object; indexing it reads an environment variable.
+    Env,
     /// Internal iterator state: (source items, current index).
     Iterator(Rc<RefCell<(Vec<Value>, usize)>>),
 }
 
+/// How a value is rendered when converted to a string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoerceMode {
+    /// The `Display` rendering, as used by `emit` and string concatenation.
+    Display,
+    /// JSON serialization, as produced by `to_json`.
+    Json,
+}
+
 impl Value {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Self {
         Value::Str(Rc::new(s.to_string()))
     }
@@ -30,6 +47,10 @@ impl Value {
         Value::Str(Rc::new(s))
     }
 
+    pub fn from_bytes(data: Vec<u8>) -> Self {
+        Value::Bytes(Rc::new(data))
+    }
+
     pub fn is_truthy(&self) -> bool {
         match self {
             Value::None => false,
@@ -38,7 +59,10 @@ impl Value {
             Value::Str(s) => !s.is_empty(),
             Value::List(l) => !l.borrow().is_empty(),
             Value::Map(m) => !m.borrow().is_empty(),
+            Value::Bytes(b) => !b.is_empty(),
             Value::AgentHandle(_) => true,
+            Value::Function(_) => true,
+            Value::Env => true,
             Value::Error(_) => false,
             Value::Iterator(_) => true,
         }
@@ -64,6 +88,122 @@ impl Value {
             _ => Option::None,
         }
     }
+
+    pub fn is_none(&self) -> bool {
+        matches!(self, Value::None)
+    }
+
+    pub fn is_str(&self) -> bool {
+        matches!(self, Value::Str(_))
+    }
+
+    pub fn is_num(&self) -> bool {
+        matches!(self, Value::Num(_))
+    }
+
+    pub fn is_bool(&self) -> bool {
+        matches!(self, Value::Bool(_))
+    }
+
+    pub fn is_list(&self) -> bool {
+        matches!(self, Value::List(_))
+    }
+
+    pub fn is_map(&self) -> bool {
+        matches!(self, Value::Map(_))
+    }
+
+    /// Same as `as_str`; named to pair with `unwrap_str`.
+    pub fn try_as_str(&self) -> Option<&str> {
+        self.as_str()
+    }
+
+    /// Same as `as_num`; named to pair with `unwrap_num`.
+    pub fn try_as_num(&self) -> Option<f64> {
+        self.as_num()
+    }
+
+    /// Same as `as_bool`; named to pair with `unwrap_bool`.
+    pub fn try_as_bool(&self) -> Option<bool> {
+        self.as_bool()
+    }
+
+    /// The string contents, panicking if the value is not a string.
+    pub fn unwrap_str(&self) -> &str {
+        self.as_str().unwrap_or_else(|| {
+            panic!("called `Value::unwrap_str()` on a {} value", self.kind_name())
+        })
+    }
+
+    /// The number, panicking if the value is not a number.
+    pub fn unwrap_num(&self) -> f64 {
+        self.as_num().unwrap_or_else(|| {
+            panic!("called `Value::unwrap_num()` on a {} value", self.kind_name())
+        })
+    }
+
+    /// The boolean, panicking if the value is not a boolean.
+    pub fn unwrap_bool(&self) -> bool {
+        self.as_bool().unwrap_or_else(|| {
+            panic!("called `Value::unwrap_bool()` on a {} value", self.kind_name())
+        })
+    }
+
+    /// Short name of the variant, for panic messages.
+    fn kind_name(&self) -> &'static str {
+        match self {
+            Value::None => "none",
+            Value::Bool(_) => "bool",
+            Value::Num(_) => "num",
+            Value::Str(_) => "str",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::AgentHandle(_) => "agent",
+            Value::Error(_) => "error",
+            Value::Bytes(_) => "bytes",
+            Value::Function(_) => "fn",
+            Value::Env => "env",
+            Value::Iterator(_) => "iterator",
+        }
+    }
+
+    /// Total ordering used by `list.sort()`:
+    /// `none < false < true < numbers < strings < everything else`.
+    /// NaN sorts after all other numbers; unorderable values compare equal.
+    pub fn sort_order(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+            (Value::Num(a), Value::Num(b)) => match (a.is_nan(), b.is_nan()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+            },
+            (Value::Str(a), Value::Str(b)) => a.cmp(b),
+            _ => self.type_rank().cmp(&other.type_rank()),
+        }
+    }
+
+    /// Three-way comparison following `sort_order`: -1 if `a` sorts before
+    /// `b`, 1 if after, 0 otherwise.
+    pub fn compare(a: &Value, b: &Value) -> i32 {
+        match a.sort_order(b) {
+            Ordering::Less => -1,
+            Ordering::Equal => 0,
+            Ordering::Greater => 1,
+        }
+    }
+
+    /// Rank of each variant in `sort_order`.
+    fn type_rank(&self) -> u8 {
+        match self {
+            Value::None => 0,
+            Value::Bool(_) => 1,
+            Value::Num(_) => 2,
+            Value::Str(_) => 3,
+            _ => 4,
+        }
+    }
 }
 
 impl fmt::Display for Value {
@@ -101,7 +241,10 @@ impl fmt::Display for Value {
                 }
                 write!(f, "}}")
             }
+            Value::Bytes(b) => write!(f, "<bytes:{}>", b.len()),
             Value::AgentHandle(id) => write!(f, "<agent:{}>", id),
+            Value::Function(idx) => write!(f, "<fn:{}>", idx),
+            Value::Env => write!(f, "<env>"),
             Value::Error(e) => write!(f, "<error: {}>", e),
             Value::Iterator(_) => write!(f, "<iterator>"),
         }
@@ -109,6 +252,14 @@ impl fmt::Display for Value {
 }
 
 impl Value {
+    /// Convert any value to a string using the given mode.
+    pub fn coerce_to_str(&self, mode: CoerceMode) -> String {
+        match mode {
+            CoerceMode::Display => self.to_string(),
+            CoerceMode::Json => self.to_json(),
+        }
+    }
+
     /// Serialize this value to a JSON string.
     pub fn to_json(&self) -> String {
         match self {
@@ -150,12 +301,10 @@ impl Value {
                 }).collect();
                 format!("{{{}}}", parts.join(", "))
             }
+            Value::Bytes(b) => format!("\"{}\"", to_base64(b)),
             Value::AgentHandle(id) => format!("\"<agent:{}>\"", id),
-            Value::Error(e) => {
-                let escaped = Value::from_str(e).to_json();
-                escaped
-            }
-            Value::Iterator(_) => "null".to_string(),
+            Value::Error(e) => Value::from_str(e).to_json(),
+            Value::Function(_) | Value::Env | Value::Iterator(_) => "null".to_string(),
         }
     }
 
@@ -176,6 +325,56 @@ impl Value {
     }
 }
 
+// =====================================================================
+// Base64 (standard alphabet with padding, no external dependencies)
+// =====================================================================
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode bytes as standard padded base64.
+pub fn to_base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Decode standard padded base64, ignoring whitespace.
+pub fn from_base64(input: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<u8> = input.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if !digits.len().is_multiple_of(4) {
+        return Err("invalid base64: length is not a multiple of 4".to_string());
+    }
+    let mut out = Vec::with_capacity(digits.len() / 4 * 3);
+    for (i, chunk) in digits.chunks(4).enumerate() {
+        let is_last = i == digits.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|&&b| b == b'=').count();
+        if padding > 2 || (padding > 0 && !is_last) {
+            return Err("invalid base64: misplaced padding".to_string());
+        }
+        let mut n = 0u32;
+        for &b in &chunk[..4 - padding] {
+            let digit = BASE64_ALPHABET
+                .iter()
+                .position(|&c| c == b)
+                .ok_or_else(|| format!("invalid base64 character '{}'", b as char))?;
+            n = n << 6 | digit as u32;
+        }
+        n <<= 6 * padding as u32;
+        out.extend_from_slice(&n.to_be_bytes()[1..4 - padding]);
+    }
+    Ok(out)
+}
+
 // =====================================================================
 // Simple JSON parser (no external dependencies)
 // =====================================================================
@@ -369,13 +568,138 @@ fn json_parse_null(input: &[u8]) -> Result<(Value, &[u8]), String> {
 }
 
 impl PartialEq for Value {
+    /// Delegates to [`Value::deep_eq`], so comparing self-referential
+    /// collections (e.g. in `list.unique()`) terminates.
     fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
+        Value::deep_eq(self, other)
+    }
+}
+
+impl Value {
+    /// Structural equality that terminates on self-referential collections.
+    ///
+    /// Identical `Rc`s compare equal without being traversed. Otherwise each
+    /// pair of collections is marked as in progress while it is compared;
+    /// meeting an in-progress pair again means the walk has come back round a
+    /// cycle, and that pair is assumed equal so the rest of the structure
+    /// decides the result.
+    pub fn deep_eq(a: &Value, b: &Value) -> bool {
+        Self::deep_eq_visited(a, b, &mut HashSet::new())
+    }
+
+    fn deep_eq_visited(a: &Value, b: &Value, visited: &mut HashSet<(usize, usize)>) -> bool {
+        match (a, b) {
             (Value::None, Value::None) => true,
             (Value::Bool(a), Value::Bool(b)) => a == b,
             (Value::Num(a), Value::Num(b)) => a == b,
             (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Bytes(a), Value::Bytes(b)) => a == b,
+            (Value::List(x), Value::List(y)) => {
+                if Rc::ptr_eq(x, y) {
+                    return true;
+                }
+                let pair = (Rc::as_ptr(x) as usize, Rc::as_ptr(y) as usize);
+                if !visited.insert(pair) {
+                    return true;
+                }
+                let (x, y) = (x.borrow(), y.borrow());
+                let equal = x.len() == y.len()
+                    && x.iter().zip(y.iter()).all(|(l, r)| Self::deep_eq_visited(l, r, visited));
+                visited.remove(&pair);
+                equal
+            }
+            (Value::Map(x), Value::Map(y)) => {
+                if Rc::ptr_eq(x, y) {
+                    return true;
+                }
+                let pair = (Rc::as_ptr(x) as usize, Rc::as_ptr(y) as usize);
+                if !visited.insert(pair) {
+                    return true;
+                }
+                let (x, y) = (x.borrow(), y.borrow());
+                let equal = x.len() == y.len()
+                    && x.iter().all(|(k, l)| {
+                        y.get(k).is_some_and(|r| Self::deep_eq_visited(l, r, visited))
+                    });
+                visited.remove(&pair);
+                equal
+            }
+            (Value::AgentHandle(a), Value::AgentHandle(b)) => a == b,
+            (Value::Function(a), Value::Function(b)) => a == b,
             _ => false,
         }
     }
 }
+
+impl PartialOrd for Value {
+    /// Orders `None`, `Bool`, `Num`, and `Str` as in [`Value::sort_order`].
+    /// Collections, handles, and NaN-vs-NaN are unordered.
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if self.type_rank() > 3 || other.type_rank() > 3 {
+            return Option::None;
+        }
+        if let (Value::Num(a), Value::Num(b)) = (self, other)
+            && a.is_nan()
+            && b.is_nan()
+        {
+            return Option::None;
+        }
+        Some(self.sort_order(other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_predicates() {
+        let list = Value::List(Rc::new(RefCell::new(vec![])));
+        let map = Value::Map(Rc::new(RefCell::new(HashMap::new())));
+        assert!(Value::None.is_none());
+        assert!(Value::from_str("a").is_str());
+        assert!(Value::Num(1.0).is_num());
+        assert!(Value::Bool(false).is_bool());
+        assert!(list.is_list());
+        assert!(map.is_map());
+        assert!(!Value::Num(1.0).is_str());
+        assert!(!list.is_map());
+        assert!(!Value::Bool(true).is_none());
+
+        assert_eq!(Value::from_str("hi").unwrap_str(), "hi");
+        assert_eq!(Value::Num(2.5).unwrap_num(), 2.5);
+        assert!(Value::Bool(true).unwrap_bool());
+        assert_eq!(Value::Num(1.0).try_as_str(), None);
+        assert_eq!(Value::Num(1.0).try_as_num(), Some(1.0));
+        assert_eq!(Value::None.try_as_bool(), None);
+    }
+
+    #[test]
+    fn test_deep_eq_shared_and_cyclic() {
+        let list = |items: Vec<Value>| Value::List(Rc::new(RefCell::new(items)));
+
+        // The same inner list appearing twice on each side
+        let i1 = list(vec![Value::Num(1.0)]);
+        let i2 = list(vec![Value::Num(1.0)]);
+        assert_eq!(list(vec![i1.clone(), i1.clone()]), list(vec![i2.clone(), i2.clone()]));
+        assert_ne!(list(vec![i1.clone(), i1]), list(vec![i2, list(vec![Value::Num(2.0)])]));
+
+        // Two lists that each contain themselves
+        let a = list(vec![Value::Num(1.0)]);
+        let b = list(vec![Value::Num(1.0)]);
+        let c = list(vec![Value::Num(2.0)]);
+        for v in [&a, &b, &c] {
+            if let Value::List(items) = v {
+                items.borrow_mut().push(v.clone());
+            }
+        }
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    #[should_panic(expected = "called `Value::unwrap_str()` on a num value")]
+    fn test_unwrap_str_on_num_panics() {
+        Value::Num(3.0).unwrap_str();
+    }
+}
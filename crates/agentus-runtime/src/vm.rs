@@ -1,8 +1,12 @@
+use std::cmp::Ordering;
 use std::collections::{HashMap, VecDeque};
-use agentus_ir::module::{Constant, Module};
+use std::rc::Rc;
+use agentus_common::errors::AgentusError;
+use agentus_ir::module::{AgentDescriptor, Constant, Module};
 use agentus_ir::opcode::OpCode;
 use crate::host::{ExecRequest, HostInterface, NoHost, ToolCallRequest};
-use crate::value::Value;
+use crate::sandbox::{heap_bytes, ExecutionBudget};
+use crate::value::{from_base64, to_base64, CoerceMode, Value};
 
 /// Output handler for the VM.
 pub trait OutputHandler {
@@ -39,6 +43,8 @@ struct AgentInstance {
     memory: HashMap<String, Value>,
     /// Message mailbox for inter-agent communication.
     mailbox: VecDeque<Value>,
+    /// Suspended generator frames, keyed by method function index.
+    coroutines: HashMap<u32, CallFrame>,
 }
 
 /// A call frame / activation record.
@@ -50,6 +56,8 @@ struct CallFrame {
     /// Program counter (instruction index).
     pc: usize,
     /// Return address: (function_idx, pc, return_register) of the caller.
+    /// The caller resumes from its own frame's `pc`, which already points past
+    /// the call's extra data words when this frame is pushed.
     return_info: Option<(u32, usize, u8)>,
     /// Which agent instance this frame belongs to (for method calls).
     agent_id: Option<u64>,
@@ -68,6 +76,17 @@ struct ErrorHandler {
     function_idx: u32,
 }
 
+/// Outcome of executing a single instruction with [`VM::step`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepResult {
+    /// The program can keep running.
+    Continue,
+    /// The program reached `Halt` or returned from its entry function.
+    Halted,
+    /// The instruction failed with a runtime error.
+    Error(String),
+}
+
 /// The Agentus Virtual Machine.
 pub struct VM {
     module: Module,
@@ -85,12 +104,68 @@ pub struct VM {
     host: Box<dyn HostInterface>,
     /// Error handler stack for try/catch.
     error_handlers: Vec<ErrorHandler>,
-    /// Current error value (set by throw, read by GetError).
+    /// Current error value (set by throw, read by GetError). User `throw`s keep
+    /// their original value; `parse_json` and `from_base64` failures throw a
+    /// `Value::Error`. Other VM errors are not thrown and cannot be caught.
     current_error: Option<Value>,
+    /// Top-level function name → function index, for `call_function`.
+    function_names: HashMap<String, u32>,
+    /// Value returned by the outermost frame (read by `call_function`).
+    return_value: Option<Value>,
+    /// Global memory shared by all agents (GLoad/GStore), pre-loaded with
+    /// built-ins like `args`.
+    global_memory: HashMap<String, Value>,
+    /// Whether the entry frame has been pushed by `run` or `step`.
+    started: bool,
+    /// Whether `step` has reached the end of the program.
+    halted: bool,
+    /// Limits checked before each instruction (set by `new_sandboxed`).
+    budget: Option<ExecutionBudget>,
+    /// Instructions executed so far, counted only under a budget.
+    instructions_executed: u64,
+    /// Maximum number of call frames, so runaway recursion fails cleanly.
+    max_call_depth: usize,
+    /// Call stacks set aside by `call_isolated`. Their frames still count
+    /// toward the call depth.
+    suspended_stacks: Vec<Vec<CallFrame>>,
+    /// Number of `call_nested` calls in progress.
+    callback_depth: usize,
+    /// Call stack length when the running callback (see `call_nested`) was
+    /// entered. A `throw` inside it can only use handlers above this depth.
+    callback_base: usize,
+    /// A throw that escaped a callback, with the `callback_base` it escaped
+    /// from, re-raised once the instruction that ran the callback has failed.
+    escaped_throw: Option<(usize, Value)>,
 }
 
+/// How often (in instructions) a sandboxed VM re-measures its heap usage.
+const HEAP_CHECK_INTERVAL: u64 = 64;
+
+/// Default for `VM::with_call_stack_limit`.
+const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+/// Maximum nesting of callbacks run from inside an instruction. Each level
+/// recurses on the Rust stack, so it is capped well below the frame limit.
+const MAX_CALLBACK_DEPTH: usize = 32;
+
 impl VM {
     pub fn new(module: Module) -> Self {
+        // Agent methods share the function table but are not callable by name
+        let method_indices: Vec<u32> = module
+            .agents
+            .iter()
+            .flat_map(|agent| agent.methods.iter().map(|&(_, func_idx)| func_idx))
+            .collect();
+        let mut function_names = HashMap::new();
+        for (idx, func) in module.functions.iter().enumerate() {
+            if method_indices.contains(&(idx as u32)) {
+                continue;
+            }
+            if let Some(Constant::Str(name)) = func.name_constant().and_then(|idx| module.get_constant(idx)) {
+                function_names.entry(name.clone()).or_insert(idx as u32);
+            }
+        }
+
         Self {
             module,
             call_stack: Vec::new(),
@@ -101,9 +176,40 @@ impl VM {
             host: Box::new(NoHost),
             error_handlers: Vec::new(),
             current_error: None,
+            function_names,
+            return_value: None,
+            global_memory: HashMap::from([(
+                "args".to_string(),
+                Value::List(Rc::new(std::cell::RefCell::new(Vec::new()))),
+            )]),
+            started: false,
+            halted: false,
+            budget: None,
+            instructions_executed: 0,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            suspended_stacks: Vec::new(),
+            callback_depth: 0,
+            callback_base: 0,
+            escaped_throw: None,
         }
     }
 
+    /// Create a VM that refuses to run past `budget`. A violation stops the
+    /// program with an `AgentusError::Sandboxed` error that `try`/`catch`
+    /// cannot intercept.
+    pub fn new_sandboxed(module: Module, budget: ExecutionBudget) -> Self {
+        let mut vm = Self::new(module);
+        vm.budget = Some(budget);
+        vm
+    }
+
+    /// Limit the number of nested call frames (1000 by default). Calls past
+    /// the limit fail with "call stack depth exceeded".
+    pub fn with_call_stack_limit(mut self, depth: usize) -> Self {
+        self.max_call_depth = depth;
+        self
+    }
+
     pub fn with_output(mut self, handler: Box<dyn OutputHandler>) -> Self {
         self.output = handler;
         self
@@ -114,36 +220,308 @@ impl VM {
         self
     }
 
+    /// Set the script arguments exposed to the program as the `args` list.
+    pub fn with_args(mut self, args: Vec<String>) -> Self {
+        let items = args.into_iter().map(Value::from_string).collect();
+        self.global_memory.insert(
+            "args".to_string(),
+            Value::List(Rc::new(std::cell::RefCell::new(items))),
+        );
+        self
+    }
+
     /// Get all emitted outputs (for testing).
     pub fn get_outputs(&self) -> &[Value] {
         &self.outputs
     }
 
+    /// Get the memory of a live agent instance (for testing/inspection).
+    pub fn get_agent_memory(&self, agent_id: u64) -> Option<&HashMap<String, Value>> {
+        self.agents.get(&agent_id).map(|agent| &agent.memory)
+    }
+
+    /// Get mutable access to the memory of a live agent instance.
+    pub fn get_agent_memory_mut(&mut self, agent_id: u64) -> Option<&mut HashMap<String, Value>> {
+        self.agents.get_mut(&agent_id).map(|agent| &mut agent.memory)
+    }
+
+    /// Get the IDs of all live agent instances, in ascending order.
+    pub fn get_agent_ids(&self) -> Vec<u64> {
+        let mut ids: Vec<u64> = self.agents.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
     /// Run the module from its entry function.
-    pub fn run(&mut self) -> Result<(), String> {
-        let entry = self.module.entry_function;
+    pub fn run(&mut self) -> Result<(), AgentusError> {
+        self.start()?;
+        self.execute()
+    }
+
+    /// Execute a single instruction, starting the entry function on the
+    /// first call. Once the program halts or reports an error, further calls
+    /// keep returning `Halted`.
+    pub fn step(&mut self) -> Result<StepResult, AgentusError> {
+        if !self.started {
+            self.start()?;
+        }
+        if self.halted {
+            return Ok(StepResult::Halted);
+        }
+        match self.step_instruction() {
+            Ok(StepResult::Halted) => {
+                self.halted = true;
+                Ok(StepResult::Halted)
+            }
+            Ok(result) => Ok(result),
+            Err(e) => {
+                self.halted = true;
+                Ok(StepResult::Error(e.message()))
+            }
+        }
+    }
+
+    /// Read a register of the currently executing frame (for debuggers).
+    pub fn get_register_value(&self, idx: usize) -> Option<&Value> {
+        self.call_stack.last().and_then(|frame| frame.registers.get(idx))
+    }
+
+    /// Run the named entry point (see `Module::named_entry_points`) instead
+    /// of the module's default entry function.
+    pub fn run_entry(&mut self, name: &str) -> Result<(), AgentusError> {
+        let entry = *self
+            .module
+            .named_entry_points
+            .get(name)
+            .ok_or_else(|| AgentusError::runtime(format!("undefined entry point '{}'", name)))?;
         self.push_frame(entry, Option::None)?;
+        self.started = true;
         self.execute()
     }
 
+    fn start(&mut self) -> Result<(), AgentusError> {
+        let entry = self.module.entry_function;
+        self.push_frame(entry, Option::None)?;
+        self.started = true;
+        Ok(())
+    }
+
+    /// Call a top-level function by name with the given arguments, without
+    /// running the entry program. Returns the function's return value.
+    pub fn call_function(
+        &mut self,
+        func_name: &str,
+        args: &[Value],
+    ) -> Result<Value, AgentusError> {
+        let func_idx = *self
+            .function_names
+            .get(func_name)
+            .ok_or_else(|| AgentusError::runtime(format!("undefined function '{}'", func_name)))?;
+        let num_params = self.module.functions[func_idx as usize].num_params as usize;
+        if args.len() != num_params {
+            return Err(AgentusError::runtime(format!(
+                "function '{}' expects {} arguments, got {}",
+                func_name,
+                num_params,
+                args.len()
+            )));
+        }
+        self.call_isolated(func_idx, None, args)
+    }
+
+    /// Call a method on a live agent instance from Rust, like `call_function`.
+    pub fn call_method(
+        &mut self,
+        agent_id: u64,
+        method_name: &str,
+        args: &[Value],
+    ) -> Result<Value, AgentusError> {
+        let agent = self
+            .agents
+            .get(&agent_id)
+            .ok_or_else(|| AgentusError::runtime(format!("agent {} not found", agent_id)))?;
+        let descriptor = self
+            .module
+            .get_agent(agent.descriptor_idx)
+            .ok_or_else(|| {
+                AgentusError::runtime(format!(
+                    "agent descriptor {} not found",
+                    agent.descriptor_idx
+                ))
+            })?;
+        let func_idx = descriptor
+            .methods
+            .iter()
+            .find(|(name_idx, _)| {
+                self.load_constant_str(*name_idx).ok().as_deref() == Some(method_name)
+            })
+            .map(|(_, idx)| *idx)
+            .ok_or_else(|| {
+                AgentusError::runtime(format!("method '{}' not found on agent", method_name))
+            })?;
+        self.check_method_arity(func_idx, method_name, args.len())?;
+        self.call_isolated(func_idx, Some(agent_id), args)
+    }
+
+    /// Spawn an agent by descriptor name without running `Spawn` bytecode.
+    /// Returns the new instance's ID.
+    pub fn spawn_agent(&mut self, descriptor_name: &str) -> Result<u64, AgentusError> {
+        let descriptor_idx = self
+            .module
+            .agents
+            .iter()
+            .position(|desc| self.load_constant_str(desc.name_idx).ok().as_deref() == Some(descriptor_name))
+            .ok_or_else(|| {
+                AgentusError::runtime(format!("undefined agent '{}'", descriptor_name))
+            })?;
+        self.spawn_instance(descriptor_idx as u32)
+    }
+
+    /// Set one memory field of a live agent instance.
+    pub fn set_agent_memory(
+        &mut self,
+        id: u64,
+        field: &str,
+        value: Value,
+    ) -> Result<(), AgentusError> {
+        let memory = self
+            .get_agent_memory_mut(id)
+            .ok_or_else(|| AgentusError::runtime(format!("agent {} not found", id)))?;
+        memory.insert(field.to_string(), value);
+        Ok(())
+    }
+
+    /// Set several memory fields of a live agent instance at once.
+    pub fn set_agent_memory_fields(
+        &mut self,
+        id: u64,
+        fields: HashMap<String, Value>,
+    ) -> Result<(), AgentusError> {
+        let memory = self
+            .get_agent_memory_mut(id)
+            .ok_or_else(|| AgentusError::runtime(format!("agent {} not found", id)))?;
+        memory.extend(fields);
+        Ok(())
+    }
+
+    /// Run a single function to completion on a fresh stack, so a halted or
+    /// in-progress program is untouched, and return its return value.
+    fn call_isolated(
+        &mut self,
+        func_idx: u32,
+        agent_id: Option<u64>,
+        args: &[Value],
+    ) -> Result<Value, AgentusError> {
+        let saved_stack = std::mem::take(&mut self.call_stack);
+        self.suspended_stacks.push(saved_stack);
+        let saved_handlers = std::mem::take(&mut self.error_handlers);
+        let saved_base = std::mem::replace(&mut self.callback_base, 0);
+        self.return_value = None;
+
+        let result = self
+            .push_call_frame(func_idx, None, agent_id, args.to_vec())
+            .and_then(|_| self.execute());
+
+        self.call_stack = self.suspended_stacks.pop().unwrap_or_default();
+        self.error_handlers = saved_handlers;
+        self.callback_base = saved_base;
+        result?;
+        Ok(self.return_value.take().unwrap_or(Value::None))
+    }
+
+    /// Run a function to completion on top of the live call stack while an
+    /// instruction is executing (callbacks, pipeline stages, lifecycle
+    /// hooks), and return its return value. The callee counts toward the call
+    /// depth, and a throw it doesn't catch reaches the caller's `try`.
+    fn call_nested(
+        &mut self,
+        func_idx: u32,
+        agent_id: Option<u64>,
+        args: Vec<Value>,
+    ) -> Result<Value, AgentusError> {
+        if self.callback_depth >= MAX_CALLBACK_DEPTH {
+            return Err(AgentusError::runtime("call stack depth exceeded"));
+        }
+        let base = self.call_stack.len();
+        let num_handlers = self.error_handlers.len();
+        let saved_base = std::mem::replace(&mut self.callback_base, base);
+        self.callback_depth += 1;
+        self.return_value = None;
+
+        let result = self.push_call_frame(func_idx, None, agent_id, args).and_then(|_| {
+            while self.call_stack.len() > base {
+                if self.step_instruction()? == StepResult::Halted {
+                    break;
+                }
+            }
+            Ok(())
+        });
+
+        self.callback_depth -= 1;
+        self.callback_base = saved_base;
+        self.call_stack.truncate(base);
+        if result.is_err() {
+            self.error_handlers.truncate(num_handlers);
+        }
+        result?;
+        Ok(self.return_value.take().unwrap_or(Value::None))
+    }
+
     fn push_frame(
         &mut self,
         function_idx: u32,
         return_info: Option<(u32, usize, u8)>,
-    ) -> Result<(), String> {
+    ) -> Result<(), AgentusError> {
         self.push_frame_with_agent(function_idx, return_info, None)
     }
 
+    /// Push a frame for a call and copy the arguments into its registers.
+    /// A method suspended by `yield` resumes its saved frame instead, and
+    /// the arguments are ignored.
+    fn push_call_frame(
+        &mut self,
+        function_idx: u32,
+        return_info: Option<(u32, usize, u8)>,
+        agent_id: Option<u64>,
+        args: Vec<Value>,
+    ) -> Result<(), AgentusError> {
+        if let Some(id) = agent_id
+            && let Some(mut frame) = self
+                .agents
+                .get_mut(&id)
+                .and_then(|agent| agent.coroutines.remove(&function_idx))
+        {
+            frame.return_info = return_info;
+            self.call_stack.push(frame);
+            return Ok(());
+        }
+
+        self.push_frame_with_agent(function_idx, return_info, agent_id)?;
+        for (i, val) in args.into_iter().enumerate() {
+            self.set_register(i, val);
+        }
+        Ok(())
+    }
+
     fn push_frame_with_agent(
         &mut self,
         function_idx: u32,
         return_info: Option<(u32, usize, u8)>,
         agent_id: Option<u64>,
-    ) -> Result<(), String> {
+    ) -> Result<(), AgentusError> {
+        if let Some(max) = self.budget.as_ref().and_then(|b| b.max_call_depth)
+            && self.call_depth() >= max
+        {
+            return Err(AgentusError::sandboxed(format!("call depth exceeds limit of {}", max)));
+        }
+        if self.call_depth() >= self.max_call_depth {
+            return Err(AgentusError::runtime("call stack depth exceeded"));
+        }
+
         let func = self
             .module
             .get_function(function_idx)
-            .ok_or_else(|| format!("function {} not found", function_idx))?;
+            .ok_or_else(|| AgentusError::runtime(format!("function {} not found", function_idx)))?;
 
         let registers = vec![Value::None; func.num_registers as usize];
 
@@ -158,724 +536,1367 @@ impl VM {
         Ok(())
     }
 
-    fn execute(&mut self) -> Result<(), String> {
-        loop {
-            if self.call_stack.is_empty() {
-                return Ok(());
+    /// Number of live call frames, including those of suspended stacks.
+    fn call_depth(&self) -> usize {
+        self.suspended_stacks.iter().map(Vec::len).sum::<usize>() + self.call_stack.len()
+    }
+
+    /// Enforce the sandbox budget before running an instruction.
+    fn check_budget(&mut self, opcode: OpCode) -> Result<(), AgentusError> {
+        let Some(budget) = &self.budget else {
+            return Ok(());
+        };
+        if !budget.allows(opcode) {
+            return Err(AgentusError::sandboxed(format!("opcode {:?} is not allowed", opcode)));
+        }
+        self.instructions_executed += 1;
+        if let Some(max) = budget.max_instructions
+            && self.instructions_executed > max
+        {
+            return Err(AgentusError::sandboxed(format!("instruction limit of {} exceeded", max)));
+        }
+        if let Some(max) = budget.max_heap_bytes
+            && self.instructions_executed % HEAP_CHECK_INTERVAL == 1
+        {
+            // Count suspended stacks and generator frames too, not just
+            // the running stack
+            let frames = self
+                .call_stack
+                .iter()
+                .chain(self.suspended_stacks.iter().flatten())
+                .chain(self.agents.values().flat_map(|agent| agent.coroutines.values()));
+            let live = frames
+                .flat_map(|frame| frame.registers.iter())
+                .chain(self.global_memory.values())
+                .chain(self.agents.values().flat_map(|agent| {
+                    agent.memory.values().chain(agent.mailbox.iter())
+                }));
+            let used = heap_bytes(live);
+            if used > max {
+                return Err(AgentusError::sandboxed(format!(
+                    "heap usage of {} bytes exceeds limit of {}",
+                    used, max
+                )));
             }
+        }
+        Ok(())
+    }
 
-            let frame = self.call_stack.last().unwrap();
-            let func_idx = frame.function_idx;
-            let pc = frame.pc;
+    /// Fail before a built-in allocates a `bytes`-sized result that alone
+    /// exceeds the heap budget. The periodic heap check only sees values
+    /// after they exist, so built-ins whose output size comes from an
+    /// argument call this first.
+    fn check_alloc(&self, bytes: usize) -> Result<(), AgentusError> {
+        if let Some(max) = self.budget.as_ref().and_then(|b| b.max_heap_bytes)
+            && bytes > max
+        {
+            return Err(AgentusError::sandboxed(format!(
+                "allocation of {} bytes exceeds heap limit of {}",
+                bytes, max
+            )));
+        }
+        Ok(())
+    }
 
-            let func = self
-                .module
-                .get_function(func_idx)
-                .ok_or("invalid function index")?;
+    fn execute(&mut self) -> Result<(), AgentusError> {
+        while self.step_instruction()? == StepResult::Continue {}
+        Ok(())
+    }
 
-            if pc >= func.instructions.len() {
-                // Function ended without explicit return
-                self.call_stack.pop();
-                continue;
-            }
+    /// Execute the next instruction of the top frame. If it failed because a
+    /// throw escaped a callback it ran, re-raise the throw here.
+    fn step_instruction(&mut self) -> Result<StepResult, AgentusError> {
+        match self.dispatch_instruction() {
+            Err(e) => match self.escaped_throw.take() {
+                Some((base, error)) if self.callback_base < base => {
+                    self.throw_error(error)?;
+                    Ok(StepResult::Continue)
+                }
+                escaped => {
+                    self.escaped_throw = escaped;
+                    Err(e)
+                }
+            },
+            result => result,
+        }
+    }
 
-            let inst = func.instructions[pc];
-            let opcode = inst
-                .opcode()
-                .ok_or_else(|| format!("invalid opcode 0x{:02X} at pc={}", inst.opcode_byte(), pc))?;
+    /// Decode and run the next instruction of the top frame.
+    fn dispatch_instruction(&mut self) -> Result<StepResult, AgentusError> {
+        if self.call_stack.is_empty() {
+            return Ok(StepResult::Halted);
+        }
 
-            // Advance PC before executing (some instructions modify it)
-            self.call_stack.last_mut().unwrap().pc += 1;
+        let frame = self.call_stack.last().unwrap();
+        let func_idx = frame.function_idx;
+        let pc = frame.pc;
 
-            match opcode {
-                OpCode::Nop => {}
-                OpCode::Halt => {
-                    return Ok(());
-                }
+        let func = self
+            .module
+            .get_function(func_idx)
+            .ok_or_else(|| AgentusError::runtime("invalid function index"))?;
 
-                // Load / Store / Move
-                OpCode::LoadConst => {
-                    let a = inst.a() as usize;
-                    let bx = inst.bx();
-                    let value = self.load_constant(bx)?;
-                    self.set_register(a, value);
-                }
-                OpCode::LoadNone => {
-                    let a = inst.a() as usize;
-                    self.set_register(a, Value::None);
-                }
-                OpCode::LoadTrue => {
-                    let a = inst.a() as usize;
-                    self.set_register(a, Value::Bool(true));
-                }
-                OpCode::LoadFalse => {
-                    let a = inst.a() as usize;
-                    self.set_register(a, Value::Bool(false));
-                }
-                OpCode::Move => {
-                    let a = inst.a() as usize;
-                    let b = inst.b() as usize;
-                    let value = self.get_register(b).clone();
-                    self.set_register(a, value);
-                }
+        if pc >= func.instructions.len() {
+            // Function ended without explicit return
+            self.call_stack.pop();
+            return Ok(StepResult::Continue);
+        }
 
-                // Arithmetic
-                OpCode::Add => {
-                    let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
-                    let result = self.arith_op(b, c, |x, y| x + y)?;
-                    self.set_register(a, result);
-                }
-                OpCode::Sub => {
-                    let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
-                    let result = self.arith_op(b, c, |x, y| x - y)?;
-                    self.set_register(a, result);
-                }
-                OpCode::Mul => {
-                    let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
-                    let result = self.arith_op(b, c, |x, y| x * y)?;
-                    self.set_register(a, result);
-                }
-                OpCode::Div => {
-                    let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
-                    let result = self.arith_op(b, c, |x, y| x / y)?;
-                    self.set_register(a, result);
-                }
-                OpCode::Mod => {
-                    let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
-                    let result = self.arith_op(b, c, |x, y| x % y)?;
-                    self.set_register(a, result);
-                }
-                OpCode::Neg => {
-                    let (a, b) = (inst.a() as usize, inst.b() as usize);
-                    let val = self.get_register(b);
-                    match val {
-                        Value::Num(n) => self.set_register(a, Value::Num(-n)),
-                        _ => return Err("Neg requires numeric operand".to_string()),
-                    }
-                }
+        let inst = func.instructions[pc];
+        let opcode = inst
+            .opcode()
+            .ok_or_else(|| {
+                AgentusError::runtime(format!(
+                    "invalid opcode 0x{:02X} at pc={}",
+                    inst.opcode_byte(),
+                    pc
+                ))
+            })?;
+
+        self.check_budget(opcode)?;
+
+        // Advance PC before executing (some instructions modify it)
+        self.call_stack.last_mut().unwrap().pc += 1;
+
+        match opcode {
+            OpCode::Nop => {}
+            OpCode::Halt => {
+                return Ok(StepResult::Halted);
+            }
 
-                // Comparison
-                OpCode::Eq => {
-                    let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
-                    let result = self.get_register(b) == self.get_register(c);
-                    self.set_register(a, Value::Bool(result));
-                }
-                OpCode::Neq => {
-                    let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
-                    let result = self.get_register(b) != self.get_register(c);
-                    self.set_register(a, Value::Bool(result));
-                }
-                OpCode::Lt => {
-                    let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
-                    let result = self.cmp_op(b, c, |x, y| x < y)?;
-                    self.set_register(a, result);
-                }
-                OpCode::Lte => {
-                    let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
-                    let result = self.cmp_op(b, c, |x, y| x <= y)?;
-                    self.set_register(a, result);
-                }
-                OpCode::Gt => {
-                    let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
-                    let result = self.cmp_op(b, c, |x, y| x > y)?;
-                    self.set_register(a, result);
-                }
-                OpCode::Gte => {
-                    let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
-                    let result = self.cmp_op(b, c, |x, y| x >= y)?;
-                    self.set_register(a, result);
-                }
+            // Load / Store / Move
+            OpCode::LoadConst => {
+                let a = inst.a() as usize;
+                let bx = inst.bx();
+                let value = self.load_constant(bx)?;
+                self.set_register(a, value);
+            }
+            OpCode::LoadNone => {
+                let a = inst.a() as usize;
+                self.set_register(a, Value::None);
+            }
+            OpCode::GLoad => {
+                let (a, bx) = (inst.a() as usize, inst.bx());
+                let name = self.load_constant_str(bx)?;
+                let value = self.global_memory.get(&name).cloned().unwrap_or(Value::None);
+                self.set_register(a, value);
+            }
+            OpCode::GStore => {
+                let (a, bx) = (inst.a() as usize, inst.bx());
+                let name = self.load_constant_str(bx)?;
+                let value = self.get_register(a).clone();
+                self.global_memory.insert(name, value);
+            }
+            OpCode::LoadEnv => {
+                let a = inst.a() as usize;
+                self.set_register(a, Value::Env);
+            }
+            OpCode::LoadTrue => {
+                let a = inst.a() as usize;
+                self.set_register(a, Value::Bool(true));
+            }
+            OpCode::LoadFalse => {
+                let a = inst.a() as usize;
+                self.set_register(a, Value::Bool(false));
+            }
+            OpCode::Move => {
+                let a = inst.a() as usize;
+                let b = inst.b() as usize;
+                let value = self.get_register(b).clone();
+                self.set_register(a, value);
+            }
 
-                // Logic
-                OpCode::And => {
-                    let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
-                    let lhs = self.get_register(b).is_truthy();
-                    let rhs = self.get_register(c).is_truthy();
-                    self.set_register(a, Value::Bool(lhs && rhs));
-                }
-                OpCode::Or => {
-                    let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
-                    let lhs = self.get_register(b).is_truthy();
-                    let rhs = self.get_register(c).is_truthy();
-                    self.set_register(a, Value::Bool(lhs || rhs));
-                }
-                OpCode::Not => {
-                    let (a, b) = (inst.a() as usize, inst.b() as usize);
-                    let val = self.get_register(b).is_truthy();
-                    self.set_register(a, Value::Bool(!val));
+            // Arithmetic
+            OpCode::Add => {
+                let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
+                let result = self.arith_op(b, c, |x, y| x + y)?;
+                self.set_register(a, result);
+            }
+            OpCode::Sub => {
+                let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
+                let result = self.arith_op(b, c, |x, y| x - y)?;
+                self.set_register(a, result);
+            }
+            OpCode::Mul => {
+                let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
+                let result = self.arith_op(b, c, |x, y| x * y)?;
+                self.set_register(a, result);
+            }
+            OpCode::Div => {
+                let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
+                let result = self.arith_op(b, c, |x, y| x / y)?;
+                self.set_register(a, result);
+            }
+            OpCode::Mod => {
+                let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
+                let result = self.arith_op(b, c, |x, y| x % y)?;
+                self.set_register(a, result);
+            }
+            OpCode::Neg => {
+                let (a, b) = (inst.a() as usize, inst.b() as usize);
+                let val = self.get_register(b);
+                match val {
+                    Value::Num(n) => self.set_register(a, Value::Num(-n)),
+                    _ => return Err(AgentusError::runtime("Neg requires numeric operand")),
                 }
+            }
 
-                // String
-                OpCode::Concat => {
-                    let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
-                    let lhs = self.get_register(b).to_string();
-                    let rhs = self.get_register(c).to_string();
-                    self.set_register(a, Value::from_string(format!("{}{}", lhs, rhs)));
-                }
+            // Comparison
+            OpCode::Eq => {
+                let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
+                let result = Value::deep_eq(self.get_register(b), self.get_register(c));
+                self.set_register(a, Value::Bool(result));
+            }
+            OpCode::Neq => {
+                let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
+                let result = !Value::deep_eq(self.get_register(b), self.get_register(c));
+                self.set_register(a, Value::Bool(result));
+            }
+            OpCode::Lt => {
+                let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
+                let result = self.cmp_op(b, c, |o| o.is_lt())?;
+                self.set_register(a, result);
+            }
+            OpCode::Lte => {
+                let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
+                let result = self.cmp_op(b, c, |o| o.is_le())?;
+                self.set_register(a, result);
+            }
+            OpCode::Gt => {
+                let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
+                let result = self.cmp_op(b, c, |o| o.is_gt())?;
+                self.set_register(a, result);
+            }
+            OpCode::Gte => {
+                let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
+                let result = self.cmp_op(b, c, |o| o.is_ge())?;
+                self.set_register(a, result);
+            }
+            OpCode::Cmp => {
+                let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
+                let result = Value::compare(self.get_register(b), self.get_register(c));
+                self.set_register(a, Value::Num(result as f64));
+            }
 
-                // Control flow
-                OpCode::Jmp => {
-                    let offset = inst.sbx_24();
+            // Logic
+            OpCode::And => {
+                let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
+                let lhs = self.get_register(b).is_truthy();
+                let rhs = self.get_register(c).is_truthy();
+                self.set_register(a, Value::Bool(lhs && rhs));
+            }
+            OpCode::Or => {
+                let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
+                let lhs = self.get_register(b).is_truthy();
+                let rhs = self.get_register(c).is_truthy();
+                self.set_register(a, Value::Bool(lhs || rhs));
+            }
+            OpCode::Not => {
+                let (a, b) = (inst.a() as usize, inst.b() as usize);
+                let val = self.get_register(b).is_truthy();
+                self.set_register(a, Value::Bool(!val));
+            }
+
+            // String
+            OpCode::Concat => {
+                let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
+                let lhs = self.get_register(b).coerce_to_str(CoerceMode::Display);
+                let rhs = self.get_register(c).coerce_to_str(CoerceMode::Display);
+                self.set_register(a, Value::from_string(format!("{}{}", lhs, rhs)));
+            }
+
+            // Control flow
+            OpCode::Jmp => {
+                let offset = inst.sbx_24();
+                let frame = self.call_stack.last_mut().unwrap();
+                frame.pc = (frame.pc as i32 + offset) as usize;
+            }
+            OpCode::JmpTrue => {
+                let a = inst.a() as usize;
+                let offset = inst.sbx_16();
+                if self.get_register(a).is_truthy() {
                     let frame = self.call_stack.last_mut().unwrap();
-                    frame.pc = (frame.pc as i32 + offset) as usize;
+                    frame.pc = (frame.pc as i32 + offset as i32) as usize;
                 }
-                OpCode::JmpTrue => {
-                    let a = inst.a() as usize;
-                    let offset = inst.sbx_16();
-                    if self.get_register(a).is_truthy() {
-                        let frame = self.call_stack.last_mut().unwrap();
-                        frame.pc = (frame.pc as i32 + offset as i32) as usize;
-                    }
-                }
-                OpCode::JmpFalse => {
-                    let a = inst.a() as usize;
-                    let offset = inst.sbx_16();
-                    if !self.get_register(a).is_truthy() {
-                        let frame = self.call_stack.last_mut().unwrap();
-                        frame.pc = (frame.pc as i32 + offset as i32) as usize;
-                    }
+            }
+            OpCode::JmpFalse => {
+                let a = inst.a() as usize;
+                let offset = inst.sbx_16();
+                if !self.get_register(a).is_truthy() {
+                    let frame = self.call_stack.last_mut().unwrap();
+                    frame.pc = (frame.pc as i32 + offset as i32) as usize;
                 }
+            }
 
-                // I/O
-                OpCode::Emit => {
-                    let a = inst.a() as usize;
-                    let value = self.get_register(a).clone();
-                    self.output.on_emit(&value);
-                    self.outputs.push(value);
-                }
-                OpCode::Log => {
-                    let level = inst.b();
-                    let c = inst.c() as usize;
-                    let msg = self.get_register(c).to_string();
-                    self.output.on_log(level, &msg);
-                }
+            // I/O
+            OpCode::Emit => {
+                let a = inst.a() as usize;
+                let value = self.get_register(a).clone();
+                self.output.on_emit(&value);
+                self.outputs.push(value);
+            }
+            OpCode::Log => {
+                let level = inst.b();
+                let c = inst.c() as usize;
+                let msg = self.get_register(c).to_string();
+                self.output.on_log(level, &msg);
+            }
 
-                // Function call
-                OpCode::Call => {
-                    let result_reg = inst.a();
-                    let func_idx_raw = inst.bx();
-
-                    if func_idx_raw == 0xFFFE {
-                        // Method call dispatch (sentinel)
-                        let frame = self.call_stack.last().unwrap();
-                        let pc1 = frame.pc;
-                        let func = self.module.get_function(frame.function_idx)
-                            .ok_or("invalid function index")?;
-                        let extra1 = func.instructions[pc1];
-                        let extra2 = func.instructions[pc1 + 1];
-                        self.call_stack.last_mut().unwrap().pc += 2;
-
-                        let first_arg_reg = extra1.b() as usize;
-                        let num_args = extra1.c() as usize;
-                        let method_name_idx = extra2.bx();
-
-                        let method_name = self.load_constant_str(method_name_idx)?;
-
-                        // r(first_arg_reg) is the receiver
-                        let handle = self.get_register(first_arg_reg).clone();
-
-                        // Built-in collection methods
-                        match &handle {
-                            Value::List(list) => {
-                                match method_name.as_str() {
-                                    "push" => {
-                                        if num_args < 2 {
-                                            return Err("list.push() requires an argument".to_string());
+            // Function call
+            OpCode::Call => {
+                let result_reg = inst.a();
+                let func_idx_raw = inst.bx();
+
+                if func_idx_raw == 0xFFFE {
+                    // Method call dispatch (sentinel)
+                    let frame = self.call_stack.last().unwrap();
+                    let pc1 = frame.pc;
+                    let func = self.module.get_function(frame.function_idx)
+                        .ok_or_else(|| AgentusError::runtime("invalid function index"))?;
+                    let extra1 = func.instructions[pc1];
+                    let extra2 = func.instructions[pc1 + 1];
+                    self.call_stack.last_mut().unwrap().pc += 2;
+
+                    let first_arg_reg = extra1.b() as usize;
+                    let num_args = extra1.c() as usize;
+                    let method_name_idx = extra2.bx();
+
+                    let method_name = self.load_constant_str(method_name_idx)?;
+
+                    // r(first_arg_reg) is the receiver, except for calls on
+                    // `self` (A=1), which target the current frame's agent
+                    let handle = if extra1.a() == 1 {
+                        let agent_id = self.call_stack.last().unwrap().agent_id.ok_or_else(|| {
+                            AgentusError::runtime(format!(
+                                "cannot call 'self.{}' outside of an agent method",
+                                method_name
+                            ))
+                        })?;
+                        Value::AgentHandle(agent_id)
+                    } else {
+                        self.get_register(first_arg_reg).clone()
+                    };
+
+                    // Built-in collection methods
+                    match &handle {
+                        Value::List(list) => {
+                            match method_name.as_str() {
+                                "push" => {
+                                    if num_args < 2 {
+                                        return Err(AgentusError::runtime("list.push() requires an argument"));
+                                    }
+                                    let val = self.get_register(first_arg_reg + 1).clone();
+                                    list.borrow_mut().push(val);
+                                    self.set_register(result_reg as usize, Value::None);
+                                    return Ok(StepResult::Continue);
+                                }
+                                "len" => {
+                                    let len = list.borrow().len();
+                                    self.set_register(result_reg as usize, Value::Num(len as f64));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "sort" => {
+                                    list.borrow_mut().sort_by(|x, y| x.sort_order(y));
+                                    self.set_register(result_reg as usize, Value::None);
+                                    return Ok(StepResult::Continue);
+                                }
+                                "sort_by" => {
+                                    if num_args != 2 {
+                                        return Err(AgentusError::runtime("list.sort_by() requires a comparator function"));
+                                    }
+                                    let comparator = self.get_register(first_arg_reg + 1).clone();
+                                    let mut items = list.borrow().clone();
+                                    // The comparator can fail, but `sort_by` cannot: keep the first error
+                                    let mut error = None;
+                                    items.sort_by(|x, y| {
+                                        if error.is_some() {
+                                            return Ordering::Equal;
+                                        }
+                                        match self.call_value(&comparator, vec![x.clone(), y.clone()]) {
+                                            Ok(Value::Num(n)) => n.partial_cmp(&0.0).unwrap_or(Ordering::Equal),
+                                            Ok(other) => {
+                                                error = Some(AgentusError::runtime(format!("list.sort_by() comparator must return a number, got {}", other)));
+                                                Ordering::Equal
+                                            }
+                                            Err(e) => {
+                                                error = Some(e);
+                                                Ordering::Equal
+                                            }
+                                        }
+                                    });
+                                    if let Some(e) = error {
+                                        return Err(e);
+                                    }
+                                    *list.borrow_mut() = items;
+                                    self.set_register(result_reg as usize, Value::None);
+                                    return Ok(StepResult::Continue);
+                                }
+                                "unique" => {
+                                    let mut unique: Vec<Value> = Vec::new();
+                                    for item in list.borrow().iter() {
+                                        if !unique.contains(item) {
+                                            unique.push(item.clone());
                                         }
-                                        let val = self.get_register(first_arg_reg + 1).clone();
-                                        list.borrow_mut().push(val);
-                                        self.set_register(result_reg as usize, Value::None);
-                                        continue;
                                     }
-                                    "len" => {
-                                        let len = list.borrow().len();
-                                        self.set_register(result_reg as usize, Value::Num(len as f64));
-                                        continue;
+                                    self.set_register(result_reg as usize, Value::List(Rc::new(std::cell::RefCell::new(unique))));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "flatten" => {
+                                    // Non-list elements are kept as-is (one level only)
+                                    let mut flat = Vec::new();
+                                    for item in list.borrow().iter() {
+                                        match item {
+                                            Value::List(inner) => flat.extend(inner.borrow().iter().cloned()),
+                                            other => flat.push(other.clone()),
+                                        }
+                                    }
+                                    self.set_register(result_reg as usize, Value::List(Rc::new(std::cell::RefCell::new(flat))));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "zip" => {
+                                    let other = match (num_args >= 2).then(|| self.get_register(first_arg_reg + 1)) {
+                                        Some(Value::List(other)) => other.borrow().clone(),
+                                        _ => return Err(AgentusError::runtime("list.zip() requires a list")),
+                                    };
+                                    // Stops at the shorter list
+                                    let pairs: Vec<Value> = list.borrow().iter().zip(other)
+                                        .map(|(a, b)| Value::List(Rc::new(std::cell::RefCell::new(vec![a.clone(), b]))))
+                                        .collect();
+                                    self.set_register(result_reg as usize, Value::List(Rc::new(std::cell::RefCell::new(pairs))));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "enumerate" => {
+                                    let pairs: Vec<Value> = list.borrow().iter().enumerate()
+                                        .map(|(i, v)| Value::List(Rc::new(std::cell::RefCell::new(vec![Value::Num(i as f64), v.clone()]))))
+                                        .collect();
+                                    self.set_register(result_reg as usize, Value::List(Rc::new(std::cell::RefCell::new(pairs))));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "chunk" => {
+                                    let size = match (num_args >= 2).then(|| self.get_register(first_arg_reg + 1)) {
+                                        Some(Value::Num(n)) if *n >= 1.0 => *n as usize,
+                                        _ => return Err(AgentusError::runtime("list.chunk() requires a positive size")),
+                                    };
+                                    let chunks: Vec<Value> = list.borrow()
+                                        .chunks(size)
+                                        .map(|c| Value::List(Rc::new(std::cell::RefCell::new(c.to_vec()))))
+                                        .collect();
+                                    self.set_register(result_reg as usize, Value::List(Rc::new(std::cell::RefCell::new(chunks))));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "to_map" => {
+                                    if num_args != 2 {
+                                        return Err(AgentusError::runtime("list.to_map() requires a key function or field name"));
+                                    }
+                                    let key_fn = self.get_register(first_arg_reg + 1).clone();
+                                    let items = list.borrow().clone();
+                                    let mut result = HashMap::new();
+                                    for item in items {
+                                        // A string names a field of each (map) item
+                                        let key = match (&key_fn, &item) {
+                                            (Value::Str(field), Value::Map(m)) => m.borrow().get(field.as_str()).cloned().unwrap_or(Value::None),
+                                            (Value::Str(field), other) => {
+                                                return Err(AgentusError::runtime(format!("list.to_map() cannot read field '{}' of {}", field, other)));
+                                            }
+                                            _ => self.call_value(&key_fn, vec![item.clone()])?,
+                                        };
+                                        result.insert(key.to_string(), item);
                                     }
-                                    _ => return Err(format!("unknown list method '{}'", method_name)),
+                                    self.set_register(result_reg as usize, Value::Map(Rc::new(std::cell::RefCell::new(result))));
+                                    return Ok(StepResult::Continue);
                                 }
+                                "group_by" => {
+                                    if num_args != 2 {
+                                        return Err(AgentusError::runtime("list.group_by() requires a key function or field name"));
+                                    }
+                                    let key_fn = self.get_register(first_arg_reg + 1).clone();
+                                    let items = list.borrow().clone();
+                                    let mut groups: HashMap<String, Vec<Value>> = HashMap::new();
+                                    for item in items {
+                                        // A string names a field of each (map) item
+                                        let key = match (&key_fn, &item) {
+                                            (Value::Str(field), Value::Map(m)) => m.borrow().get(field.as_str()).cloned().unwrap_or(Value::None),
+                                            (Value::Str(field), other) => {
+                                                return Err(AgentusError::runtime(format!("list.group_by() cannot read field '{}' of {}", field, other)));
+                                            }
+                                            _ => self.call_value(&key_fn, vec![item.clone()])?,
+                                        };
+                                        groups.entry(key.to_string()).or_default().push(item);
+                                    }
+                                    let result = groups
+                                        .into_iter()
+                                        .map(|(key, items)| (key, Value::List(Rc::new(std::cell::RefCell::new(items)))))
+                                        .collect();
+                                    self.set_register(result_reg as usize, Value::Map(Rc::new(std::cell::RefCell::new(result))));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "join" => {
+                                    if num_args < 2 {
+                                        return Err(AgentusError::runtime("list.join() requires a separator"));
+                                    }
+                                    let sep = self.get_register(first_arg_reg + 1).to_string();
+                                    let joined = list.borrow()
+                                        .iter()
+                                        .map(|v| v.to_string())
+                                        .collect::<Vec<_>>()
+                                        .join(&sep);
+                                    self.set_register(result_reg as usize, Value::from_string(joined));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "reduce" => {
+                                    if num_args != 3 {
+                                        return Err(AgentusError::runtime("list.reduce() requires an initial value and a reducer"));
+                                    }
+                                    let mut acc = self.get_register(first_arg_reg + 1).clone();
+                                    let reducer = self.get_register(first_arg_reg + 2).clone();
+                                    let items = list.borrow().clone();
+                                    for item in items {
+                                        acc = match &reducer {
+                                            Value::Str(op) => Self::apply_named_reducer(op, acc, item)?,
+                                            _ => self.call_value(&reducer, vec![acc, item])?,
+                                        };
+                                    }
+                                    self.set_register(result_reg as usize, acc);
+                                    return Ok(StepResult::Continue);
+                                }
+                                _ => return Err(AgentusError::runtime(format!("unknown list method '{}'", method_name))),
                             }
-                            Value::Map(map) => {
-                                match method_name.as_str() {
-                                    "len" => {
-                                        let len = map.borrow().len();
-                                        self.set_register(result_reg as usize, Value::Num(len as f64));
-                                        continue;
+                        }
+                        Value::Map(map) => {
+                            match method_name.as_str() {
+                                "len" => {
+                                    let len = map.borrow().len();
+                                    self.set_register(result_reg as usize, Value::Num(len as f64));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "keys" => {
+                                    let keys: Vec<Value> = map.borrow().keys()
+                                        .map(|k| Value::from_str(k))
+                                        .collect();
+                                    self.set_register(result_reg as usize, Value::List(std::rc::Rc::new(std::cell::RefCell::new(keys))));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "values" => {
+                                    let vals: Vec<Value> = map.borrow().values()
+                                        .cloned()
+                                        .collect();
+                                    self.set_register(result_reg as usize, Value::List(std::rc::Rc::new(std::cell::RefCell::new(vals))));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "to_list_of_pairs" | "entries" | "items" => {
+                                    let mut entries: Vec<(String, Value)> = map.borrow()
+                                        .iter()
+                                        .map(|(k, v)| (k.clone(), v.clone()))
+                                        .collect();
+                                    entries.sort_by(|x, y| x.0.cmp(&y.0));
+                                    let pairs: Vec<Value> = entries.into_iter()
+                                        .map(|(k, v)| Value::List(Rc::new(std::cell::RefCell::new(vec![Value::from_string(k), v]))))
+                                        .collect();
+                                    self.set_register(result_reg as usize, Value::List(Rc::new(std::cell::RefCell::new(pairs))));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "contains" => {
+                                    if num_args < 2 {
+                                        return Err(AgentusError::runtime("map.contains() requires an argument"));
                                     }
-                                    "keys" => {
-                                        let keys: Vec<Value> = map.borrow().keys()
-                                            .map(|k| Value::from_str(k))
-                                            .collect();
-                                        self.set_register(result_reg as usize, Value::List(std::rc::Rc::new(std::cell::RefCell::new(keys))));
-                                        continue;
+                                    let key = self.get_register(first_arg_reg + 1).to_string();
+                                    let has = map.borrow().contains_key(&key);
+                                    self.set_register(result_reg as usize, Value::Bool(has));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "get" | "get_or" => {
+                                    let wanted = if method_name == "get" { 2 } else { 3 };
+                                    if num_args != wanted {
+                                        return Err(AgentusError::runtime(format!("map.{}() takes {} argument(s)", method_name, wanted - 1)));
                                     }
-                                    "values" => {
-                                        let vals: Vec<Value> = map.borrow().values()
-                                            .cloned()
-                                            .collect();
-                                        self.set_register(result_reg as usize, Value::List(std::rc::Rc::new(std::cell::RefCell::new(vals))));
-                                        continue;
+                                    let key = self.get_register(first_arg_reg + 1).to_string();
+                                    let found = map.borrow().get(&key).cloned();
+                                    let value = match found {
+                                        Some(v) => v,
+                                        None if wanted == 3 => self.get_register(first_arg_reg + 2).clone(),
+                                        None => Value::None,
+                                    };
+                                    self.set_register(result_reg as usize, value);
+                                    return Ok(StepResult::Continue);
+                                }
+                                "remove" => {
+                                    if num_args < 2 {
+                                        return Err(AgentusError::runtime("map.remove() requires an argument"));
                                     }
-                                    "contains" => {
-                                        if num_args < 2 {
-                                            return Err("map.contains() requires an argument".to_string());
-                                        }
-                                        let key = self.get_register(first_arg_reg + 1).to_string();
-                                        let has = map.borrow().contains_key(&key);
-                                        self.set_register(result_reg as usize, Value::Bool(has));
-                                        continue;
+                                    let key = self.get_register(first_arg_reg + 1).to_string();
+                                    let removed = map.borrow_mut().remove(&key).unwrap_or(Value::None);
+                                    self.set_register(result_reg as usize, removed);
+                                    return Ok(StepResult::Continue);
+                                }
+                                "transform_values" | "transform_keys" => {
+                                    if num_args != 2 {
+                                        return Err(AgentusError::runtime(format!("map.{}() requires a function argument", method_name)));
                                     }
-                                    "remove" => {
-                                        if num_args < 2 {
-                                            return Err("map.remove() requires an argument".to_string());
+                                    let func = self.get_register(first_arg_reg + 1).clone();
+                                    // Visit entries in key order so duplicate renamed keys
+                                    // resolve deterministically (the last key wins)
+                                    let mut entries: Vec<(String, Value)> = map.borrow()
+                                        .iter()
+                                        .map(|(k, v)| (k.clone(), v.clone()))
+                                        .collect();
+                                    entries.sort_by(|x, y| x.0.cmp(&y.0));
+                                    let mut result = HashMap::new();
+                                    for (key, val) in entries {
+                                        if method_name == "transform_values" {
+                                            let new_val = self.call_value(&func, vec![val])?;
+                                            result.insert(key, new_val);
+                                        } else {
+                                            let new_key = self.call_value(&func, vec![Value::from_string(key)])?;
+                                            result.insert(new_key.to_string(), val);
                                         }
-                                        let key = self.get_register(first_arg_reg + 1).to_string();
-                                        let removed = map.borrow_mut().remove(&key).unwrap_or(Value::None);
-                                        self.set_register(result_reg as usize, removed);
-                                        continue;
                                     }
-                                    _ => return Err(format!("unknown map method '{}'", method_name)),
+                                    self.set_register(result_reg as usize, Value::Map(Rc::new(std::cell::RefCell::new(result))));
+                                    return Ok(StepResult::Continue);
                                 }
+                                _ => return Err(AgentusError::runtime(format!("unknown map method '{}'", method_name))),
                             }
-                            Value::Str(s) => {
-                                match method_name.as_str() {
-                                    "len" => {
-                                        self.set_register(result_reg as usize, Value::Num(s.len() as f64));
-                                        continue;
+                        }
+                        Value::Str(s) => {
+                            match method_name.as_str() {
+                                "len" => {
+                                    self.set_register(result_reg as usize, Value::Num(s.len() as f64));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "lower" => {
+                                    self.set_register(result_reg as usize, Value::from_string(s.to_lowercase()));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "upper" => {
+                                    self.set_register(result_reg as usize, Value::from_string(s.to_uppercase()));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "trim" | "trim_start" | "trim_end" => {
+                                    let trimmed = match method_name.as_str() {
+                                        "trim" => s.trim(),
+                                        "trim_start" => s.trim_start(),
+                                        _ => s.trim_end(),
+                                    };
+                                    self.set_register(result_reg as usize, Value::from_str(trimmed));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "pad_start" | "pad_end" => {
+                                    if num_args < 2 {
+                                        return Err(AgentusError::runtime(format!("str.{}() requires a width", method_name)));
                                     }
-                                    _ => return Err(format!("unknown string method '{}'", method_name)),
+                                    let width = match self.get_register(first_arg_reg + 1) {
+                                        Value::Num(n) if *n >= 0.0 => *n as usize,
+                                        _ => return Err(AgentusError::runtime(format!("str.{}() requires a non-negative width", method_name))),
+                                    };
+                                    // The pad string defaults to a single space
+                                    let pad = if num_args >= 3 {
+                                        self.get_register(first_arg_reg + 2).to_string()
+                                    } else {
+                                        " ".to_string()
+                                    };
+                                    let len = s.chars().count();
+                                    if width <= len || pad.is_empty() {
+                                        self.set_register(result_reg as usize, Value::Str(s.clone()));
+                                        return Ok(StepResult::Continue);
+                                    }
+                                    // Every fill char takes at least one byte
+                                    self.check_alloc(s.len().saturating_add(width - len))?;
+                                    let fill: String = pad.chars().cycle().take(width - len).collect();
+                                    let padded = if method_name == "pad_start" {
+                                        format!("{}{}", fill, s)
+                                    } else {
+                                        format!("{}{}", s, fill)
+                                    };
+                                    self.set_register(result_reg as usize, Value::from_string(padded));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "repeat" => {
+                                    let count = match (num_args >= 2).then(|| self.get_register(first_arg_reg + 1)) {
+                                        Some(Value::Num(n)) if *n >= 0.0 => *n as usize,
+                                        _ => return Err(AgentusError::runtime("str.repeat() requires a non-negative count")),
+                                    };
+                                    self.check_alloc(s.len().saturating_mul(count))?;
+                                    self.set_register(result_reg as usize, Value::from_string(s.repeat(count)));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "reverse" => {
+                                    let reversed: String = s.chars().rev().collect();
+                                    self.set_register(result_reg as usize, Value::from_string(reversed));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "match_regex" => {
+                                    let pattern = match (num_args >= 2).then(|| self.get_register(first_arg_reg + 1)) {
+                                        Some(Value::Str(p)) => p.clone(),
+                                        _ => return Err(AgentusError::runtime("str.match_regex() requires a pattern string")),
+                                    };
+                                    let found = Self::regex_matches(s, &pattern)?;
+                                    self.set_register(result_reg as usize, Value::List(Rc::new(std::cell::RefCell::new(found))));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "replace_regex" => {
+                                    let (pattern, replacement) = match (num_args >= 3).then(|| {
+                                        (self.get_register(first_arg_reg + 1), self.get_register(first_arg_reg + 2))
+                                    }) {
+                                        Some((Value::Str(p), Value::Str(r))) => (p.clone(), r.clone()),
+                                        _ => return Err(AgentusError::runtime("str.replace_regex() requires a pattern and a replacement string")),
+                                    };
+                                    let replaced = Self::regex_replace(s, &pattern, &replacement)?;
+                                    self.set_register(result_reg as usize, Value::from_string(replaced));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "to_num" => {
+                                    // Unparseable text gives `none` rather than an error
+                                    let parsed = s.trim().parse::<f64>().ok().filter(|n| n.is_finite());
+                                    self.set_register(result_reg as usize, parsed.map_or(Value::None, Value::Num));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "find" | "count" => {
+                                    if num_args < 2 {
+                                        return Err(AgentusError::runtime(format!("str.{}() requires a substring", method_name)));
+                                    }
+                                    let needle = self.get_register(first_arg_reg + 1).to_string();
+                                    // `find` gives a byte offset, matching `len()`
+                                    let result = if method_name == "find" {
+                                        s.find(needle.as_str()).map_or(-1.0, |idx| idx as f64)
+                                    } else {
+                                        s.matches(needle.as_str()).count() as f64
+                                    };
+                                    self.set_register(result_reg as usize, Value::Num(result));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "chars" => {
+                                    let chars: Vec<Value> =
+                                        s.chars().map(|c| Value::from_string(c.to_string())).collect();
+                                    self.set_register(result_reg as usize, Value::List(Rc::new(std::cell::RefCell::new(chars))));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "split" => {
+                                    if num_args < 2 {
+                                        return Err(AgentusError::runtime("str.split() requires a separator"));
+                                    }
+                                    let sep = self.get_register(first_arg_reg + 1).to_string();
+                                    let parts: Vec<Value> = if sep.is_empty() {
+                                        s.chars().map(|c| Value::from_string(c.to_string())).collect()
+                                    } else {
+                                        s.split(sep.as_str()).map(Value::from_str).collect()
+                                    };
+                                    self.set_register(result_reg as usize, Value::List(Rc::new(std::cell::RefCell::new(parts))));
+                                    return Ok(StepResult::Continue);
                                 }
+                                _ => return Err(AgentusError::runtime(format!("unknown string method '{}'", method_name))),
                             }
-                            _ => {}
                         }
-
-                        let agent_id = match &handle {
-                            Value::AgentHandle(id) => *id,
-                            _ => return Err(format!("method call on non-agent: {}", handle)),
-                        };
-
-                        let agent = self.agents.get(&agent_id)
-                            .ok_or_else(|| format!("agent {} not found", agent_id))?;
-                        let desc_idx = agent.descriptor_idx;
-                        let descriptor = self.module.get_agent(desc_idx)
-                            .ok_or_else(|| format!("agent descriptor {} not found", desc_idx))?
-                            .clone();
-
-                        // Find method by name
-                        let method_func_idx = descriptor.methods.iter()
-                            .find(|(name_idx, _)| {
-                                self.load_constant_str(*name_idx).ok().as_deref() == Some(method_name.as_str())
-                            })
-                            .map(|(_, idx)| *idx)
-                            .ok_or_else(|| format!("method '{}' not found on agent", method_name))?;
-
-                        // Collect arguments (skip the handle at first_arg_reg)
-                        let mut arg_values = Vec::with_capacity(if num_args > 0 { num_args - 1 } else { 0 });
-                        for i in 1..num_args {
-                            arg_values.push(self.get_register(first_arg_reg + i).clone());
+                        Value::Num(n) => {
+                            let n = *n;
+                            match method_name.as_str() {
+                                "to_str" => {
+                                    self.set_register(result_reg as usize, Value::from_string(Value::Num(n).to_string()));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "to_fixed" => {
+                                    let decimals = match (num_args >= 2).then(|| self.get_register(first_arg_reg + 1)) {
+                                        Some(Value::Num(d)) if *d >= 0.0 => *d as usize,
+                                        _ => return Err(AgentusError::runtime("num.to_fixed() requires a non-negative number of decimals")),
+                                    };
+                                    self.check_alloc(decimals)?;
+                                    self.set_register(result_reg as usize, Value::from_string(format!("{:.*}", decimals, n)));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "to_hex" => {
+                                    let int = n.trunc() as i64;
+                                    let sign = if int < 0 { "-" } else { "" };
+                                    self.set_register(result_reg as usize, Value::from_string(format!("{}0x{:x}", sign, int.unsigned_abs())));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "to_int" => {
+                                    self.set_register(result_reg as usize, Value::Num(n.trunc()));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "clamp" => {
+                                    let bounds = (num_args >= 3).then(|| {
+                                        (self.get_register(first_arg_reg + 1), self.get_register(first_arg_reg + 2))
+                                    });
+                                    let (min, max) = match bounds {
+                                        Some((Value::Num(min), Value::Num(max))) if min <= max => (*min, *max),
+                                        _ => return Err(AgentusError::runtime("num.clamp() requires numeric bounds with min <= max")),
+                                    };
+                                    self.set_register(result_reg as usize, Value::Num(n.clamp(min, max)));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "abs" => {
+                                    self.set_register(result_reg as usize, Value::Num(n.abs()));
+                                    return Ok(StepResult::Continue);
+                                }
+                                "sign" => {
+                                    // Zero (and NaN) map to themselves
+                                    let sign = if n > 0.0 { 1.0 } else if n < 0.0 { -1.0 } else { n };
+                                    self.set_register(result_reg as usize, Value::Num(sign));
+                                    return Ok(StepResult::Continue);
+                                }
+                                _ => return Err(AgentusError::runtime(format!("unknown number method '{}'", method_name))),
+                            }
                         }
+                        _ => {}
+                    }
 
-                        let caller_func_idx = self.call_stack.last().unwrap().function_idx;
-                        let caller_pc = self.call_stack.last().unwrap().pc;
-                        let return_info = Some((caller_func_idx, caller_pc, result_reg));
+                    let agent_id = match &handle {
+                        Value::AgentHandle(id) => *id,
+                        _ => return Err(AgentusError::runtime(format!("method call on non-agent: {}", handle))),
+                    };
 
-                        self.push_frame_with_agent(method_func_idx, return_info, Some(agent_id))?;
+                    let agent = self.agents.get(&agent_id)
+                        .ok_or_else(|| AgentusError::runtime(format!("agent {} not found", agent_id)))?;
+                    let desc_idx = agent.descriptor_idx;
+                    let descriptor = self.module.get_agent(desc_idx)
+                        .ok_or_else(|| AgentusError::runtime(format!("agent descriptor {} not found", desc_idx)))?
+                        .clone();
 
-                        // Copy arguments (params are r0, r1, ...)
-                        for (i, val) in arg_values.into_iter().enumerate() {
-                            self.set_register(i, val);
-                        }
-                    } else {
-                        // Regular function call
-                        let func_idx = func_idx_raw as u32;
-
-                        // Read the extra data word (next instruction)
-                        let frame = self.call_stack.last().unwrap();
-                        let extra_pc = frame.pc;
-                        let func = self.module.get_function(frame.function_idx)
-                            .ok_or("invalid function index")?;
-                        let extra = func.instructions[extra_pc];
-                        // Advance PC past the extra word
-                        self.call_stack.last_mut().unwrap().pc += 1;
-
-                        let first_arg_reg = extra.b() as usize;
-                        let num_args = extra.c() as usize;
-
-                        // Collect argument values from caller's registers
-                        let mut arg_values = Vec::with_capacity(num_args);
-                        for i in 0..num_args {
-                            arg_values.push(self.get_register(first_arg_reg + i).clone());
-                        }
+                    // Find method by name
+                    let method_func_idx = self.find_method(&descriptor, &method_name)
+                        .ok_or_else(|| AgentusError::runtime(format!("method '{}' not found on agent", method_name)))?;
 
-                        // Save return info
-                        let caller_func_idx = self.call_stack.last().unwrap().function_idx;
-                        let caller_pc = self.call_stack.last().unwrap().pc;
-                        let return_info = Some((caller_func_idx, caller_pc, result_reg));
+                    // Collect arguments (skip the handle at first_arg_reg)
+                    let mut arg_values = Vec::with_capacity(if num_args > 0 { num_args - 1 } else { 0 });
+                    for i in 1..num_args {
+                        arg_values.push(self.get_register(first_arg_reg + i).clone());
+                    }
+                    self.check_method_arity(method_func_idx, &method_name, arg_values.len())?;
 
-                        // Push new frame
-                        self.push_frame(func_idx, return_info)?;
+                    let caller_func_idx = self.call_stack.last().unwrap().function_idx;
+                    let caller_pc = self.call_stack.last().unwrap().pc;
+                    let return_info = Some((caller_func_idx, caller_pc, result_reg));
 
-                        // Copy arguments into the new frame's registers
-                        for (i, val) in arg_values.into_iter().enumerate() {
-                            self.set_register(i, val);
-                        }
-                    }
-                }
+                    self.push_call_frame(method_func_idx, return_info, Some(agent_id), arg_values)?;
+                } else {
+                    // Regular function call
+                    let func_idx = func_idx_raw as u32;
 
-                // Return
-                OpCode::Ret => {
-                    let a = inst.a() as usize;
-                    let return_value = self.get_register(a).clone();
-                    let frame = self.call_stack.pop().unwrap();
-                    if let Some((_func_idx, _pc, ret_reg)) = frame.return_info {
-                        self.set_register(ret_reg as usize, return_value);
+                    // Read the extra data word (next instruction)
+                    let frame = self.call_stack.last().unwrap();
+                    let extra_pc = frame.pc;
+                    let func = self.module.get_function(frame.function_idx)
+                        .ok_or_else(|| AgentusError::runtime("invalid function index"))?;
+                    let extra = func.instructions[extra_pc];
+                    // Advance PC past the extra word
+                    self.call_stack.last_mut().unwrap().pc += 1;
+
+                    let first_arg_reg = extra.b() as usize;
+                    let num_args = extra.c() as usize;
+
+                    // Collect argument values from caller's registers
+                    let mut arg_values = Vec::with_capacity(num_args);
+                    for i in 0..num_args {
+                        arg_values.push(self.get_register(first_arg_reg + i).clone());
                     }
-                }
-                OpCode::RetNone => {
-                    let frame = self.call_stack.pop().unwrap();
-                    if let Some((_func_idx, _pc, ret_reg)) = frame.return_info {
-                        self.set_register(ret_reg as usize, Value::None);
+
+                    // Save return info
+                    let caller_func_idx = self.call_stack.last().unwrap().function_idx;
+                    let caller_pc = self.call_stack.last().unwrap().pc;
+                    let return_info = Some((caller_func_idx, caller_pc, result_reg));
+
+                    // Push new frame
+                    self.push_frame(func_idx, return_info)?;
+
+                    // Copy arguments into the new frame's registers
+                    for (i, val) in arg_values.into_iter().enumerate() {
+                        self.set_register(i, val);
                     }
                 }
+            }
 
-                // Collections
-                OpCode::NewList => {
-                    let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
-                    let mut items = Vec::with_capacity(c);
-                    for i in 0..c {
-                        items.push(self.get_register(b + i).clone());
-                    }
-                    self.set_register(a, Value::List(std::rc::Rc::new(std::cell::RefCell::new(items))));
+            OpCode::MakeFn => {
+                let (a, bx) = (inst.a() as usize, inst.bx() as u32);
+                self.set_register(a, Value::Function(bx));
+            }
+
+            // Return
+            OpCode::Ret => {
+                let a = inst.a() as usize;
+                let return_value = self.get_register(a).clone();
+                let frame = self.call_stack.pop().unwrap();
+                if let Some((_func_idx, _pc, ret_reg)) = frame.return_info {
+                    self.set_register(ret_reg as usize, return_value);
+                } else {
+                    self.return_value = Some(return_value);
                 }
-                OpCode::NewMap => {
-                    let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
-                    let mut map = std::collections::HashMap::new();
-                    for i in 0..c {
-                        let key = self.get_register(b + i * 2).to_string();
-                        let val = self.get_register(b + i * 2 + 1).clone();
-                        map.insert(key, val);
-                    }
-                    self.set_register(a, Value::Map(std::rc::Rc::new(std::cell::RefCell::new(map))));
+            }
+            OpCode::Yield => {
+                let a = inst.a() as usize;
+                let value = self.get_register(a).clone();
+                let depth = self.call_stack.len();
+                if self.error_handlers.last().is_some_and(|h| h.call_stack_depth == depth) {
+                    return Err(AgentusError::runtime("cannot yield inside a try block"));
                 }
-                OpCode::IndexGet => {
-                    let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
-                    let obj = self.get_register(b).clone();
-                    let idx = self.get_register(c).clone();
-                    let result = match (&obj, &idx) {
-                        (Value::List(list), Value::Num(n)) => {
-                            let i = *n as usize;
-                            let items = list.borrow();
-                            items.get(i).cloned().unwrap_or(Value::None)
-                        }
-                        (Value::Map(map), Value::Str(key)) => {
-                            let items = map.borrow();
-                            items.get(key.as_str()).cloned().unwrap_or(Value::None)
-                        }
-                        _ => return Err(format!("cannot index {:?} with {:?}", obj, idx)),
-                    };
-                    self.set_register(a, result);
+                let frame = self.call_stack.pop().unwrap();
+                let agent_id = frame.agent_id
+                    .ok_or_else(|| AgentusError::runtime("yield outside of an agent method"))?;
+                let return_info = frame.return_info;
+                // Save the frame (PC already past the yield) for the next call
+                self.agents.get_mut(&agent_id)
+                    .ok_or_else(|| AgentusError::runtime(format!("agent {} not found", agent_id)))?
+                    .coroutines.insert(frame.function_idx, frame);
+                if let Some((_func_idx, _pc, ret_reg)) = return_info {
+                    self.set_register(ret_reg as usize, value);
+                } else {
+                    self.return_value = Some(value);
+                }
+            }
+            OpCode::RetNone => {
+                let frame = self.call_stack.pop().unwrap();
+                if let Some((_func_idx, _pc, ret_reg)) = frame.return_info {
+                    self.set_register(ret_reg as usize, Value::None);
                 }
+            }
 
-                OpCode::IndexSet => {
-                    let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
-                    let idx_val = self.get_register(b).clone();
-                    let val = self.get_register(c).clone();
-                    let obj = self.get_register(a).clone();
-                    match (&obj, &idx_val) {
-                        (Value::List(list), Value::Num(n)) => {
-                            let i = *n as usize;
-                            let mut items = list.borrow_mut();
-                            if i < items.len() {
-                                items[i] = val;
-                            } else {
-                                return Err(format!("list index {} out of bounds", i));
-                            }
-                        }
-                        (Value::Map(map), Value::Str(key)) => {
-                            map.borrow_mut().insert(key.to_string(), val);
-                        }
-                        _ => return Err(format!("cannot index-set {:?} with {:?}", obj, idx_val)),
-                    }
+            // Collections
+            OpCode::NewList => {
+                let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
+                let mut items = Vec::with_capacity(c);
+                for i in 0..c {
+                    items.push(self.get_register(b + i).clone());
                 }
-                OpCode::Len => {
-                    let (a, b) = (inst.a() as usize, inst.b() as usize);
-                    let obj = self.get_register(b).clone();
-                    let len = match &obj {
-                        Value::List(l) => l.borrow().len(),
-                        Value::Map(m) => m.borrow().len(),
-                        Value::Str(s) => s.len(),
-                        _ => return Err(format!("cannot get length of {:?}", obj)),
-                    };
-                    self.set_register(a, Value::Num(len as f64));
+                self.set_register(a, Value::List(std::rc::Rc::new(std::cell::RefCell::new(items))));
+            }
+            OpCode::NewMap => {
+                let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
+                let mut map = std::collections::HashMap::new();
+                for i in 0..c {
+                    let key = self.get_register(b + i * 2).to_string();
+                    let val = self.get_register(b + i * 2 + 1).clone();
+                    map.insert(key, val);
                 }
-                OpCode::ListPush => {
-                    let (a, b) = (inst.a() as usize, inst.b() as usize);
-                    let val = self.get_register(b).clone();
-                    let list = self.get_register(a).clone();
-                    match &list {
-                        Value::List(l) => l.borrow_mut().push(val),
-                        _ => return Err(format!("cannot push to {:?}", list)),
+                self.set_register(a, Value::Map(std::rc::Rc::new(std::cell::RefCell::new(map))));
+            }
+            OpCode::IndexGet => {
+                let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
+                let obj = self.get_register(b).clone();
+                let idx = self.get_register(c).clone();
+                let result = match (&obj, &idx) {
+                    (Value::List(list), Value::Num(n)) => {
+                        let i = *n as usize;
+                        let items = list.borrow();
+                        items.get(i).cloned().unwrap_or(Value::None)
                     }
-                }
-                OpCode::ParseJson => {
-                    let (a, b) = (inst.a() as usize, inst.b() as usize);
-                    let val = self.get_register(b).clone();
-                    let json_str = val.to_string();
-                    match Value::parse_json(&json_str) {
-                        Ok(parsed) => self.set_register(a, parsed),
-                        Err(e) => {
-                            // Throw a parse error
-                            let err_val = Value::from_string(format!("parse_json error: {}", e));
-                            self.throw_error(err_val)?;
+                    (Value::Map(map), Value::Str(key)) => {
+                        let items = map.borrow();
+                        items.get(key.as_str()).cloned().unwrap_or(Value::None)
+                    }
+                    (Value::Env, Value::Str(name)) => std::env::var(name.as_str())
+                        .map(Value::from_string)
+                        .unwrap_or(Value::None),
+                    _ => return Err(AgentusError::runtime(format!("cannot index {:?} with {:?}", obj, idx))),
+                };
+                self.set_register(a, result);
+            }
+
+            OpCode::IndexSet => {
+                let (a, b, c) = (inst.a() as usize, inst.b() as usize, inst.c() as usize);
+                let idx_val = self.get_register(b).clone();
+                let val = self.get_register(c).clone();
+                let obj = self.get_register(a).clone();
+                match (&obj, &idx_val) {
+                    (Value::List(list), Value::Num(n)) => {
+                        let i = *n as usize;
+                        let mut items = list.borrow_mut();
+                        if i < items.len() {
+                            items[i] = val;
+                        } else {
+                            return Err(AgentusError::runtime(format!("list index {} out of bounds", i)));
                         }
                     }
+                    (Value::Map(map), Value::Str(key)) => {
+                        map.borrow_mut().insert(key.to_string(), val);
+                    }
+                    _ => return Err(AgentusError::runtime(format!("cannot index-set {:?} with {:?}", obj, idx_val))),
                 }
-                OpCode::ToJson => {
-                    let (a, b) = (inst.a() as usize, inst.b() as usize);
-                    let val = self.get_register(b).clone();
-                    let json = val.to_json();
-                    self.set_register(a, Value::from_string(json));
+            }
+            OpCode::Len => {
+                let (a, b) = (inst.a() as usize, inst.b() as usize);
+                let obj = self.get_register(b).clone();
+                let len = match &obj {
+                    Value::List(l) => l.borrow().len(),
+                    Value::Map(m) => m.borrow().len(),
+                    Value::Str(s) => s.len(),
+                    Value::Bytes(b) => b.len(),
+                    _ => return Err(AgentusError::runtime(format!("cannot get length of {:?}", obj))),
+                };
+                self.set_register(a, Value::Num(len as f64));
+            }
+            OpCode::ListPush => {
+                let (a, b) = (inst.a() as usize, inst.b() as usize);
+                let val = self.get_register(b).clone();
+                let list = self.get_register(a).clone();
+                match &list {
+                    Value::List(l) => l.borrow_mut().push(val),
+                    _ => return Err(AgentusError::runtime(format!("cannot push to {:?}", list))),
                 }
-                OpCode::StrLen => {
-                    let (a, b) = (inst.a() as usize, inst.b() as usize);
-                    let val = self.get_register(b).clone();
-                    match &val {
-                        Value::Str(s) => self.set_register(a, Value::Num(s.len() as f64)),
-                        _ => return Err(format!("StrLen requires string, got {:?}", val)),
+            }
+            OpCode::ParseJson => {
+                let (a, b) = (inst.a() as usize, inst.b() as usize);
+                let val = self.get_register(b).clone();
+                let json_str = val.to_string();
+                match Value::parse_json(&json_str) {
+                    Ok(parsed) => self.set_register(a, parsed),
+                    Err(e) => {
+                        // Throw a parse error
+                        let err_val = Value::Error(Rc::new(format!("parse_json error: {}", e)));
+                        self.throw_error(err_val)?;
                     }
                 }
+            }
+            OpCode::ToJson => {
+                let (a, b) = (inst.a() as usize, inst.b() as usize);
+                let val = self.get_register(b).clone();
+                let json = val.coerce_to_str(CoerceMode::Json);
+                self.set_register(a, Value::from_string(json));
+            }
+            OpCode::ToBase64 => {
+                let (a, b) = (inst.a() as usize, inst.b() as usize);
+                let encoded = match self.get_register(b) {
+                    Value::Bytes(data) => to_base64(data),
+                    other => return Err(AgentusError::runtime(format!("to_base64() requires bytes, got {}", other))),
+                };
+                self.set_register(a, Value::from_string(encoded));
+            }
+            OpCode::FromBase64 => {
+                let (a, b) = (inst.a() as usize, inst.b() as usize);
+                let decoded = match self.get_register(b) {
+                    Value::Str(s) => from_base64(s),
+                    other => return Err(AgentusError::runtime(format!("from_base64() requires a string, got {}", other))),
+                };
+                match decoded {
+                    Ok(data) => self.set_register(a, Value::from_bytes(data)),
+                    Err(e) => {
+                        let err_val = Value::Error(Rc::new(format!("from_base64 error: {}", e)));
+                        self.throw_error(err_val)?;
+                    }
+                }
+            }
+            OpCode::StrLen => {
+                let (a, b) = (inst.a() as usize, inst.b() as usize);
+                let val = self.get_register(b).clone();
+                match &val {
+                    Value::Str(s) => self.set_register(a, Value::Num(s.len() as f64)),
+                    _ => return Err(AgentusError::runtime(format!("StrLen requires string, got {:?}", val))),
+                }
+            }
 
-                // Iterators
-                OpCode::IterInit => {
-                    let (a, b) = (inst.a() as usize, inst.b() as usize);
-                    let source = self.get_register(b).clone();
-                    let items = match &source {
-                        Value::List(l) => l.borrow().clone(),
+            // Iterators
+            OpCode::IterInit => {
+                let (a, b) = (inst.a() as usize, inst.b() as usize);
+                let source = self.get_register(b).clone();
+                let items = match &source {
+                    Value::List(l) => l.borrow().clone(),
+                    Value::Map(m) => {
+                        // Iterate over keys
+                        m.borrow().keys().map(|k| Value::from_str(k)).collect()
+                    }
+                    // Iterate over single-character strings
+                    Value::Str(s) => s.chars().map(|c| Value::from_string(c.to_string())).collect(),
+                    _ => return Err(AgentusError::runtime(format!("cannot iterate over {:?}", source))),
+                };
+                // C=1: iterate [key, value] pairs, pairing list and string
+                // items with their index and visiting map keys in sorted order
+                let items = if inst.c() == 1 {
+                    let pair = |k: Value, v: Value| Value::List(Rc::new(std::cell::RefCell::new(vec![k, v])));
+                    match &source {
                         Value::Map(m) => {
-                            // Iterate over keys
-                            m.borrow().keys().map(|k| Value::from_str(k)).collect()
+                            let mut entries: Vec<(String, Value)> = m.borrow()
+                                .iter()
+                                .map(|(k, v)| (k.clone(), v.clone()))
+                                .collect();
+                            entries.sort_by(|x, y| x.0.cmp(&y.0));
+                            entries.into_iter().map(|(k, v)| pair(Value::from_string(k), v)).collect()
                         }
-                        _ => return Err(format!("cannot iterate over {:?}", source)),
-                    };
-                    self.set_register(
-                        a,
-                        Value::Iterator(std::rc::Rc::new(std::cell::RefCell::new((items, 0)))),
-                    );
-                }
-                OpCode::IterNext => {
-                    // Two-instruction sequence:
-                    // 1. IterNext A=var_reg, sBx=jump_offset_if_exhausted
-                    // 2. Extra data: B=iter_reg
-                    let var_reg = inst.a() as usize;
-                    let jump_offset = inst.sbx_16();
-
-                    // Read extra data word
-                    let frame = self.call_stack.last().unwrap();
-                    let extra_pc = frame.pc;
-                    let func = self.module.get_function(frame.function_idx)
-                        .ok_or("invalid function index")?;
-                    let extra = func.instructions[extra_pc];
-                    self.call_stack.last_mut().unwrap().pc += 1;
-
-                    let iter_reg = extra.b() as usize;
-
-                    let iter_val = self.get_register(iter_reg).clone();
-                    match &iter_val {
-                        Value::Iterator(state) => {
-                            let mut st = state.borrow_mut();
-                            if st.1 < st.0.len() {
-                                let val = st.0[st.1].clone();
-                                st.1 += 1;
-                                drop(st);
-                                self.set_register(var_reg, val);
+                        _ => items.into_iter().enumerate()
+                            .map(|(i, v)| pair(Value::Num(i as f64), v))
+                            .collect(),
+                    }
+                } else {
+                    items
+                };
+                self.set_register(
+                    a,
+                    Value::Iterator(std::rc::Rc::new(std::cell::RefCell::new((items, 0)))),
+                );
+            }
+            OpCode::IterNext => {
+                // Two-instruction sequence:
+                // 1. IterNext A=var_reg, sBx=jump_offset_if_exhausted
+                // 2. Extra data: A=pairs flag, B=iter_reg, C=value_reg
+                let var_reg = inst.a() as usize;
+                let jump_offset = inst.sbx_16();
+
+                // Read extra data word
+                let frame = self.call_stack.last().unwrap();
+                let extra_pc = frame.pc;
+                let func = self.module.get_function(frame.function_idx)
+                    .ok_or_else(|| AgentusError::runtime("invalid function index"))?;
+                let extra = func.instructions[extra_pc];
+                self.call_stack.last_mut().unwrap().pc += 1;
+
+                let iter_reg = extra.b() as usize;
+
+                let iter_val = self.get_register(iter_reg).clone();
+                match &iter_val {
+                    Value::Iterator(state) => {
+                        let mut st = state.borrow_mut();
+                        if st.1 < st.0.len() {
+                            let val = st.0[st.1].clone();
+                            st.1 += 1;
+                            drop(st);
+                            // A=1: split a [key, value] pair across r(A) and r(C)
+                            if extra.a() == 1 && let Value::List(pair) = &val {
+                                let (key, value) = {
+                                    let pair = pair.borrow();
+                                    (pair[0].clone(), pair[1].clone())
+                                };
+                                self.set_register(var_reg, key);
+                                self.set_register(extra.c() as usize, value);
                             } else {
-                                drop(st);
-                                // Iterator exhausted — jump
-                                let frame = self.call_stack.last_mut().unwrap();
-                                frame.pc = (frame.pc as i32 + jump_offset as i32) as usize;
+                                self.set_register(var_reg, val);
                             }
-                        }
-                        _ => return Err(format!("IterNext on non-iterator: {:?}", iter_val)),
-                    }
-                }
-
-                // Agent memory
-                OpCode::MLoad => {
-                    let a = inst.a() as usize;
-                    let bx = inst.bx();
-                    let field_name = self.load_constant_str(bx)?;
-                    let agent_id = self.current_agent_id()?;
-                    let agent = self.agents.get(&agent_id)
-                        .ok_or_else(|| format!("agent {} not found", agent_id))?;
-                    let value = agent.memory.get(&field_name)
-                        .cloned()
-                        .unwrap_or(Value::None);
-                    self.set_register(a, value);
-                }
-                OpCode::MStore => {
-                    let a = inst.a() as usize;
-                    let bx = inst.bx();
-                    let field_name = self.load_constant_str(bx)?;
-                    let value = self.get_register(a).clone();
-                    let agent_id = self.current_agent_id()?;
-                    let agent = self.agents.get_mut(&agent_id)
-                        .ok_or_else(|| format!("agent {} not found", agent_id))?;
-                    agent.memory.insert(field_name, value);
-                }
-
-                // Agent spawn
-                OpCode::Spawn => {
-                    let a = inst.a() as usize;
-                    let bx = inst.bx() as u32;
-                    let descriptor = self.module.get_agent(bx)
-                        .ok_or_else(|| format!("agent descriptor {} not found", bx))?
-                        .clone();
-
-                    // Initialize memory with defaults
-                    let mut memory = HashMap::new();
-                    for field in &descriptor.memory_fields {
-                        let name = self.load_constant_str(field.name_idx)?;
-                        let default_val = if let Some(default_idx) = field.default_idx {
-                            self.load_constant(default_idx)?
                         } else {
-                            Value::None
-                        };
-                        memory.insert(name, default_val);
+                            drop(st);
+                            // Iterator exhausted — jump
+                            let frame = self.call_stack.last_mut().unwrap();
+                            frame.pc = (frame.pc as i32 + jump_offset as i32) as usize;
+                        }
                     }
-
-                    let id = self.next_agent_id;
-                    self.next_agent_id += 1;
-                    self.agents.insert(id, AgentInstance {
-                        descriptor_idx: bx,
-                        memory,
-                        mailbox: VecDeque::new(),
-                    });
-                    self.set_register(a, Value::AgentHandle(id));
+                    _ => return Err(AgentusError::runtime(format!("IterNext on non-iterator: {:?}", iter_val))),
                 }
+            }
 
-                // LLM execution
-                OpCode::Exec => {
-                    let a = inst.a() as usize;
-                    let b = inst.b() as usize;
-                    let prompt = self.get_register(b).to_string();
+            // Agent memory
+            OpCode::MLoad => {
+                let a = inst.a() as usize;
+                let bx = inst.bx();
+                let field_name = self.load_constant_str(bx)?;
+                let agent_id = self.current_agent_id()?;
+                let agent = self.agents.get(&agent_id)
+                    .ok_or_else(|| AgentusError::runtime(format!("agent {} not found", agent_id)))?;
+                let value = agent.memory.get(&field_name)
+                    .cloned()
+                    .unwrap_or(Value::None);
+                self.set_register(a, value);
+            }
+            OpCode::MStore => {
+                let a = inst.a() as usize;
+                let bx = inst.bx();
+                let field_name = self.load_constant_str(bx)?;
+                let value = self.get_register(a).clone();
+                let agent_id = self.current_agent_id()?;
+                let agent = self.agents.get_mut(&agent_id)
+                    .ok_or_else(|| AgentusError::runtime(format!("agent {} not found", agent_id)))?;
+                agent.memory.insert(field_name, value);
+            }
 
-                    // Get model/system_prompt from agent context if available
-                    let (model, sys_prompt) = self.get_agent_context();
+            // Agent spawn
+            OpCode::Spawn => {
+                let a = inst.a() as usize;
+                let bx = inst.bx() as u32;
+                let id = self.spawn_instance(bx)?;
+                self.set_register(a, Value::AgentHandle(id));
+            }
 
-                    let request = ExecRequest {
-                        model: model.unwrap_or_else(|| "default".to_string()),
-                        system_prompt: sys_prompt,
-                        user_prompt: prompt,
-                    };
-                    let result = self.host.exec(request).map_err(|e| format!("exec error: {}", e))?;
-                    self.set_register(a, Value::from_string(result));
-                }
+            // LLM execution
+            OpCode::Exec => {
+                let a = inst.a() as usize;
+                let b = inst.b() as usize;
+                let prompt = self.get_register(b).to_string();
+
+                // Get model/system_prompt from agent context if available
+                let (model, sys_prompt) = self.get_agent_context();
+
+                let request = ExecRequest {
+                    model: model.unwrap_or_else(|| "default".to_string()),
+                    system_prompt: sys_prompt,
+                    user_prompt: prompt,
+                };
+                let result = self.host.exec(request).map_err(|e| AgentusError::runtime(format!("exec error: {}", e)))?;
+                self.set_register(a, Value::from_string(result));
+            }
 
-                // Agent message passing
-                OpCode::Send => {
-                    let a = inst.a() as usize;
-                    let b = inst.b() as usize;
-                    let handle = self.get_register(a).clone();
-                    let message = self.get_register(b).clone();
-                    let agent_id = match &handle {
+            // Agent message passing
+            OpCode::Send => {
+                let a = inst.a() as usize;
+                let b = inst.b() as usize;
+                let message = self.get_register(b).clone();
+                // C=1: `send self, msg` from inside an agent method
+                let agent_id = if inst.c() == 1 {
+                    self.current_agent_id()?
+                } else {
+                    match self.get_register(a) {
                         Value::AgentHandle(id) => *id,
-                        _ => return Err(format!("send target is not an agent handle: {}", handle)),
-                    };
-                    let agent = self.agents.get_mut(&agent_id)
-                        .ok_or_else(|| format!("agent {} not found", agent_id))?;
-                    agent.mailbox.push_back(message);
-                }
-                OpCode::Recv => {
-                    let a = inst.a() as usize;
-                    let b = inst.b() as usize;
-                    let handle = self.get_register(b).clone();
-                    let agent_id = match &handle {
+                        handle => return Err(AgentusError::runtime(format!("send target is not an agent handle: {}", handle))),
+                    }
+                };
+                let agent = self.agents.get_mut(&agent_id)
+                    .ok_or_else(|| AgentusError::runtime(format!("agent {} not found", agent_id)))?;
+                agent.mailbox.push_back(message);
+            }
+            OpCode::Kill => {
+                let handle = self.get_register(inst.a() as usize).clone();
+                let agent_id = match &handle {
+                    Value::AgentHandle(id) => *id,
+                    _ => return Err(AgentusError::runtime(format!("kill target is not an agent handle: {}", handle))),
+                };
+                let desc_idx = self.agents.get(&agent_id)
+                    .ok_or_else(|| AgentusError::runtime(format!("agent {} not found", agent_id)))?
+                    .descriptor_idx;
+                self.run_lifecycle_hook(desc_idx, agent_id, "on_kill")?;
+                self.agents.remove(&agent_id);
+            }
+            OpCode::Recv => {
+                let a = inst.a() as usize;
+                let b = inst.b() as usize;
+                // C=1: `recv self` from inside an agent method
+                let agent_id = if inst.c() == 1 {
+                    self.current_agent_id()?
+                } else {
+                    match self.get_register(b) {
                         Value::AgentHandle(id) => *id,
-                        _ => return Err(format!("recv target is not an agent handle: {}", handle)),
-                    };
-                    let agent = self.agents.get_mut(&agent_id)
-                        .ok_or_else(|| format!("agent {} not found", agent_id))?;
-                    let value = agent.mailbox.pop_front().unwrap_or(Value::None);
-                    self.set_register(a, value);
-                }
-
-                // Tool call
-                OpCode::TCall => {
-                    let result_reg = inst.a() as usize;
-                    let tool_desc_idx = inst.bx() as u32;
-
-                    // Read the extra data word (next instruction)
-                    let frame = self.call_stack.last().unwrap();
-                    let extra_pc = frame.pc;
-                    let func = self.module.get_function(frame.function_idx)
-                        .ok_or("invalid function index")?;
-                    let extra = func.instructions[extra_pc];
-                    self.call_stack.last_mut().unwrap().pc += 1;
-
-                    let first_arg_reg = extra.b() as usize;
-                    let num_args = extra.c() as usize;
-
-                    // Get tool descriptor
-                    let tool_desc = self.module.get_tool(tool_desc_idx)
-                        .ok_or_else(|| format!("tool descriptor {} not found", tool_desc_idx))?
-                        .clone();
-
-                    let tool_name = self.load_constant_str(tool_desc.name_idx)?;
-
-                    // Build named arguments from registers + param names
-                    let mut args = Vec::new();
-                    for i in 0..num_args {
-                        let param_name = if i < tool_desc.params.len() {
-                            self.load_constant_str(tool_desc.params[i].name_idx)?
-                        } else {
-                            format!("arg{}", i)
-                        };
-                        let value = self.get_register(first_arg_reg + i).to_string();
-                        args.push((param_name, value));
+                        handle => return Err(AgentusError::runtime(format!("recv target is not an agent handle: {}", handle))),
                     }
+                };
+                let agent = self.agents.get_mut(&agent_id)
+                    .ok_or_else(|| AgentusError::runtime(format!("agent {} not found", agent_id)))?;
+                let value = agent.mailbox.pop_front().unwrap_or(Value::None);
+                self.set_register(a, value);
+            }
+
+            // Tool call
+            OpCode::TCall => {
+                let result_reg = inst.a() as usize;
+                let tool_desc_idx = inst.bx() as u32;
+
+                // Read the extra data word (next instruction)
+                let frame = self.call_stack.last().unwrap();
+                let extra_pc = frame.pc;
+                let func = self.module.get_function(frame.function_idx)
+                    .ok_or_else(|| AgentusError::runtime("invalid function index"))?;
+                let extra = func.instructions[extra_pc];
+                self.call_stack.last_mut().unwrap().pc += 1;
+
+                let (tool_name, args) =
+                    self.tool_call_args(tool_desc_idx, extra.b() as usize, extra.c() as usize)?;
+
+                let output = &self.output;
+                let log_callback = |level: u8, message: &str| output.on_log(level, message);
+                let request = ToolCallRequest {
+                    tool_name,
+                    args,
+                    log_callback: Some(&log_callback),
+                };
+                let result = self.host.tool_call(request)
+                    .map_err(|e| AgentusError::runtime(format!("tool call error: {}", e)))?;
+                self.set_register(result_reg, result);
+            }
+            OpCode::BatchTCall => {
+                let result_reg = inst.a() as usize;
+                let count = inst.b() as usize;
+
+                // The next `count` TCall + data word pairs describe the requests
+                let frame = self.call_stack.last().unwrap();
+                let start_pc = frame.pc;
+                let func = self.module.get_function(frame.function_idx)
+                    .ok_or_else(|| AgentusError::runtime("invalid function index"))?;
+                let calls: Vec<(u32, usize, usize)> = (0..count)
+                    .map(|i| {
+                        let tcall = func.instructions.get(start_pc + i * 2).copied();
+                        let extra = func.instructions.get(start_pc + i * 2 + 1).copied();
+                        match (tcall, extra) {
+                            (Some(tcall), Some(extra)) if tcall.opcode() == Some(OpCode::TCall) => {
+                                Ok((tcall.bx() as u32, extra.b() as usize, extra.c() as usize))
+                            }
+                            _ => Err(AgentusError::runtime("malformed BatchTCall sequence")),
+                        }
+                    })
+                    .collect::<Result<_, _>>()?;
+                self.call_stack.last_mut().unwrap().pc += count * 2;
 
-                    let request = ToolCallRequest {
+                let mut named_args = Vec::with_capacity(count);
+                for (tool_desc_idx, first_arg_reg, num_args) in calls {
+                    named_args.push(self.tool_call_args(tool_desc_idx, first_arg_reg, num_args)?);
+                }
+                let output = &self.output;
+                let log_callback = |level: u8, message: &str| output.on_log(level, message);
+                let requests = named_args
+                    .into_iter()
+                    .map(|(tool_name, args)| ToolCallRequest {
                         tool_name,
                         args,
-                    };
-                    let result = self.host.tool_call(request)
-                        .map_err(|e| format!("tool call error: {}", e))?;
-                    self.set_register(result_reg, Value::from_string(result));
+                        log_callback: Some(&log_callback),
+                    })
+                    .collect();
+                let results = self.host.batch_tool_call(requests)
+                    .map_err(|e| AgentusError::runtime(format!("tool call error: {}", e)))?;
+                if results.len() != count {
+                    return Err(AgentusError::runtime(format!(
+                        "tool call error: batch of {} requests returned {} results",
+                        count,
+                        results.len()
+                    )));
                 }
+                self.set_register(result_reg, Value::List(Rc::new(std::cell::RefCell::new(results))));
+            }
 
-                // Error handling
-                OpCode::TryBegin => {
-                    let err_reg = inst.a();
-                    let offset = inst.sbx_16();
-                    let frame = self.call_stack.last().unwrap();
-                    let catch_pc = (frame.pc as i32 + offset as i32) as usize;
-                    self.error_handlers.push(ErrorHandler {
-                        catch_pc,
-                        err_reg,
-                        call_stack_depth: self.call_stack.len(),
-                        function_idx: frame.function_idx,
-                    });
-                }
-                OpCode::TryEnd => {
-                    self.error_handlers.pop();
-                }
-                OpCode::Throw => {
-                    let a = inst.a() as usize;
-                    let error_value = self.get_register(a).clone();
-                    self.throw_error(error_value)?;
-                }
-                OpCode::GetError => {
-                    let a = inst.a() as usize;
-                    let error = self.current_error.clone().unwrap_or(Value::None);
-                    self.set_register(a, error);
+            // Pipeline: run each stage on the previous stage's output
+            OpCode::PipelineRun => {
+                let result_reg = inst.a() as usize;
+                let pipeline_idx = inst.bx() as u32;
+
+                // Read the extra data word (next instruction)
+                let frame = self.call_stack.last().unwrap();
+                let extra_pc = frame.pc;
+                let func = self.module.get_function(frame.function_idx)
+                    .ok_or_else(|| AgentusError::runtime("invalid function index"))?;
+                let extra = func.instructions[extra_pc];
+                self.call_stack.last_mut().unwrap().pc += 1;
+
+                let stages = self.module.get_pipeline(pipeline_idx)
+                    .ok_or_else(|| AgentusError::runtime(format!("pipeline descriptor {} not found", pipeline_idx)))?
+                    .stages
+                    .clone();
+                let mut value = self.get_register(extra.b() as usize).clone();
+                for func_idx in stages {
+                    value = self.call_value(&Value::Function(func_idx), vec![value])?;
                 }
+                self.set_register(result_reg, value);
+            }
 
-                // Stubs for not-yet-implemented opcodes
-                _ => {
-                    return Err(format!("opcode {:?} not yet implemented", opcode));
-                }
+            // Error handling
+            OpCode::TryBegin => {
+                let err_reg = inst.a();
+                let offset = inst.sbx_16();
+                let frame = self.call_stack.last().unwrap();
+                let catch_pc = (frame.pc as i32 + offset as i32) as usize;
+                self.error_handlers.push(ErrorHandler {
+                    catch_pc,
+                    err_reg,
+                    call_stack_depth: self.call_stack.len(),
+                    function_idx: frame.function_idx,
+                });
+            }
+            OpCode::TryEnd => {
+                self.error_handlers.pop();
+            }
+            OpCode::Throw => {
+                let a = inst.a() as usize;
+                let error_value = self.get_register(a).clone();
+                self.throw_error(error_value)?;
+            }
+            OpCode::GetError => {
+                let a = inst.a() as usize;
+                let error = self.current_error.clone().unwrap_or(Value::None);
+                self.set_register(a, error);
+            }
+
+            // Stubs for not-yet-implemented opcodes
+            _ => {
+                return Err(AgentusError::runtime(format!("opcode {:?} not yet implemented", opcode)));
             }
         }
+        Ok(StepResult::Continue)
     }
 
     // =====================================================================
@@ -895,11 +1916,11 @@ impl VM {
         frame.registers[idx] = value;
     }
 
-    fn load_constant(&self, idx: u16) -> Result<Value, String> {
+    fn load_constant(&self, idx: u16) -> Result<Value, AgentusError> {
         let constant = self
             .module
             .get_constant(idx)
-            .ok_or_else(|| format!("constant {} not found", idx))?;
+            .ok_or_else(|| AgentusError::runtime(format!("constant {} not found", idx)))?;
         Ok(match constant {
             Constant::None => Value::None,
             Constant::Bool(b) => Value::Bool(*b),
@@ -908,20 +1929,121 @@ impl VM {
         })
     }
 
+    /// Call a function value with the given arguments and run it to
+    /// completion. The callee runs in the calling frame's agent, if any.
+    fn call_value(&mut self, callee: &Value, args: Vec<Value>) -> Result<Value, AgentusError> {
+        let func_idx = match callee {
+            Value::Function(idx) => *idx,
+            other => return Err(AgentusError::runtime(format!("cannot call {}", other))),
+        };
+        let func = self
+            .module
+            .get_function(func_idx)
+            .ok_or_else(|| AgentusError::runtime(format!("function {} not found", func_idx)))?;
+        if func.num_params as usize != args.len() {
+            return Err(AgentusError::runtime(format!(
+                "function expects {} arguments, got {}",
+                func.num_params,
+                args.len()
+            )));
+        }
+        let agent_id = self.call_stack.last().and_then(|f| f.agent_id);
+        self.call_nested(func_idx, agent_id, args)
+    }
+
+    /// Resolve a tool call's name and its named, stringified arguments from
+    /// `num_args` registers starting at `first_arg_reg`.
+    fn tool_call_args(
+        &self,
+        tool_desc_idx: u32,
+        first_arg_reg: usize,
+        num_args: usize,
+    ) -> Result<(String, Vec<(String, String)>), AgentusError> {
+        let tool_desc = self.module.get_tool(tool_desc_idx)
+            .ok_or_else(|| AgentusError::runtime(format!("tool descriptor {} not found", tool_desc_idx)))?;
+        let tool_name = self.load_constant_str(tool_desc.name_idx)?;
+
+        // Build named arguments from registers + param names
+        let mut args = Vec::new();
+        for i in 0..num_args {
+            let param_name = if i < tool_desc.params.len() {
+                self.load_constant_str(tool_desc.params[i].name_idx)?
+            } else {
+                format!("arg{}", i)
+            };
+            let value = self.get_register(first_arg_reg + i).to_string();
+            args.push((param_name, value));
+        }
+        Ok((tool_name, args))
+    }
+
+    /// Apply a built-in reducer named by an operator: `"+"`, `"*"` or `"++"`.
+    fn apply_named_reducer(op: &str, acc: Value, item: Value) -> Result<Value, AgentusError> {
+        match (op, &acc, &item) {
+            ("+", Value::Num(a), Value::Num(b)) => Ok(Value::Num(a + b)),
+            ("*", Value::Num(a), Value::Num(b)) => Ok(Value::Num(a * b)),
+            ("++", _, _) => Ok(Value::from_string(format!("{}{}", acc, item))),
+            ("+" | "*", _, _) => Err(AgentusError::runtime(format!(
+                "arithmetic requires numeric operands, got {} and {}",
+                acc, item
+            ))),
+            _ => Err(AgentusError::runtime(format!("unknown reducer '{}'", op))),
+        }
+    }
+
+    /// Every match of `pattern` in `s`: the capture groups of each match, or
+    /// the whole match when the pattern has no groups. Groups that did not
+    /// participate in a match are `none`.
+    #[cfg(feature = "regex")]
+    fn regex_matches(s: &str, pattern: &str) -> Result<Vec<Value>, AgentusError> {
+        let re = regex::Regex::new(pattern)
+            .map_err(|e| AgentusError::runtime(format!("invalid regex '{}': {}", pattern, e)))?;
+        let mut found = Vec::new();
+        for caps in re.captures_iter(s) {
+            if caps.len() == 1 {
+                found.push(Value::from_str(&caps[0]));
+                continue;
+            }
+            for group in caps.iter().skip(1) {
+                found.push(group.map_or(Value::None, |m| Value::from_str(m.as_str())));
+            }
+        }
+        Ok(found)
+    }
+
+    /// Replace every match of `pattern` in `s`; `$1` (or `${name}`) in
+    /// `replacement` expands to a capture group.
+    #[cfg(feature = "regex")]
+    fn regex_replace(s: &str, pattern: &str, replacement: &str) -> Result<String, AgentusError> {
+        let re = regex::Regex::new(pattern)
+            .map_err(|e| AgentusError::runtime(format!("invalid regex '{}': {}", pattern, e)))?;
+        Ok(re.replace_all(s, replacement).into_owned())
+    }
+
+    #[cfg(not(feature = "regex"))]
+    fn regex_matches(_s: &str, _pattern: &str) -> Result<Vec<Value>, AgentusError> {
+        Err(AgentusError::runtime("regex support is not enabled (build with the 'regex' feature)"))
+    }
+
+    #[cfg(not(feature = "regex"))]
+    fn regex_replace(_s: &str, _pattern: &str, _replacement: &str) -> Result<String, AgentusError> {
+        Err(AgentusError::runtime("regex support is not enabled (build with the 'regex' feature)"))
+    }
+
     fn arith_op(
         &self,
         b: usize,
         c: usize,
         op: fn(f64, f64) -> f64,
-    ) -> Result<Value, String> {
+    ) -> Result<Value, AgentusError> {
         let lhs = self.get_register(b);
         let rhs = self.get_register(c);
         match (lhs, rhs) {
             (Value::Num(a), Value::Num(b)) => Ok(Value::Num(op(*a, *b))),
-            _ => Err(format!(
+            _ => Err(AgentusError::runtime(format!(
                 "arithmetic requires numeric operands, got {} and {}",
                 lhs, rhs
-            )),
+            ))),
         }
     }
 
@@ -929,38 +2051,122 @@ impl VM {
         &self,
         b: usize,
         c: usize,
-        op: fn(f64, f64) -> bool,
-    ) -> Result<Value, String> {
+        op: fn(Ordering) -> bool,
+    ) -> Result<Value, AgentusError> {
         let lhs = self.get_register(b);
         let rhs = self.get_register(c);
         match (lhs, rhs) {
-            (Value::Num(a), Value::Num(b)) => Ok(Value::Bool(op(*a, *b))),
-            _ => Err(format!(
-                "comparison requires numeric operands, got {} and {}",
+            // IEEE semantics: any comparison involving NaN is false
+            (Value::Num(a), Value::Num(b)) => Ok(Value::Bool(a.partial_cmp(b).is_some_and(op))),
+            (Value::None, _) | (_, Value::None) => Err(AgentusError::runtime(format!(
+                "cannot compare none: {} and {}",
                 lhs, rhs
-            )),
+            ))),
+            _ => match lhs.partial_cmp(rhs) {
+                Some(ordering) => Ok(Value::Bool(op(ordering))),
+                None => Err(AgentusError::runtime(format!("cannot compare {} and {}", lhs, rhs))),
+            },
         }
     }
 
-    fn load_constant_str(&self, idx: u16) -> Result<String, String> {
+    fn load_constant_str(&self, idx: u16) -> Result<String, AgentusError> {
         let constant = self
             .module
             .get_constant(idx)
-            .ok_or_else(|| format!("constant {} not found", idx))?;
+            .ok_or_else(|| AgentusError::runtime(format!("constant {} not found", idx)))?;
         match constant {
             Constant::Str(s) => Ok(s.clone()),
-            _ => Err(format!("expected string constant at index {}", idx)),
+            _ => Err(AgentusError::runtime(format!("expected string constant at index {}", idx))),
         }
     }
 
-    fn current_agent_id(&self) -> Result<u64, String> {
+    /// Create an agent instance with its memory fields set to their defaults.
+    fn spawn_instance(&mut self, descriptor_idx: u32) -> Result<u64, AgentusError> {
+        let descriptor = self.module.get_agent(descriptor_idx)
+            .ok_or_else(|| AgentusError::runtime(format!("agent descriptor {} not found", descriptor_idx)))?
+            .clone();
+
+        // Initialize memory with defaults
+        let mut memory = HashMap::new();
+        for field in &descriptor.memory_fields {
+            let name = self.load_constant_str(field.name_idx)?;
+            let default_val = if let Some(default_idx) = field.default_idx {
+                self.load_constant(default_idx)?
+            } else {
+                Value::None
+            };
+            memory.insert(name, default_val);
+        }
+
+        let id = self.next_agent_id;
+        self.next_agent_id += 1;
+        self.agents.insert(id, AgentInstance {
+            descriptor_idx,
+            memory,
+            mailbox: VecDeque::new(),
+            coroutines: HashMap::new(),
+        });
+        if let Err(e) = self.run_lifecycle_hook(descriptor_idx, id, "on_spawn") {
+            // A failed `on_spawn` leaves no half-initialised agent behind
+            self.agents.remove(&id);
+            return Err(e);
+        }
+        Ok(id)
+    }
+
+    /// Find the function index of a method on an agent by name.
+    fn find_method(&self, descriptor: &AgentDescriptor, method_name: &str) -> Option<u32> {
+        descriptor.methods.iter()
+            .find(|(name_idx, _)| self.load_constant_str(*name_idx).ok().as_deref() == Some(method_name))
+            .map(|(_, idx)| *idx)
+    }
+
+    /// Fail unless the method at `func_idx` takes exactly `num_args` arguments.
+    fn check_method_arity(&self, func_idx: u32, method_name: &str, num_args: usize) -> Result<(), AgentusError> {
+        let num_params = self
+            .module
+            .get_function(func_idx)
+            .ok_or_else(|| AgentusError::runtime(format!("function {} not found", func_idx)))?
+            .num_params as usize;
+        if num_args != num_params {
+            return Err(AgentusError::runtime(format!(
+                "method '{}' expects {} arguments, got {}",
+                method_name, num_params, num_args
+            )));
+        }
+        Ok(())
+    }
+
+    /// Run a lifecycle method (`on_spawn` or `on_kill`) in the agent's
+    /// context to completion, if the agent defines it.
+    fn run_lifecycle_hook(&mut self, descriptor_idx: u32, agent_id: u64, hook: &str) -> Result<(), AgentusError> {
+        let descriptor = self.module.get_agent(descriptor_idx)
+            .ok_or_else(|| AgentusError::runtime(format!("agent descriptor {} not found", descriptor_idx)))?;
+        if let Some(func_idx) = self.find_method(descriptor, hook) {
+            self.call_nested(func_idx, Some(agent_id), Vec::new())?;
+        }
+        Ok(())
+    }
+
+    fn current_agent_id(&self) -> Result<u64, AgentusError> {
         self.call_stack
             .last()
             .and_then(|f| f.agent_id)
-            .ok_or_else(|| "not in an agent context".to_string())
+            .ok_or_else(|| AgentusError::runtime("not in an agent context"))
     }
 
-    fn throw_error(&mut self, error: Value) -> Result<(), String> {
+    fn throw_error(&mut self, error: Value) -> Result<(), AgentusError> {
+        // A handler at or below the callback base belongs to the frame running
+        // the callback: leave it for `step_instruction` to re-raise into
+        if self
+            .error_handlers
+            .last()
+            .is_some_and(|h| h.call_stack_depth <= self.callback_base)
+        {
+            let message = format!("unhandled error: {}", error_message(&error));
+            self.escaped_throw = Some((self.callback_base, error));
+            return Err(AgentusError::runtime(message));
+        }
         if let Some(handler) = self.error_handlers.pop() {
             // Unwind call stack to the handler's depth
             while self.call_stack.len() > handler.call_stack_depth {
@@ -973,30 +2179,36 @@ impl VM {
             Ok(())
         } else {
             // No handler — propagate as runtime error
-            Err(format!("unhandled error: {}", error))
+            Err(AgentusError::runtime(format!("unhandled error: {}", error_message(&error))))
         }
     }
 
     fn get_agent_context(&self) -> (Option<String>, Option<String>) {
         let agent_id = self.call_stack.last().and_then(|f| f.agent_id);
-        if let Some(id) = agent_id {
-            if let Some(agent) = self.agents.get(&id) {
-                let desc = self.module.get_agent(agent.descriptor_idx);
-                if let Some(desc) = desc {
-                    let model = desc.model_idx.and_then(|idx| {
-                        self.load_constant_str(idx).ok()
-                    });
-                    let sys = desc.system_prompt_idx.and_then(|idx| {
-                        self.load_constant_str(idx).ok()
-                    });
-                    return (model, sys);
-                }
-            }
+        if let Some(id) = agent_id
+            && let Some(agent) = self.agents.get(&id)
+            && let Some(desc) = self.module.get_agent(agent.descriptor_idx)
+        {
+            let model = desc.model_idx.and_then(|idx| {
+                self.load_constant_str(idx).ok()
+            });
+            let sys = desc.system_prompt_idx.and_then(|idx| {
+                self.load_constant_str(idx).ok()
+            });
+            return (model, sys);
         }
         (None, None)
     }
 }
 
+/// The text of a thrown value as shown in an "unhandled error" message.
+fn error_message(error: &Value) -> String {
+    match error {
+        Value::Error(msg) => msg.to_string(),
+        other => other.to_string(),
+    }
+}
+
 /// Convenience: no-op output handler for testing.
 pub struct SilentHandler;
 
@@ -1023,7 +2235,9 @@ mod tests {
             }],
             agents: Vec::new(),
             tools: Vec::new(),
+            pipelines: Vec::new(),
             entry_function: 0,
+            named_entry_points: HashMap::new(),
         }
     }
 
@@ -1145,4 +2359,73 @@ mod tests {
         assert_eq!(vm.outputs[0], Value::Bool(false));
         assert_eq!(vm.outputs[1], Value::Bool(true));
     }
+
+    #[test]
+    fn test_step_through_program() {
+        let module = make_module(
+            vec![Constant::Num(7.0)],
+            vec![
+                Instruction::abx(OpCode::LoadConst, 0, 0),
+                Instruction::op_a(OpCode::Emit, 0),
+                Instruction::op_only(OpCode::Halt),
+            ],
+        );
+
+        let mut vm = VM::new(module).with_output(Box::new(SilentHandler));
+        assert_eq!(vm.step().unwrap(), StepResult::Continue);
+        assert_eq!(vm.get_register_value(0), Some(&Value::Num(7.0)));
+        assert!(vm.outputs.is_empty());
+
+        assert_eq!(vm.step().unwrap(), StepResult::Continue);
+        assert_eq!(vm.outputs, vec![Value::Num(7.0)]);
+
+        assert_eq!(vm.step().unwrap(), StepResult::Halted);
+        assert_eq!(vm.step().unwrap(), StepResult::Halted);
+        assert_eq!(vm.get_register_value(0), Some(&Value::Num(7.0)));
+    }
+
+    #[test]
+    fn test_step_reports_error() {
+        let module = make_module(
+            vec![Constant::Str("a".to_string())],
+            vec![
+                Instruction::abx(OpCode::LoadConst, 0, 0),
+                Instruction::op_a(OpCode::LoadTrue, 1),
+                Instruction::abc(OpCode::Sub, 2, 0, 1),
+                Instruction::op_only(OpCode::Halt),
+            ],
+        );
+
+        let mut vm = VM::new(module).with_output(Box::new(SilentHandler));
+        assert_eq!(vm.step().unwrap(), StepResult::Continue);
+        assert_eq!(vm.step().unwrap(), StepResult::Continue);
+        assert!(matches!(vm.step().unwrap(), StepResult::Error(_)));
+        assert_eq!(vm.step().unwrap(), StepResult::Halted);
+    }
+
+    #[test]
+    fn test_runtime_errors_are_runtime_variant() {
+        let module = make_module(
+            vec![Constant::Str("boom".to_string())],
+            vec![
+                Instruction::abx(OpCode::LoadConst, 0, 0),
+                Instruction::op_a(OpCode::Throw, 0),
+                Instruction::op_only(OpCode::Halt),
+            ],
+        );
+        let mut vm = VM::new(module).with_output(Box::new(SilentHandler));
+        assert!(matches!(
+            vm.run(),
+            Err(AgentusError::RuntimeError { message }) if message == "unhandled error: boom"
+        ));
+        assert!(matches!(
+            vm.call_function("missing", &[]),
+            Err(AgentusError::RuntimeError { message }) if message == "undefined function 'missing'"
+        ));
+        assert!(matches!(vm.spawn_agent("Missing"), Err(AgentusError::RuntimeError { .. })));
+        assert!(matches!(
+            vm.set_agent_memory(7, "count", Value::Num(1.0)),
+            Err(AgentusError::RuntimeError { message }) if message == "agent 7 not found"
+        ));
+    }
 }
@@ -1,25 +1,69 @@
 use std::collections::HashMap;
+use agentus_common::errors::AgentusError;
+use agentus_common::span::Span;
 use agentus_parser::ast::*;
 
 /// Minimal semantic analysis: name resolution and scope checking.
 ///
 /// Ensures all variables are defined before use and tracks scopes.
 pub struct Resolver {
-    /// Stack of scopes. Each scope maps variable names to a "defined" flag.
+    /// Stack of scopes. Each scope maps names to whether they are locals
+    /// (registers of the enclosing function body) rather than declarations.
     scopes: Vec<HashMap<String, bool>>,
-    errors: Vec<String>,
+    /// Index of the first scope of the innermost lambda or function body,
+    /// with the plural noun used when it tries to capture an outer local.
+    function_scope: Option<(usize, &'static str)>,
+    /// Top-level tools by name, as (total params, required params).
+    tools: HashMap<String, (usize, usize)>,
+    /// Functions resolved so far by name, as their parameter count.
+    functions: HashMap<String, usize>,
+    /// Whether the statements being resolved belong to an agent method.
+    in_method: bool,
+    errors: Vec<AgentusError>,
 }
 
 impl Resolver {
     pub fn new() -> Self {
         Self {
-            scopes: vec![HashMap::new()], // global scope
+            // global scope, with the built-in names predefined
+            scopes: vec![HashMap::from([
+                ("env".to_string(), false),
+                ("args".to_string(), false),
+                ("cmp".to_string(), false),
+            ])],
+            function_scope: None,
+            tools: HashMap::new(),
+            functions: HashMap::new(),
+            in_method: false,
             errors: Vec::new(),
         }
     }
 
     /// Resolve the given program, returning any errors found.
-    pub fn resolve(mut self, program: &Program) -> Result<(), Vec<String>> {
+    ///
+    /// Each message ends with the span it refers to; use `resolve_errors`
+    /// to get the span as a separate field instead.
+    pub fn resolve(self, program: &Program) -> Result<(), Vec<String>> {
+        self.resolve_errors(program).map_err(|errors| {
+            errors
+                .into_iter()
+                .map(|e| match e.span() {
+                    Some(span) => format!("{} at {:?}", e.message(), span),
+                    None => e.message(),
+                })
+                .collect()
+        })
+    }
+
+    /// Resolve the given program, returning any errors as `SemanticError`s.
+    pub fn resolve_errors(mut self, program: &Program) -> Result<(), Vec<AgentusError>> {
+        // First pass: collect tool signatures so calls can be checked
+        // regardless of where the tool is declared.
+        for tool in program.iter_tool_defs() {
+            let required = tool.params.iter().filter(|p| p.default.is_none()).count();
+            self.tools
+                .insert(tool.name.clone(), (tool.params.len(), required));
+        }
         for stmt in &program.statements {
             self.resolve_stmt(stmt);
         }
@@ -30,6 +74,10 @@ impl Resolver {
         }
     }
 
+    fn error(&mut self, message: String, span: Span) {
+        self.errors.push(AgentusError::semantic(message, span));
+    }
+
     fn push_scope(&mut self) {
         self.scopes.push(HashMap::new());
     }
@@ -39,11 +87,59 @@ impl Resolver {
     }
 
     fn define(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), false);
+        }
+    }
+
+    /// Define a local variable (`let`, parameter, loop or catch variable).
+    fn define_local(&mut self, name: &str) {
         if let Some(scope) = self.scopes.last_mut() {
             scope.insert(name.to_string(), true);
         }
     }
 
+    /// Report a use of `name` that refers to a local of an enclosing
+    /// function body; each body gets its own registers, so it can't see it.
+    fn check_capture(&mut self, name: &str, span: Span) {
+        let Some((base, kind)) = self.function_scope else {
+            return;
+        };
+        let Some(depth) = self.scopes.iter().rposition(|scope| scope.contains_key(name)) else {
+            return;
+        };
+        if depth < base && self.scopes[depth][name] {
+            self.error(format!("{} cannot capture local '{}'", kind, name), span);
+        }
+    }
+
+    /// Resolve a function or lambda body in a fresh scope holding `params`.
+    fn resolve_function_body<'p>(
+        &mut self,
+        kind: &'static str,
+        params: impl IntoIterator<Item = &'p str>,
+        body: &[Stmt],
+        in_method: bool,
+    ) {
+        self.push_scope();
+        for p in params {
+            self.define_local(p);
+        }
+        let outer = self.function_scope.replace((self.scopes.len() - 1, kind));
+        let was_in_method = std::mem::replace(&mut self.in_method, in_method);
+        for s in body {
+            self.resolve_stmt(s);
+        }
+        self.in_method = was_in_method;
+        self.function_scope = outer;
+        self.pop_scope();
+    }
+
+    /// Whether the innermost binding of `name` is a local variable.
+    fn is_local(&self, name: &str) -> bool {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name)) == Some(&true)
+    }
+
     fn is_defined(&self, name: &str) -> bool {
         for scope in self.scopes.iter().rev() {
             if scope.contains_key(name) {
@@ -57,7 +153,7 @@ impl Resolver {
         match stmt {
             Stmt::Let(l) => {
                 self.resolve_expr(&l.value);
-                self.define(&l.name);
+                self.define_local(&l.name);
             }
             Stmt::Emit(e) => {
                 self.resolve_expr(&e.value);
@@ -72,11 +168,9 @@ impl Resolver {
             }
             Stmt::Assign(a) => {
                 if !self.is_defined(&a.name) {
-                    self.errors.push(format!(
-                        "undefined variable '{}' at {:?}",
-                        a.name, a.span
-                    ));
+                    self.error(format!("undefined variable '{}'", a.name), a.span);
                 }
+                self.check_capture(&a.name, a.span);
                 self.resolve_expr(&a.value);
             }
             Stmt::If(i) => {
@@ -105,7 +199,10 @@ impl Resolver {
             Stmt::For(f) => {
                 self.resolve_expr(&f.iterable);
                 self.push_scope();
-                self.define(&f.variable);
+                self.define_local(&f.variable);
+                if let Some(value) = &f.value_variable {
+                    self.define_local(value);
+                }
                 for s in &f.body {
                     self.resolve_stmt(s);
                 }
@@ -113,16 +210,19 @@ impl Resolver {
             }
             Stmt::FnDef(f) => {
                 self.define(&f.name);
-                self.push_scope();
-                for p in &f.params {
-                    self.define(&p.name);
-                }
-                for s in &f.body {
-                    self.resolve_stmt(s);
-                }
-                self.pop_scope();
+                self.functions.insert(f.name.clone(), f.params.len());
+                let params = f.params.iter().map(|p| p.name.as_str());
+                self.resolve_function_body("functions", params, &f.body, false);
             }
             Stmt::AgentDef(a) => {
+                if let Some(parent) = &a.extends
+                    && !self.is_defined(parent)
+                {
+                    self.error(
+                        format!("undefined parent agent '{}' for agent '{}'", parent, a.name),
+                        a.span,
+                    );
+                }
                 self.define(&a.name);
                 self.push_scope();
                 self.define("self");
@@ -132,15 +232,17 @@ impl Resolver {
                     }
                 }
                 for method in &a.methods {
-                    self.define(&method.name);
-                    self.push_scope();
-                    for p in &method.params {
-                        self.define(&p.name);
-                    }
-                    for s in &method.body {
-                        self.resolve_stmt(s);
+                    if matches!(method.name.as_str(), "on_spawn" | "on_kill")
+                        && !method.params.is_empty()
+                    {
+                        self.error(
+                            format!("lifecycle hook '{}' cannot take parameters", method.name),
+                            method.span,
+                        );
                     }
-                    self.pop_scope();
+                    self.define(&method.name);
+                    let params = method.params.iter().map(|p| p.name.as_str());
+                    self.resolve_function_body("methods", params, &method.body, true);
                 }
                 self.pop_scope();
             }
@@ -148,9 +250,64 @@ impl Resolver {
                 self.resolve_expr(&fa.object);
                 self.resolve_expr(&fa.value);
             }
+            Stmt::GlobalDecl(g) => {
+                if self.scopes.len() > 1 {
+                    self.error(
+                        "'global' declarations are only allowed at the top level".to_string(),
+                        g.span,
+                    );
+                }
+                for field in &g.fields {
+                    if let Some(default) = &field.default {
+                        self.resolve_expr(default);
+                    }
+                    self.define(&field.name);
+                }
+            }
+            Stmt::PipelineDef(p) => {
+                if p.stages.is_empty() {
+                    self.error(format!("pipeline '{}' has no stages", p.name), p.span);
+                }
+                for stage in &p.stages {
+                    if !self.is_defined(&stage.name) {
+                        self.error(
+                            format!("undefined stage '{}' in pipeline '{}'", stage.name, p.name),
+                            stage.span,
+                        );
+                        continue;
+                    }
+                    match self.functions.get(&stage.name) {
+                        Some(&params) if !self.is_local(&stage.name) => {
+                            if params != 1 {
+                                self.error(
+                                    format!(
+                                        "stage '{}' in pipeline '{}' must take exactly 1 parameter, found {}",
+                                        stage.name, p.name, params
+                                    ),
+                                    stage.span,
+                                );
+                            }
+                        }
+                        _ => self.error(
+                            format!("stage '{}' in pipeline '{}' is not a function", stage.name, p.name),
+                            stage.span,
+                        ),
+                    }
+                }
+                self.define(&p.name);
+            }
             Stmt::ToolDef(t) => {
                 self.define(&t.name);
+                let mut seen: Vec<&str> = Vec::new();
                 for param in &t.params {
+                    if seen.contains(&param.name.as_str()) {
+                        self.error(
+                            format!("duplicate parameter '{}' in tool '{}'", param.name, t.name),
+                            param.span,
+                        );
+                    } else {
+                        seen.push(&param.name);
+                    }
                     if let Some(default) = &param.default {
                         self.resolve_expr(default);
                     }
@@ -160,6 +317,7 @@ impl Resolver {
                 self.resolve_expr(&s.target);
                 self.resolve_expr(&s.message);
             }
+            Stmt::Kill(k) => self.resolve_expr(&k.target),
             Stmt::IndexAssign(ia) => {
                 self.resolve_expr(&ia.object);
                 self.resolve_expr(&ia.index);
@@ -172,15 +330,28 @@ impl Resolver {
                 }
                 self.pop_scope();
                 self.push_scope();
-                self.define(&tc.catch_var);
+                self.define_local(&tc.catch_var);
                 for s in &tc.catch_body {
                     self.resolve_stmt(s);
                 }
                 self.pop_scope();
+                if let Some(finally_body) = &tc.finally_body {
+                    self.push_scope();
+                    for s in finally_body {
+                        self.resolve_stmt(s);
+                    }
+                    self.pop_scope();
+                }
             }
             Stmt::Throw(t) => {
                 self.resolve_expr(&t.value);
             }
+            Stmt::Yield(y) => {
+                if !self.in_method {
+                    self.error("'yield' is only allowed inside agent methods".to_string(), y.span);
+                }
+                self.resolve_expr(&y.value);
+            }
             Stmt::Assert(a) => {
                 self.resolve_expr(&a.condition);
                 if let Some(msg) = &a.message {
@@ -198,16 +369,19 @@ impl Resolver {
             | Expr::NoneLit(_) => {}
             Expr::TemplateLit(segments, _) => {
                 for seg in segments {
-                    if let agentus_parser::ast::TemplateSegment::Expr(e) = seg {
-                        self.resolve_expr(e);
+                    match seg {
+                        TemplateSegment::Expr(e) | TemplateSegment::Formatted(e, _) => {
+                            self.resolve_expr(e);
+                        }
+                        TemplateSegment::Literal(_) => {}
                     }
                 }
             }
             Expr::Ident(name, span) => {
                 if !self.is_defined(name) {
-                    self.errors
-                        .push(format!("undefined variable '{}' at {:?}", name, span));
+                    self.error(format!("undefined variable '{}'", name), *span);
                 }
+                self.check_capture(name, *span);
             }
             Expr::BinOp(left, _, right, _) => {
                 self.resolve_expr(left);
@@ -216,10 +390,17 @@ impl Resolver {
             Expr::UnaryOp(_, expr, _) => {
                 self.resolve_expr(expr);
             }
-            Expr::FnCall(_, args, _) => {
+            Expr::FnCall(name, args, span) => {
                 for arg in args {
                     self.resolve_expr(arg);
                 }
+                self.check_capture(name, *span);
+                if self.is_local(name) {
+                    // A local shadows any tool, function or agent of that name
+                    self.error(format!("cannot call local variable '{}'", name), *span);
+                } else {
+                    self.check_tool_args(name, args, span);
+                }
             }
             Expr::MethodCall(obj, _, args, _) => {
                 self.resolve_expr(obj);
@@ -248,9 +429,28 @@ impl Resolver {
             Expr::ExecBlock(prompt, _) => {
                 self.resolve_expr(prompt);
             }
+            Expr::Batch(calls, _) => {
+                for call in calls {
+                    match call {
+                        Expr::FnCall(name, _, _) if self.tools.contains_key(name) => {}
+                        _ => self.error(
+                            "batch entries must be tool calls".to_string(),
+                            call.span(),
+                        ),
+                    }
+                    self.resolve_expr(call);
+                }
+            }
             Expr::Recv(target, _) => {
                 self.resolve_expr(target);
             }
+            Expr::Spread(inner, _) => {
+                self.resolve_expr(inner);
+            }
+            Expr::Lambda(params, body, _) => {
+                let params = params.iter().map(String::as_str);
+                self.resolve_function_body("lambdas", params, body, false);
+            }
             Expr::Retry(attempts, body, _) => {
                 self.resolve_expr(attempts);
                 self.push_scope();
@@ -261,6 +461,38 @@ impl Resolver {
             }
         }
     }
+
+    /// Check the argument count of a call to a known tool. Calls with a
+    /// spread argument are checked at runtime instead.
+    fn check_tool_args(&mut self, name: &str, args: &[Expr], span: &Span) {
+        let Some(&(total, required)) = self.tools.get(name) else {
+            return;
+        };
+        if args.iter().any(|a| matches!(a, Expr::Spread(_, _))) {
+            return;
+        }
+        if args.len() > total {
+            self.error(
+                format!(
+                    "too many arguments to tool '{}': expected at most {}, got {}",
+                    name,
+                    total,
+                    args.len()
+                ),
+                *span,
+            );
+        } else if args.len() < required {
+            self.error(
+                format!(
+                    "too few arguments to tool '{}': expected at least {}, got {}",
+                    name,
+                    required,
+                    args.len()
+                ),
+                *span,
+            );
+        }
+    }
 }
 
 impl Default for Resolver {
@@ -274,6 +506,45 @@ pub fn resolve(program: &Program) -> Result<(), Vec<String>> {
     Resolver::new().resolve(program)
 }
 
+/// Convenience: resolve a program, keeping error spans structured.
+pub fn resolve_errors(program: &Program) -> Result<(), Vec<AgentusError>> {
+    Resolver::new().resolve_errors(program)
+}
+
+/// What kind of declaration a name refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefinitionKind {
+    Fn,
+    Agent,
+    Tool,
+    /// A `let` binding.
+    Local,
+    /// A function parameter.
+    Param,
+}
+
+/// Collect the top-level definitions of a program with their spans. When a
+/// name is bound more than once, the first definition wins.
+pub fn collect_definitions(program: &Program) -> HashMap<String, (DefinitionKind, Span)> {
+    let mut defs = HashMap::new();
+    for stmt in &program.statements {
+        let (name, kind, span) = match stmt {
+            Stmt::FnDef(f) => (&f.name, DefinitionKind::Fn, f.span),
+            Stmt::AgentDef(a) => (&a.name, DefinitionKind::Agent, a.span),
+            Stmt::ToolDef(t) => (&t.name, DefinitionKind::Tool, t.span),
+            Stmt::Let(l) => (&l.name, DefinitionKind::Local, l.name_span),
+            _ => continue,
+        };
+        defs.entry(name.clone()).or_insert((kind, span));
+    }
+    defs
+}
+
+/// The span of the top-level definition of `name`, if there is one.
+pub fn find_definition(program: &Program, name: &str) -> Option<Span> {
+    collect_definitions(program).get(name).map(|(_, span)| *span)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -315,4 +586,152 @@ mod tests {
         let program = parse(src).unwrap();
         assert!(resolve(&program).is_ok());
     }
+
+    #[test]
+    fn test_tool_duplicate_parameter() {
+        let src = "tool foo {\n    param x: str\n    param x: num\n}";
+        let program = parse(src).unwrap();
+        let errors = resolve(&program).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("duplicate parameter 'x' in tool 'foo'"));
+    }
+
+    #[test]
+    fn test_tool_default_resolved() {
+        let src = "tool foo {\n    param x: str = missing\n}";
+        let program = parse(src).unwrap();
+        let errors = resolve(&program).unwrap_err();
+        assert!(errors[0].contains("undefined variable 'missing'"));
+    }
+
+    #[test]
+    fn test_yield_outside_method() {
+        let program = parse("fn gen() -> num {\n    yield 1\n}").unwrap();
+        let errors = resolve(&program).unwrap_err();
+        assert!(errors[0].contains("'yield' is only allowed inside agent methods"));
+    }
+
+    #[test]
+    fn test_tool_too_many_args() {
+        let src = "tool ping {\n    param host: str\n}\nlet r = ping(\"a\", \"b\")";
+        let program = parse(src).unwrap();
+        let errors = resolve(&program).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("too many arguments to tool 'ping'"));
+    }
+
+    #[test]
+    fn test_tool_too_few_args() {
+        let src = "let r = ping()\ntool ping {\n    param host: str\n    param count: num = 3\n}";
+        let program = parse(src).unwrap();
+        let errors = resolve(&program).unwrap_err();
+        assert!(errors[0].contains("too few arguments to tool 'ping'"));
+
+        let ok = "tool ping {\n    param host: str\n    param count: num = 3\n}\nlet r = ping(\"a\")";
+        assert!(resolve(&parse(ok).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_tool_args_respect_shadowing() {
+        let src = "tool ping {\n    param host: str\n}\nfn check(ping: num) -> num {\n    return ping(1, 2)\n}";
+        let errors = resolve(&parse(src).unwrap()).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("cannot call local variable 'ping'"));
+
+        let src = "tool ping {\n    param host: str\n}\nfn check(ping: num) -> num {\n    return ping\n}\nlet r = ping(\"a\")";
+        assert!(resolve(&parse(src).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_pipeline_undefined_stage() {
+        let src = "fn clean(s: str) -> str {\n    return s\n}\npipeline P {\n    stage clean\n    stage missing\n}";
+        let errors = resolve(&parse(src).unwrap()).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("undefined stage 'missing' in pipeline 'P'"));
+    }
+
+    #[test]
+    fn test_pipeline_stage_must_be_unary_fn() {
+        let src = "fn add(a: num, b: num) -> num {\n    return a + b\n}\nlet clean = 1\ntool ping {\n    param host: str\n}\npipeline P {\n    stage add\n    stage clean\n    stage ping\n}";
+        let errors = resolve_errors(&parse(src).unwrap()).unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert_eq!(
+            errors[0].message(),
+            "stage 'add' in pipeline 'P' must take exactly 1 parameter, found 2"
+        );
+        assert_eq!(errors[1].message(), "stage 'clean' in pipeline 'P' is not a function");
+        assert_eq!(errors[2].message(), "stage 'ping' in pipeline 'P' is not a function");
+        let start = src.find("stage clean").unwrap() as u32;
+        assert_eq!(errors[1].span().map(|s| s.start), Some(start));
+    }
+
+    #[test]
+    fn test_lambda_capture_rejected() {
+        let src = "let x = 1\nlet f = fn(a) a + x\n";
+        let errors = resolve_errors(&parse(src).unwrap()).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message(), "lambdas cannot capture local 'x'");
+        let start = src.rfind('x').unwrap() as u32;
+        assert_eq!(errors[0].span(), Some(Span::new(start, start + 1)));
+
+        let src = "fn apply(n: num) -> num {\n    let g = fn() n\n    return g()\n}";
+        let errors = resolve(&parse(src).unwrap()).unwrap_err();
+        assert!(errors[0].contains("lambdas cannot capture local 'n'"));
+
+        let src = "let x = 1\nfn get() -> num {\n    return x\n}";
+        let errors = resolve(&parse(src).unwrap()).unwrap_err();
+        assert!(errors[0].contains("functions cannot capture local 'x'"));
+    }
+
+    #[test]
+    fn test_lambda_without_capture() {
+        let src = "fn double(n: num) -> num {\n    return n * 2\n}\nlet f = fn(a) {\n    let b = a + 1\n    return double(b)\n}\nlet g = fn(x) x\n";
+        assert!(resolve(&parse(src).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_collect_definitions() {
+        let src = "fn add(a: num, b: num) -> num {\n    return a + b\n}\nagent Bot {\n    fn hi() -> str {\n        return \"hi\"\n    }\n}\ntool ping {\n    param host: str\n}\n";
+        let program = parse(src).unwrap();
+        let defs = collect_definitions(&program);
+        assert_eq!(defs.len(), 3);
+        let span_of = |needle: &str| {
+            let start = src.find(needle).unwrap() as u32;
+            (start, start + needle.len() as u32)
+        };
+        let (kind, span) = defs["add"];
+        assert_eq!(kind, DefinitionKind::Fn);
+        assert_eq!(span.start, span_of("fn add").0);
+        let (kind, span) = defs["Bot"];
+        assert_eq!(kind, DefinitionKind::Agent);
+        assert_eq!(span.start, span_of("agent Bot").0);
+        let (kind, span) = defs["ping"];
+        assert_eq!(kind, DefinitionKind::Tool);
+        assert_eq!(span.start, span_of("tool ping").0);
+        assert!(span.end > span.start);
+
+        let program = parse("let total = 1\nemit total\n").unwrap();
+        assert_eq!(
+            find_definition(&program, "total"),
+            Some(Span::new(4, 9))
+        );
+        assert_eq!(find_definition(&program, "missing"), None);
+    }
+
+    #[test]
+    fn test_resolve_errors_are_semantic() {
+        let program = parse("let a = 1\nemit b\n").unwrap();
+        let errors = resolve_errors(&program).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        match &errors[0] {
+            AgentusError::SemanticError { message, span } => {
+                assert_eq!(message, "undefined variable 'b'");
+                assert_eq!(*span, Span::new(15, 16));
+            }
+            other => panic!("expected a semantic error, got {:?}", other),
+        }
+        // The string form keeps the span suffix
+        let errors = resolve(&program).unwrap_err();
+        assert!(errors[0].starts_with("undefined variable 'b' at Span { start: 15, end: 16"));
+    }
 }